@@ -0,0 +1,29 @@
+extern crate criterion;
+extern crate ctlv;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use ctlv::{decode_all, par_decode_all, Ctlv};
+
+// About 40 MB of ctlvs with values of up to 4 KiB.
+fn synthetic() -> Vec<u8> {
+    let mut out = Vec::new();
+    for i in 0..20_000u64 {
+        Ctlv::new(128 + i % 1000, vec![i as u8; ((i * 7919) % 4096) as usize])
+            .encode_write(&mut out)
+            .unwrap();
+    }
+    out
+}
+
+fn decode(c: &mut Criterion) {
+    let input = synthetic();
+    let mut group = c.benchmark_group("decode_all");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.bench_function("serial", |b| b.iter(|| decode_all(&input).unwrap()));
+    group.bench_function("parallel", |b| b.iter(|| par_decode_all(&input).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
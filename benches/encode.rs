@@ -0,0 +1,68 @@
+extern crate criterion;
+extern crate ctlv;
+extern crate varu64;
+
+use std::io::{self, Write};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use ctlv::CtlvRef;
+
+// An unbuffered writer, where every call has a (simulated) fixed cost.
+struct Unbuffered(u64);
+
+impl Write for Unbuffered {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for _ in 0..16 {
+            self.0 = black_box(self.0.wrapping_add(buf.len() as u64));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Writes the type, the length and the value separately.
+fn encode_write_separately<W: Write>(frame: &CtlvRef, mut w: W) -> io::Result<usize> {
+    let mut total = varu64::encode_write(frame.type_, &mut w)?;
+    if frame.type_ >= 128 {
+        total += varu64::encode_write(frame.value.len() as u64, &mut w)?;
+    }
+    w.write_all(frame.value)?;
+    Ok(total + frame.value.len())
+}
+
+fn encode(c: &mut Criterion) {
+    let values: Vec<Vec<u8>> = (0..1000).map(|i| vec![i as u8; i % 100]).collect();
+    let frames: Vec<_> = values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| CtlvRef { type_: 128 + i as u64, value })
+        .collect();
+
+    let mut group = c.benchmark_group("encode_write");
+    group.bench_function("coalesced", |b| {
+        b.iter(|| {
+                   let mut w = Unbuffered(0);
+                   for frame in &frames {
+                       frame.encode_write(&mut w).unwrap();
+                   }
+                   w.0
+               })
+    });
+    group.bench_function("separate", |b| {
+        b.iter(|| {
+                   let mut w = Unbuffered(0);
+                   for frame in &frames {
+                       encode_write_separately(frame, &mut w).unwrap();
+                   }
+                   w.0
+               })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, encode);
+criterion_main!(benches);
@@ -0,0 +1,26 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate ctlv;
+
+use ctlv::CtlvIter;
+
+fuzz_target!(|data: &[u8]| {
+    // test that re-encoding the valid prefix of a sequence reproduces it
+    let mut iter = CtlvIter::new(data);
+    let mut enc = Vec::new();
+    let mut consumed = 0;
+    for result in &mut iter {
+        match result {
+            Err((_, offset)) => {
+                assert_eq!(offset, consumed);
+                break;
+            }
+            Ok(ctlv) => {
+                assert_eq!(ctlv.encode_write(&mut enc).unwrap(), ctlv.encoding_length());
+                consumed = enc.len();
+            }
+        }
+    }
+    assert_eq!(&enc[..], &data[..consumed]);
+});
@@ -0,0 +1,957 @@
+use std::future::poll_fn;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::{FusedStream, Stream};
+use futures_io::{AsyncBufRead, AsyncRead, AsyncWrite};
+use futures_sink::Sink;
+
+use super::read::HeaderParser;
+use super::write_buffer::WriteBuffer;
+use super::{Ctlv, CtlvGeneric, CtlvRef, DecodeConfig, DecodeError, Header, ReadDecodeError,
+            MAX_HEADER_LENGTH};
+
+// How much of a value is read at once, so that a malicious length does not cause a large
+// allocation unless the data is actually there.
+const CHUNK_LEN: usize = 8 * 1024;
+
+async fn read_some<R: AsyncRead + Unpin>(r: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match poll_fn(|cx| Pin::new(&mut *r).poll_read(cx, buf)).await {
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            result => return result,
+        }
+    }
+}
+
+async fn write_all<W: AsyncWrite + Unpin>(w: &mut W, mut buf: &[u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        match poll_fn(|cx| Pin::new(&mut *w).poll_write(cx, buf)).await {
+            Ok(0) => {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write ctlv"));
+            }
+            Ok(written) => buf = &buf[written..],
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Decode a `Ctlv` from an asynchronous reader, reading exactly the bytes of its encoding.
+///
+/// This behaves like [`Ctlv::decode_read`](struct.CtlvGeneric.html#method.decode_read), in
+/// particular it errors with `ReadDecodeError::Eof` if the reader is at its end already.
+///
+/// Requires the `futures-io` feature.
+pub async fn decode_async<R: AsyncRead + Unpin>(r: &mut R) -> Result<Ctlv, ReadDecodeError> {
+    decode_async_with(r, &DecodeConfig::default()).await
+}
+
+/// Decode a `Ctlv` from an asynchronous reader like [`decode_async`](fn.decode_async.html),
+/// but with the checks configured by `config`.
+///
+/// Requires the `futures-io` feature.
+pub async fn decode_async_with<R: AsyncRead + Unpin>(r: &mut R,
+                                                     config: &DecodeConfig)
+                                                     -> Result<Ctlv, ReadDecodeError> {
+    let (header, header_len) = read_header_async_impl(r, config).await?;
+
+    let mut value = Vec::new();
+    while (value.len() as u64) < header.len {
+        let start = value.len();
+        let chunk = (header.len - start as u64).min(CHUNK_LEN as u64) as usize;
+        value.resize(start + chunk, 0);
+        let read = read_some(r, &mut value[start..]).await?;
+        value.truncate(start + read);
+        if read == 0 {
+            return Err(ReadDecodeError::UnexpectedEof { consumed: header_len + start });
+        }
+    }
+    Ok(Ctlv::new(header.type_, value))
+}
+
+/// Decode the header of a ctlv from an asynchronous reader, reading exactly the bytes of the
+/// type and the length, so that the reader is left at the start of the value.
+///
+/// This is the asynchronous counterpart to [`read_header`](fn.read_header.html). Together with
+/// [`ValueBody`](struct.ValueBody.html), it allows streaming values without buffering them.
+///
+/// Requires the `futures-io` feature.
+pub async fn read_header_async<R: AsyncRead + Unpin>(r: &mut R) -> Result<Header, ReadDecodeError> {
+    read_header_async_with(r, &DecodeConfig::default()).await
+}
+
+/// Decode the header of a ctlv from an asynchronous reader like
+/// [`read_header_async`](fn.read_header_async.html), but with the checks configured by
+/// `config`.
+///
+/// Requires the `futures-io` feature.
+pub async fn read_header_async_with<R: AsyncRead + Unpin>(r: &mut R,
+                                                          config: &DecodeConfig)
+                                                          -> Result<Header, ReadDecodeError> {
+    read_header_async_impl(r, config).await.map(|(header, _)| header)
+}
+
+// Reads a header byte by byte, returning it together with its encoded length.
+async fn read_header_async_impl<R: AsyncRead + Unpin>(r: &mut R,
+                                                      config: &DecodeConfig)
+                                                      -> Result<(Header, usize), ReadDecodeError> {
+    let mut parser = HeaderParser::new(config);
+    loop {
+        let mut byte = [0];
+        if read_some(r, &mut byte).await? == 0 {
+            if parser.consumed() == 0 {
+                return Err(ReadDecodeError::Eof);
+            }
+            return Err(ReadDecodeError::UnexpectedEof { consumed: parser.consumed() });
+        }
+        if let Some(header) = parser.push(byte[0]).map_err(ReadDecodeError::Decode)? {
+            return Ok((header, parser.consumed()));
+        }
+    }
+}
+
+/// Decode the header of a ctlv from an asynchronous reader, returning it together with a
+/// reader of exactly its value.
+///
+/// This is the asynchronous counterpart to
+/// [`read_frame_streaming`](fn.read_frame_streaming.html).
+///
+/// Requires the `futures-io` feature.
+pub async fn read_frame_streaming_async<R: AsyncRead + Unpin>(
+    r: &mut R)
+    -> Result<(Header, ValueBody<'_, R>), ReadDecodeError> {
+    let header = read_header_async(r).await?;
+    Ok((header, ValueBody::new(r, &header)))
+}
+
+/// An asynchronous reader of the value of a ctlv whose header has already been read from the
+/// inner reader, e.g. with [`read_header_async`](fn.read_header_async.html).
+///
+/// This reads at most the length of the value from the inner reader, so it can be passed to
+/// `futures::io::copy` to stream a value of any size without buffering it. The value must be
+/// read completely (or skipped with [`skip`](#method.skip)) before decoding the next ctlv from
+/// the inner reader. If the inner reader ends within the value, the `ValueBody` simply ends
+/// early.
+///
+/// Requires the `futures-io` feature.
+#[derive(Debug)]
+pub struct ValueBody<'a, R> {
+    inner: &'a mut R,
+    remaining: u64,
+}
+
+impl<'a, R: AsyncRead + Unpin> ValueBody<'a, R> {
+    /// Creates a reader of the value of a ctlv with the given header from `inner`, which must
+    /// be positioned at the start of the value.
+    pub fn new(inner: &'a mut R, header: &Header) -> ValueBody<'a, R> {
+        ValueBody {
+            inner,
+            remaining: header.len,
+        }
+    }
+
+    /// Returns how many bytes of the value have not been read yet.
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    /// Discards the remainder of the value, returning how many bytes have been discarded.
+    ///
+    /// Errors with `io::ErrorKind::UnexpectedEof` if the inner reader ends before the value
+    /// does.
+    pub async fn skip(&mut self) -> io::Result<u64> {
+        let mut skipped = 0;
+        let mut buf = vec![0; self.remaining.min(CHUNK_LEN as u64) as usize];
+        while self.remaining > 0 {
+            let len = self.remaining.min(buf.len() as u64) as usize;
+            let read = read_some(self, &mut buf[..len]).await?;
+            if read == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                          "reader ended within the value of a ctlv"));
+            }
+            skipped += read as u64;
+        }
+        Ok(skipped)
+    }
+}
+
+impl<'a, R: AsyncRead + Unpin> AsyncRead for ValueBody<'a, R> {
+    fn poll_read(self: Pin<&mut Self>,
+                 cx: &mut Context<'_>,
+                 buf: &mut [u8])
+                 -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.remaining == 0 {
+            return Poll::Ready(Ok(0));
+        }
+        let len = this.remaining.min(buf.len() as u64) as usize;
+        let result = Pin::new(&mut *this.inner).poll_read(cx, &mut buf[..len]);
+        if let Poll::Ready(Ok(read)) = result {
+            this.remaining -= read as u64;
+        }
+        result
+    }
+}
+
+impl<'a, R: AsyncBufRead + Unpin> AsyncBufRead for ValueBody<'a, R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if this.remaining == 0 {
+            return Poll::Ready(Ok(&[]));
+        }
+        let remaining = this.remaining;
+        match Pin::new(&mut *this.inner).poll_fill_buf(cx) {
+            Poll::Ready(Ok(buf)) => {
+                let len = remaining.min(buf.len() as u64) as usize;
+                Poll::Ready(Ok(&buf[..len]))
+            }
+            other => other,
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        let amt = this.remaining.min(amt as u64);
+        this.remaining -= amt;
+        Pin::new(&mut *this.inner).consume(amt as usize);
+    }
+}
+
+/// Encodes a ctlv into an asynchronous writer, returning how many bytes have been written.
+///
+/// Requires the `futures-io` feature.
+pub async fn encode_async<W: AsyncWrite + Unpin>(frame: CtlvRef<'_>,
+                                                 w: &mut W)
+                                                 -> io::Result<usize> {
+    let mut header = [0; MAX_HEADER_LENGTH];
+    let header_len = frame.encode_header(&mut header);
+    write_all(w, &header[..header_len]).await?;
+    write_all(w, frame.value).await?;
+    Ok(header_len + frame.value.len())
+}
+
+/// Returns a stream of the ctlvs read from `r`, see [`FrameStream`](struct.FrameStream.html).
+///
+/// Requires the `futures-io` feature.
+pub fn frame_stream<R: AsyncRead + Unpin>(r: R) -> FrameStream<R> {
+    FrameStream::with_config(r, DecodeConfig::default())
+}
+
+/// A `Stream` of the ctlvs read from an asynchronous reader, through an internal buffer.
+///
+/// Like the synchronous [`CtlvReader`](struct.CtlvReader.html), this reads in chunks of
+/// several kilobytes, and the buffer only ever holds the ctlv that is being decoded plus the
+/// remainder of the last chunk. Nothing is read until the next ctlv is polled for.
+///
+/// The stream ends when the reader ends after a ctlv. If it ends within a ctlv, or if the
+/// input is invalid, the stream yields the error and then ends.
+///
+/// Polling is cancellation-safe in the same way as for
+/// [`AsyncCtlvReader`](struct.AsyncCtlvReader.html#cancellation-safety).
+///
+/// Requires the `futures-io` feature.
+#[derive(Debug)]
+pub struct FrameStream<R> {
+    inner: R,
+    buf: Vec<u8>,
+    // The bytes in `buf` that have been read but not decoded yet.
+    start: usize,
+    end: usize,
+    config: DecodeConfig,
+    done: bool,
+}
+
+impl<R: AsyncRead + Unpin> FrameStream<R> {
+    /// Creates a `FrameStream` that decodes with the given configuration.
+    pub fn with_config(inner: R, config: DecodeConfig) -> FrameStream<R> {
+        FrameStream {
+            inner,
+            buf: Vec::new(),
+            start: 0,
+            end: 0,
+            config,
+            done: false,
+        }
+    }
+
+    /// Returns the underlying reader, together with the bytes that have been read from it but
+    /// not decoded yet.
+    pub fn into_inner(self) -> (R, Vec<u8>) {
+        let mut buf = self.buf;
+        buf.truncate(self.end);
+        buf.drain(..self.start);
+        (self.inner, buf)
+    }
+
+    // Moves the undecoded bytes to the front of the buffer, then reads more bytes, growing the
+    // buffer if it is full.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        if self.start > 0 {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        if self.end == self.buf.len() {
+            let len = (2 * self.buf.len()).max(CHUNK_LEN);
+            self.buf.resize(len, 0);
+        }
+
+        loop {
+            match Pin::new(&mut self.inner).poll_read(cx, &mut self.buf[self.end..]) {
+                Poll::Ready(Ok(read)) => {
+                    self.end += read;
+                    return Poll::Ready(Ok(read));
+                }
+                Poll::Ready(Err(ref e)) if e.kind() == io::ErrorKind::Interrupted => {}
+                other => return other,
+            }
+        }
+    }
+
+    fn poll_frame(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Ctlv, ReadDecodeError>>> {
+        loop {
+            match CtlvRef::decode_with(&self.buf[self.start..self.end], &self.config) {
+                Ok((frame, _)) => {
+                    let ctlv = frame.to_owned();
+                    self.start += frame.encoding_length();
+                    return Poll::Ready(Some(Ok(ctlv)));
+                }
+                Err((DecodeError::UnexpectedEndOfInput, _)) => {
+                    match self.poll_fill(cx) {
+                        Poll::Ready(Ok(0)) => {
+                            let pending = self.end - self.start;
+                            return Poll::Ready(if pending == 0 {
+                                                   None
+                                               } else {
+                                                   Some(Err(ReadDecodeError::UnexpectedEof {
+                                                                consumed: pending,
+                                                            }))
+                                               });
+                        }
+                        Poll::Ready(Ok(_)) => {}
+                        Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+                        Poll::Pending => return Poll::Pending,
+                    }
+                }
+                Err((e, _)) => return Poll::Ready(Some(Err(ReadDecodeError::Decode(e)))),
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for FrameStream<R> {
+    type Item = Result<Ctlv, ReadDecodeError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        let result = this.poll_frame(cx);
+        if let Poll::Ready(item) = &result {
+            if !matches!(item, Some(Ok(_))) {
+                this.done = true;
+            }
+        }
+        result
+    }
+}
+
+impl<R: AsyncRead + Unpin> FusedStream for FrameStream<R> {
+    fn is_terminated(&self) -> bool {
+        self.done
+    }
+}
+
+/// Reads ctlvs from an asynchronous reader, one at a time.
+///
+/// This is the `async fn` counterpart to [`FrameStream`](struct.FrameStream.html), for code
+/// that does not want to go through the `Stream` trait.
+///
+/// # Cancellation safety
+///
+/// [`next`](#method.next) is cancellation-safe: all bytes that have been read but not decoded
+/// yet live in the buffer of the `AsyncCtlvReader` rather than in the future, so dropping a
+/// `next` future before it completes (for example because another branch of a `select!`
+/// completed first) loses no data. Calling `next` again later resumes exactly where the
+/// dropped future left off, even if it was dropped within a ctlv.
+///
+/// Requires the `futures-io` feature.
+#[derive(Debug)]
+pub struct AsyncCtlvReader<R> {
+    stream: FrameStream<R>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncCtlvReader<R> {
+    /// Creates an `AsyncCtlvReader` that decodes with the default configuration.
+    pub fn new(inner: R) -> AsyncCtlvReader<R> {
+        AsyncCtlvReader::with_config(inner, DecodeConfig::default())
+    }
+
+    /// Creates an `AsyncCtlvReader` that decodes with the given configuration.
+    pub fn with_config(inner: R, config: DecodeConfig) -> AsyncCtlvReader<R> {
+        AsyncCtlvReader { stream: FrameStream::with_config(inner, config) }
+    }
+
+    /// Reads the next ctlv, or returns `None` if the reader ended after the previous one.
+    ///
+    /// Once this has returned an error or `None`, it always returns `None`. This method is
+    /// cancellation-safe, see the [type-level docs](#cancellation-safety).
+    pub async fn next(&mut self) -> Option<Result<Ctlv, ReadDecodeError>> {
+        poll_fn(|cx| Pin::new(&mut self.stream).poll_next(cx)).await
+    }
+
+    /// Returns the underlying reader, together with the bytes that have been read from it but
+    /// not decoded yet.
+    pub fn into_inner(self) -> (R, Vec<u8>) {
+        self.stream.into_inner()
+    }
+
+    /// Converts this reader into a `Stream` of ctlvs, keeping any buffered bytes.
+    pub fn into_stream(self) -> FrameStream<R> {
+        self.stream
+    }
+}
+
+/// Returns a sink that encodes ctlvs into `w`, see [`FrameSink`](struct.FrameSink.html).
+///
+/// Requires the `futures-io` feature.
+pub fn frame_sink<W: AsyncWrite + Unpin>(w: W) -> FrameSink<W> {
+    FrameSink::with_threshold(w, CHUNK_LEN)
+}
+
+/// A `Sink` that encodes ctlvs into an asynchronous writer, accepting both `CtlvRef`s and
+/// owned ctlvs.
+///
+/// Sending a ctlv encodes it into an internal buffer, so that small ctlvs are batched into
+/// few writes. The buffer is written out when the sink is flushed or closed, and before
+/// accepting another ctlv once it holds at least a threshold of bytes (8 KiB by default).
+///
+/// Requires the `futures-io` feature.
+#[derive(Debug)]
+pub struct FrameSink<W> {
+    inner: W,
+    buf: WriteBuffer,
+    threshold: usize,
+}
+
+impl<W: AsyncWrite + Unpin> FrameSink<W> {
+    /// Creates a `FrameSink` that writes out its buffer before accepting another ctlv once the
+    /// buffer holds at least `threshold` bytes.
+    pub fn with_threshold(inner: W, threshold: usize) -> FrameSink<W> {
+        FrameSink {
+            inner,
+            buf: WriteBuffer::new(),
+            threshold,
+        }
+    }
+
+    /// Returns how many encoded bytes have not been written out yet.
+    pub fn buffered(&self) -> usize {
+        self.buf.buffered()
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns the underlying writer, discarding any bytes that have not been written out.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    // Writes out buffered bytes until fewer than `limit` remain.
+    fn poll_write_buf(&mut self, cx: &mut Context<'_>, limit: usize) -> Poll<io::Result<()>> {
+        let inner = &mut self.inner;
+        self.buf.poll_write_out(limit, |pending| Pin::new(&mut *inner).poll_write(cx, pending))
+    }
+}
+
+impl<'a, W: AsyncWrite + Unpin> Sink<CtlvRef<'a>> for FrameSink<W> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let threshold = this.threshold;
+        this.poll_write_buf(cx, threshold)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: CtlvRef<'a>) -> io::Result<()> {
+        self.get_mut().buf.push(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_write_buf(cx, 1) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_flush(cx),
+            other => other,
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_write_buf(cx, 1) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_close(cx),
+            other => other,
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin, B: AsRef<[u8]>> Sink<CtlvGeneric<B>> for FrameSink<W> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Sink::<CtlvRef>::poll_ready(self, cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: CtlvGeneric<B>) -> io::Result<()> {
+        self.start_send(item.as_ctlv_ref())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Sink::<CtlvRef>::poll_flush(self, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Sink::<CtlvRef>::poll_close(self, cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::DecodeError::*;
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+    use futures::{Future, SinkExt, StreamExt};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use std::task::Waker;
+
+    // Returns at most three bytes per read, and is pending every other time.
+    struct Trickle<'a> {
+        input: &'a [u8],
+        pending: bool,
+    }
+
+    impl<'a> AsyncRead for Trickle<'a> {
+        fn poll_read(mut self: Pin<&mut Self>,
+                     cx: &mut Context<'_>,
+                     buf: &mut [u8])
+                     -> Poll<io::Result<usize>> {
+            self.pending = !self.pending;
+            if self.pending {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let len = buf.len().min(self.input.len()).min(3);
+            buf[..len].copy_from_slice(&self.input[..len]);
+            self.input = &self.input[len..];
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    fn fixture() -> Vec<Ctlv> {
+        vec![Ctlv::new(0, vec![1]),
+             Ctlv::new(300, vec![2; 20000]),
+             Ctlv::new(128, vec![]),
+             Ctlv::new(8, vec![3, 4])]
+    }
+
+    #[test]
+    fn roundtrip() {
+        block_on(async {
+            let frames = fixture();
+            let mut w = Cursor::new(Vec::new());
+            for frame in &frames {
+                let written = encode_async(frame.as_ctlv_ref(), &mut w).await.unwrap();
+                assert_eq!(written, frame.encoding_length());
+            }
+            let input = w.into_inner();
+            let expected: Vec<_> = frames.iter().map(Ctlv::encode_vec).collect();
+            assert_eq!(input, expected.concat());
+
+            let mut r = Cursor::new(&input);
+            for frame in &frames {
+                assert_eq!(&decode_async(&mut r).await.unwrap(), frame);
+            }
+            assert!(matches!(decode_async(&mut r).await, Err(ReadDecodeError::Eof)));
+
+            let mut r = Trickle {
+                input: &input,
+                pending: false,
+            };
+            for frame in &frames {
+                assert_eq!(&decode_async(&mut r).await.unwrap(), frame);
+            }
+            assert!(matches!(decode_async(&mut r).await, Err(ReadDecodeError::Eof)));
+        });
+    }
+
+    #[test]
+    fn errors() {
+        block_on(async {
+            let truncated: [(&[u8], usize); 3] = [(&[128, 3, 1, 2], 4), (&[249, 1], 2), (&[16], 1)];
+            for (input, consumed) in &truncated {
+                match decode_async(&mut Cursor::new(input)).await {
+                    Err(ReadDecodeError::UnexpectedEof { consumed: c }) => assert_eq!(c, *consumed),
+                    other => panic!("{:?}", other),
+                }
+            }
+
+            assert!(matches!(decode_async(&mut Cursor::new(&[248, 5, 42])).await,
+                             Err(ReadDecodeError::Decode(Type(_)))));
+
+            let config = DecodeConfig {
+                max_value_len: Some(2),
+                ..DecodeConfig::default()
+            };
+            let mut r = Cursor::new(vec![128, 3, 1, 2, 3]);
+            assert!(matches!(decode_async_with(&mut r, &config).await,
+                             Err(ReadDecodeError::Decode(LengthTooLarge { declared: 3, max: 2 }))));
+            assert_eq!(r.position(), 2);
+        });
+    }
+
+    // Counts how many bytes have been asked for.
+    struct Counting<'a> {
+        input: &'a [u8],
+        requested: usize,
+    }
+
+    impl<'a> AsyncRead for Counting<'a> {
+        fn poll_read(mut self: Pin<&mut Self>,
+                     _cx: &mut Context<'_>,
+                     buf: &mut [u8])
+                     -> Poll<io::Result<usize>> {
+            self.requested += buf.len();
+            let len = buf.len().min(self.input.len());
+            buf[..len].copy_from_slice(&self.input[..len]);
+            self.input = &self.input[len..];
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    #[test]
+    fn stream() {
+        block_on(async {
+            let frames = fixture();
+            let input: Vec<_> = frames.iter().map(Ctlv::encode_vec).collect();
+            let input = input.concat();
+
+            let r = Trickle {
+                input: &input,
+                pending: false,
+            };
+            let decoded: Vec<_> = frame_stream(r).map(Result::unwrap).collect().await;
+            assert_eq!(decoded, frames);
+
+            let mut stream = frame_stream(Cursor::new(&input[..input.len() - 1]));
+            for frame in &frames[..3] {
+                assert_eq!(&stream.next().await.unwrap().unwrap(), frame);
+            }
+            assert!(matches!(stream.next().await,
+                             Some(Err(ReadDecodeError::UnexpectedEof { consumed: 2 }))));
+            assert!(stream.next().await.is_none());
+            assert!(stream.is_terminated());
+
+            let mut stream = frame_stream(Cursor::new(vec![0, 1, 248, 5, 0]));
+            assert!(stream.next().await.unwrap().is_ok());
+            assert!(matches!(stream.next().await,
+                             Some(Err(ReadDecodeError::Decode(Type(_))))));
+            assert!(stream.next().await.is_none());
+        });
+    }
+
+    #[test]
+    fn stream_backpressure() {
+        block_on(async {
+            let input = vec![0; 1000000];
+            let mut stream = frame_stream(Counting {
+                                              input: &input,
+                                              requested: 0,
+                                          });
+            for _ in 0..10 {
+                assert_eq!(stream.next().await.unwrap().unwrap(),
+                           Ctlv::new(0, vec![0]));
+            }
+            let (r, rest) = stream.into_inner();
+            assert_eq!(r.requested, CHUNK_LEN);
+            assert_eq!(rest.len(), CHUNK_LEN - 20);
+            assert_eq!(r.input.len(), input.len() - CHUNK_LEN);
+        });
+    }
+
+    // Yields the given bytes, each repeated the given number of times, in small reads.
+    struct Synthetic(VecDeque<(u8, u64)>);
+
+    impl Synthetic {
+        fn push(&mut self, bytes: &[u8]) {
+            self.0.extend(bytes.iter().map(|byte| (*byte, 1)));
+        }
+    }
+
+    impl AsyncRead for Synthetic {
+        fn poll_read(mut self: Pin<&mut Self>,
+                     _cx: &mut Context<'_>,
+                     buf: &mut [u8])
+                     -> Poll<io::Result<usize>> {
+            let (byte, count) = match self.0.front_mut() {
+                Some(piece) => piece,
+                None => return Poll::Ready(Ok(0)),
+            };
+            let len = (*count).min(buf.len() as u64).min(1000) as usize;
+            for dst in &mut buf[..len] {
+                *dst = *byte;
+            }
+            *count -= len as u64;
+            if *count == 0 {
+                self.0.pop_front();
+            }
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    #[test]
+    fn streaming() {
+        const LEN: u64 = 1 << 26;
+        let mut r = Synthetic(VecDeque::new());
+        r.push(&[0xb0, 251, 4, 0, 0, 0]);
+        r.0.push_back((7, LEN));
+        r.push(&[0xb1, 251, 4, 0, 0, 0]);
+        r.0.push_back((8, LEN));
+        r.push(&[0, 1]);
+        r.push(&[0xb2, 251, 4, 0, 0, 0, 9]);
+
+        block_on(async {
+            let header = read_header_async(&mut r).await.unwrap();
+            assert_eq!(header, Header { type_: 0xb0, len: LEN });
+            let mut body = ValueBody::new(&mut r, &header);
+            let mut w = futures::io::sink();
+            let copied = futures::io::copy(futures::io::BufReader::with_capacity(8192, &mut body),
+                                           &mut w)
+                    .await
+                    .unwrap();
+            assert_eq!(copied, LEN);
+            assert_eq!(body.remaining(), 0);
+
+            let (header, mut body) = read_frame_streaming_async(&mut r).await.unwrap();
+            assert_eq!(header, Header { type_: 0xb1, len: LEN });
+            let mut start = [0; 3];
+            futures::AsyncReadExt::read_exact(&mut body, &mut start).await.unwrap();
+            assert_eq!(start, [8; 3]);
+            assert_eq!(body.skip().await.unwrap(), LEN - 3);
+
+            assert_eq!(decode_async(&mut r).await.unwrap(), Ctlv::new(0, vec![1]));
+
+            let (_, mut body) = read_frame_streaming_async(&mut r).await.unwrap();
+            assert_eq!(body.skip().await.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+            assert!(matches!(read_header_async(&mut r).await, Err(ReadDecodeError::Eof)));
+        });
+
+        let mut r = Cursor::new(vec![128, 2, 1, 2, 0, 3]);
+        block_on(async {
+            let (_, mut body) = read_frame_streaming_async(&mut r).await.unwrap();
+            let mut value = Vec::new();
+            futures::io::copy_buf(&mut body, &mut value).await.unwrap();
+            assert_eq!(value, [1, 2]);
+            assert_eq!(decode_async(&mut r).await.unwrap(), Ctlv::new(0, vec![3]));
+        });
+    }
+
+    #[test]
+    fn cancellation() {
+        let frames = vec![Ctlv::new(300, vec![1; 100]),
+                          Ctlv::new(128, vec![2; 20]),
+                          Ctlv::new(64, vec![3; 256])];
+        let input: Vec<_> = frames.iter().map(Ctlv::encode_vec).collect();
+        let input = input.concat();
+
+        let mut reader = AsyncCtlvReader::new(Trickle {
+                                                  input: &input,
+                                                  pending: false,
+                                              });
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        for frame in &frames {
+            // Seven polls read at most nine bytes, which together with the at most two bytes
+            // left over from the previous ctlv ends within the next one.
+            {
+                let mut next = Box::pin(reader.next());
+                for _ in 0..7 {
+                    assert!(next.as_mut().poll(&mut cx).is_pending());
+                }
+            }
+            assert_eq!(&block_on(reader.next()).unwrap().unwrap(), frame);
+        }
+        assert!(block_on(reader.next()).is_none());
+        assert!(block_on(reader.next()).is_none());
+    }
+
+    // One direction of an in-memory pipe that holds at most 100 bytes.
+    #[derive(Default)]
+    struct Pipe {
+        bytes: VecDeque<u8>,
+        closed: bool,
+        reader: Option<Waker>,
+        writer: Option<Waker>,
+    }
+
+    struct PipeWriter(Rc<RefCell<Pipe>>);
+
+    struct PipeReader(Rc<RefCell<Pipe>>);
+
+    impl AsyncWrite for PipeWriter {
+        fn poll_write(self: Pin<&mut Self>,
+                      cx: &mut Context<'_>,
+                      buf: &[u8])
+                      -> Poll<io::Result<usize>> {
+            let mut pipe = self.0.borrow_mut();
+            let len = buf.len().min(100 - pipe.bytes.len());
+            if len == 0 {
+                pipe.writer = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            pipe.bytes.extend(&buf[..len]);
+            if let Some(waker) = pipe.reader.take() {
+                waker.wake();
+            }
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            let mut pipe = self.0.borrow_mut();
+            pipe.closed = true;
+            if let Some(waker) = pipe.reader.take() {
+                waker.wake();
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncRead for PipeReader {
+        fn poll_read(self: Pin<&mut Self>,
+                     cx: &mut Context<'_>,
+                     buf: &mut [u8])
+                     -> Poll<io::Result<usize>> {
+            let mut pipe = self.0.borrow_mut();
+            if pipe.bytes.is_empty() && !pipe.closed {
+                pipe.reader = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            let len = buf.len().min(pipe.bytes.len());
+            for (dst, src) in buf.iter_mut().zip(pipe.bytes.drain(..len)) {
+                *dst = src;
+            }
+            if let Some(waker) = pipe.writer.take() {
+                waker.wake();
+            }
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    #[test]
+    fn sink() {
+        let frames: Vec<_> = (0..500u64)
+            .map(|i| Ctlv::new(128 + i, vec![i as u8; (i % 70) as usize]))
+            .collect();
+
+        let pipe = Rc::new(RefCell::new(Pipe::default()));
+        let mut sink = frame_sink(PipeWriter(pipe.clone()));
+        let stream = frame_stream(PipeReader(pipe));
+        let send = async {
+            for frame in &frames[..250] {
+                sink.feed(frame.as_ctlv_ref()).await.unwrap();
+            }
+            for frame in &frames[250..] {
+                sink.feed(frame.clone()).await.unwrap();
+            }
+            SinkExt::<Ctlv>::close(&mut sink).await.unwrap();
+        };
+        let receive = stream.map(Result::unwrap).collect::<Vec<_>>();
+        let ((), received) = block_on(futures::future::join(send, receive));
+        assert_eq!(received, frames);
+
+        let mut sink = FrameSink::with_threshold(Cursor::new(Vec::new()), 10);
+        block_on(async {
+            sink.feed(CtlvRef { type_: 0, value: &[1] }).await.unwrap();
+            assert_eq!(sink.buffered(), 2);
+            assert!(sink.get_ref().get_ref().is_empty());
+            SinkExt::<Ctlv>::flush(&mut sink).await.unwrap();
+            assert_eq!(sink.buffered(), 0);
+        });
+        assert_eq!(sink.into_inner().into_inner(), [0, 1]);
+    }
+
+    // Accepts at most three bytes per write, and is pending every other time.
+    struct TrickleWriter {
+        out: Vec<u8>,
+        pending: bool,
+    }
+
+    impl AsyncWrite for TrickleWriter {
+        fn poll_write(mut self: Pin<&mut Self>,
+                      cx: &mut Context<'_>,
+                      buf: &[u8])
+                      -> Poll<io::Result<usize>> {
+            self.pending = !self.pending;
+            if self.pending {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let len = buf.len().min(3);
+            self.out.extend_from_slice(&buf[..len]);
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn sink_backpressure() {
+        // Encodings of up to 203 bytes.
+        let frames: Vec<_> = (0..200u64).map(|i| Ctlv::new(128 + i, vec![i as u8; i as usize]))
+                                        .collect();
+        let expected: Vec<_> = frames.iter().map(Ctlv::encode_vec).collect();
+
+        let trickle = TrickleWriter {
+            out: Vec::new(),
+            pending: false,
+        };
+        let mut sink = FrameSink::with_threshold(trickle, 100);
+        block_on(async {
+            let mut max_buffered = 0;
+            let mut max_capacity = 0;
+            for frame in &frames {
+                sink.feed(frame.as_ctlv_ref()).await.unwrap();
+                max_buffered = max_buffered.max(sink.buffered());
+                max_capacity = max_capacity.max(sink.buf.capacity());
+            }
+            // At most one ctlv is added to a buffer holding less than the threshold, and the
+            // bytes that have been written out do not stay in the buffer.
+            assert!(max_buffered < 100 + 203);
+            assert!(max_capacity < 2 * (100 + 203));
+            assert!(!sink.get_ref().out.is_empty());
+            SinkExt::<Ctlv>::flush(&mut sink).await.unwrap();
+            assert_eq!(sink.buffered(), 0);
+        });
+        assert_eq!(sink.into_inner().out, expected.concat());
+    }
+}
@@ -0,0 +1,282 @@
+use std::io;
+
+use bytes::{Buf, BytesMut};
+#[cfg(feature = "tokio")]
+use tokio_util::codec;
+
+use super::read::read_header_impl;
+use super::DecodeError::UnexpectedEndOfInput;
+#[cfg(feature = "tokio")]
+use super::CtlvGeneric;
+use super::{Ctlv, CtlvRef, DecodeConfig, ReadDecodeError, MAX_HEADER_LENGTH};
+
+/// The maximum length of a value that a [`CtlvCodec`](struct.CtlvCodec.html) created with
+/// `CtlvCodec::new` accepts: 8 MiB.
+pub const DEFAULT_MAX_VALUE_LEN: usize = 8 * 1024 * 1024;
+
+// How much space to reserve in the read buffer at most for the remainder of a ctlv, so that a
+// declared length alone can not make the codec allocate.
+const RESERVE_CHUNK_LEN: usize = 8 * 1024;
+
+/// A codec for sequences of ctlvs, for use with `Framed`, `FramedRead` or `FramedWrite`.
+///
+/// With the `tokio` feature, this implements the tokio-util `Encoder` and `Decoder` traits,
+/// with the `asynchronous-codec` feature those of the asynchronous-codec crate, as used with
+/// async-std and smol. Both behave identically.
+///
+/// Decoding reads the header of a ctlv first, then reserves space for the remainder of it in
+/// the read buffer (at most 8 KiB at a time, so that the read buffer only grows as data
+/// arrives), and waits until it is complete. Values longer than the `max_value_len` of the
+/// [`DecodeConfig`](struct.DecodeConfig.html) are rejected as soon as their header has been
+/// read, so that a malicious peer can not make the codec buffer an arbitrary amount of data.
+/// If the stream ends within a ctlv, decoding errors with `ReadDecodeError::UnexpectedEof`.
+///
+/// Requires the `tokio` or the `asynchronous-codec` feature.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CtlvCodec {
+    config: DecodeConfig,
+}
+
+impl Default for CtlvCodec {
+    fn default() -> CtlvCodec {
+        CtlvCodec::new()
+    }
+}
+
+impl CtlvCodec {
+    /// Creates a `CtlvCodec` that accepts values of up to
+    /// [`DEFAULT_MAX_VALUE_LEN`](constant.DEFAULT_MAX_VALUE_LEN.html) bytes.
+    pub fn new() -> CtlvCodec {
+        CtlvCodec::with_config(DecodeConfig {
+                                   max_value_len: Some(DEFAULT_MAX_VALUE_LEN),
+                                   ..DecodeConfig::default()
+                               })
+    }
+
+    /// Creates a `CtlvCodec` that decodes with the given configuration.
+    pub fn with_config(config: DecodeConfig) -> CtlvCodec {
+        CtlvCodec { config }
+    }
+
+    // The framing shared by the implementations of the codec traits.
+
+    fn decode_buf(&self, src: &mut BytesMut) -> Result<Option<Ctlv>, ReadDecodeError> {
+        let (consumed, ctlv) = match CtlvRef::decode_with(src, &self.config) {
+            Ok((frame, tail)) => (src.len() - tail.len(), frame.to_owned()),
+            Err((UnexpectedEndOfInput, _)) => {
+                // Reserve space for the rest of the ctlv once its header is complete, but only as
+                // much as a single chunk, since the peer may never send the declared bytes.
+                if let Ok((header, header_len)) = read_header_impl(&mut &src[..], &self.config) {
+                    let end = (header_len as u64).saturating_add(header.len);
+                    let missing = end - src.len() as u64;
+                    src.reserve(missing.min(RESERVE_CHUNK_LEN as u64) as usize);
+                }
+                return Ok(None);
+            }
+            Err((e, _)) => return Err(ReadDecodeError::Decode(e)),
+        };
+        src.advance(consumed);
+        Ok(Some(ctlv))
+    }
+
+    fn decode_buf_eof(&self, src: &mut BytesMut) -> Result<Option<Ctlv>, ReadDecodeError> {
+        match self.decode_buf(src)? {
+            Some(ctlv) => Ok(Some(ctlv)),
+            None if src.is_empty() => Ok(None),
+            None => Err(ReadDecodeError::UnexpectedEof { consumed: src.len() }),
+        }
+    }
+
+    fn encode_buf(item: CtlvRef, dst: &mut BytesMut) {
+        let mut header = [0; MAX_HEADER_LENGTH];
+        let header_len = item.encode_header(&mut header);
+        dst.reserve(header_len + item.value.len());
+        dst.extend_from_slice(&header[..header_len]);
+        dst.extend_from_slice(item.value);
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl codec::Decoder for CtlvCodec {
+    type Item = Ctlv;
+    type Error = ReadDecodeError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Ctlv>, ReadDecodeError> {
+        self.decode_buf(src)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Ctlv>, ReadDecodeError> {
+        self.decode_buf_eof(src)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<'a> codec::Encoder<CtlvRef<'a>> for CtlvCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: CtlvRef<'a>, dst: &mut BytesMut) -> io::Result<()> {
+        CtlvCodec::encode_buf(item, dst);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<B: AsRef<[u8]>> codec::Encoder<CtlvGeneric<B>> for CtlvCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: CtlvGeneric<B>, dst: &mut BytesMut) -> io::Result<()> {
+        codec::Encoder::encode(self, item.as_ctlv_ref(), dst)
+    }
+}
+
+#[cfg(feature = "asynchronous-codec")]
+impl asynchronous_codec::Decoder for CtlvCodec {
+    type Item = Ctlv;
+    type Error = ReadDecodeError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Ctlv>, ReadDecodeError> {
+        self.decode_buf(src)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Ctlv>, ReadDecodeError> {
+        self.decode_buf_eof(src)
+    }
+}
+
+/// Encodes `CtlvRef`s, use `as_ctlv_ref` to send owned ctlvs.
+#[cfg(feature = "asynchronous-codec")]
+impl asynchronous_codec::Encoder for CtlvCodec {
+    type Item<'a> = CtlvRef<'a>;
+    type Error = io::Error;
+
+    fn encode(&mut self, item: CtlvRef<'_>, dst: &mut BytesMut) -> io::Result<()> {
+        CtlvCodec::encode_buf(item, dst);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::DecodeError;
+    use futures::{SinkExt, StreamExt};
+
+    fn fixture() -> Vec<Ctlv> {
+        vec![Ctlv::new(0, vec![1]),
+             Ctlv::new(300, vec![2; 3000]),
+             Ctlv::new(128, vec![]),
+             Ctlv::new(16, vec![3, 4, 5, 6]),
+             Ctlv::new(u64::MAX, vec![7; 200])]
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn framed() {
+        use tokio::io::AsyncWriteExt;
+        use tokio_util::codec::{FramedRead, FramedWrite};
+
+        let frames = fixture();
+        let input: Vec<_> = frames.iter().map(Ctlv::encode_vec).collect();
+        let input = input.concat();
+
+        let (mut tx, rx) = tokio::io::duplex(64);
+        let writer = tokio::spawn(async move {
+            // Pieces of a length that splits the headers and values at varying offsets.
+            for piece in input.chunks(7) {
+                tx.write_all(piece).await.unwrap();
+            }
+        });
+        let decoded: Vec<_> = FramedRead::new(rx, CtlvCodec::new()).collect().await;
+        writer.await.unwrap();
+        assert_eq!(decoded.into_iter().map(Result::unwrap).collect::<Vec<_>>(), frames);
+
+        let mut w = FramedWrite::new(Vec::new(), CtlvCodec::new());
+        for frame in &frames {
+            w.send(frame.as_ctlv_ref()).await.unwrap();
+        }
+        w.send(frames[0].clone()).await.unwrap();
+        let expected: Vec<_> = frames.iter().chain(&frames[..1]).map(Ctlv::encode_vec).collect();
+        assert_eq!(w.into_inner(), expected.concat());
+    }
+
+    #[cfg(feature = "asynchronous-codec")]
+    #[test]
+    fn framed_asynchronous_codec() {
+        use asynchronous_codec::Framed;
+        use futures::io::Cursor;
+
+        let frames = fixture();
+        futures::executor::block_on(async {
+            let mut framed = Framed::new(Cursor::new(Vec::new()), CtlvCodec::new());
+            for frame in &frames {
+                framed.send(frame.as_ctlv_ref()).await.unwrap();
+            }
+            let mut io = framed.into_inner();
+            let expected: Vec<_> = frames.iter().map(Ctlv::encode_vec).collect();
+            assert_eq!(io.get_ref(), &expected.concat());
+
+            io.set_position(0);
+            let decoded: Vec<_> = Framed::new(io, CtlvCodec::new()).collect().await;
+            assert_eq!(decoded.into_iter().map(Result::unwrap).collect::<Vec<_>>(), frames);
+
+            let io = Cursor::new(vec![128, 3, 1]);
+            let mut framed = Framed::new(io, CtlvCodec::new());
+            assert!(matches!(framed.next().await,
+                             Some(Err(ReadDecodeError::UnexpectedEof { consumed: 3 }))));
+        });
+    }
+
+    #[test]
+    fn reserve() {
+        let codec = CtlvCodec::new();
+        let mut buf = BytesMut::from(&[249, 1, 44, 249, 19][..]);
+        assert_eq!(codec.decode_buf(&mut buf).unwrap(), None);
+        buf.extend_from_slice(&[136, 1]);
+        assert_eq!(codec.decode_buf(&mut buf).unwrap(), None);
+        assert!(buf.capacity() >= 6 + 5000);
+
+        // Larger values get their space a chunk at a time.
+        let mut buf = BytesMut::from(&[249, 1, 44, 250, 1, 0, 0][..]);
+        assert_eq!(codec.decode_buf(&mut buf).unwrap(), None);
+        assert!(buf.capacity() >= 7 + RESERVE_CHUNK_LEN);
+        assert!(buf.capacity() < 7 + 2 * RESERVE_CHUNK_LEN);
+
+        let mut buf = BytesMut::new();
+        CtlvCodec::encode_buf(CtlvRef { type_: 8, value: &[1, 2] }, &mut buf);
+        buf.extend_from_slice(&[128]);
+        assert_eq!(codec.decode_buf(&mut buf).unwrap(), Some(Ctlv::new(8, vec![1, 2])));
+        assert_eq!(buf, [128][..]);
+    }
+
+    #[test]
+    fn errors() {
+        let codec = CtlvCodec::with_config(DecodeConfig {
+                                               max_value_len: Some(100),
+                                               ..DecodeConfig::default()
+                                           });
+        let mut buf = BytesMut::from(&[128, 101][..]);
+        assert!(matches!(codec.decode_buf(&mut buf),
+                         Err(ReadDecodeError::Decode(DecodeError::LengthTooLarge {
+                                                         declared: 101,
+                                                         max: 100,
+                                                     }))));
+
+        let mut buf = BytesMut::from(&[128, 3, 1][..]);
+        assert!(matches!(codec.decode_buf_eof(&mut buf),
+                         Err(ReadDecodeError::UnexpectedEof { consumed: 3 })));
+        assert_eq!(codec.decode_buf_eof(&mut BytesMut::new()).unwrap(), None);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn max_lengths_unlimited() {
+        let codec = CtlvCodec::with_config(DecodeConfig::default());
+        for input in super::super::test_util::max_lengths() {
+            let mut buf = BytesMut::from(&input[..]);
+            assert_eq!(codec.decode_buf(&mut buf).unwrap(), None);
+            assert!(buf.capacity() <= input.len() + RESERVE_CHUNK_LEN);
+            assert!(matches!(codec.decode_buf_eof(&mut buf),
+                             Err(ReadDecodeError::UnexpectedEof { .. })));
+        }
+    }
+}
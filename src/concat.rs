@@ -0,0 +1,154 @@
+use std::io::Read;
+
+use super::read::read_header_impl;
+use super::{Ctlv, CtlvIter, CtlvRef, DecodeConfig, DecodeError, ReadDecodeError};
+
+/// Join several encoded sequences of ctlvs into one, after checking that each of them is a
+/// complete sequence.
+///
+/// Simply concatenating the buffers would silently join a truncated ctlv at the end of one
+/// buffer with the start of the next one. Instead, this returns the index of the first invalid
+/// buffer, the error, and the offset within that buffer at which it occurred.
+pub fn concat_validated(buffers: &[&[u8]]) -> Result<Vec<u8>, (usize, DecodeError, usize)> {
+    let mut out = Vec::with_capacity(buffers.iter().map(|buffer| buffer.len()).sum());
+    concat_validated_into(buffers, &mut out)?;
+    Ok(out)
+}
+
+/// What [`concat_validated_into`](fn.concat_validated_into.html) appended.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ConcatInfo {
+    /// How many ctlvs the buffers contained in total.
+    pub frames: usize,
+    /// How many bytes were appended.
+    pub bytes: usize,
+}
+
+/// Like [`concat_validated`](fn.concat_validated.html), but appends the buffers to `out`.
+///
+/// Each buffer is checked before it is appended. On error, `out` is left unchanged.
+pub fn concat_validated_into(buffers: &[&[u8]],
+                             out: &mut Vec<u8>)
+                             -> Result<ConcatInfo, (usize, DecodeError, usize)> {
+    let start = out.len();
+    let mut info = ConcatInfo::default();
+
+    for (index, buffer) in buffers.iter().enumerate() {
+        for result in CtlvIter::new(buffer) {
+            if let Err((e, offset)) = result {
+                out.truncate(start);
+                return Err((index, e, offset));
+            }
+            info.frames += 1;
+        }
+        out.extend_from_slice(buffer);
+        info.bytes += buffer.len();
+    }
+    Ok(info)
+}
+
+/// Check that each of the buffers is a complete sequence of ctlvs (like
+/// [`concat_validated`](fn.concat_validated.html)), and return an iterator over the ctlvs of all
+/// of them, without copying anything.
+pub fn validate_then_chain<'a>(buffers: &'a [&'a [u8]])
+                               -> Result<impl Iterator<Item = CtlvRef<'a>> + 'a,
+                                         (usize, DecodeError, usize)> {
+    for (index, buffer) in buffers.iter().enumerate() {
+        for result in CtlvIter::new(buffer) {
+            result.map_err(|(e, offset)| (index, e, offset))?;
+        }
+    }
+    Ok(buffers.iter().flat_map(|buffer| CtlvIter::new(buffer).map(Result::unwrap)))
+}
+
+/// Decode a `Ctlv` from the concatenation of two slices, without concatenating them, e.g.
+/// because the ctlv straddles the wrap point of a ring buffer. Returns the ctlv and how many
+/// bytes it took up of `first` and of `second`.
+///
+/// Errors like [`CtlvRef::decode`](struct.CtlvRef.html#method.decode), with
+/// `DecodeError::UnexpectedEndOfInput` if the ctlv continues past the end of `second`.
+pub fn decode_chained(first: &[u8], second: &[u8]) -> Result<(Ctlv, usize, usize), DecodeError> {
+    let mut r = first.chain(second);
+    let (header, header_len) = match read_header_impl(&mut r, &DecodeConfig::default()) {
+        Ok(header) => header,
+        Err(ReadDecodeError::Decode(e)) => return Err(e),
+        Err(_) => return Err(DecodeError::UnexpectedEndOfInput),
+    };
+    if header.len > (first.len() + second.len() - header_len) as u64 {
+        return Err(DecodeError::UnexpectedEndOfInput);
+    }
+
+    let mut value = Vec::with_capacity(header.len as usize);
+    // Reading from slices can not fail.
+    r.take(header.len).read_to_end(&mut value).unwrap();
+    let consumed = header_len + value.len();
+    let from_first = consumed.min(first.len());
+    Ok((Ctlv::new(header.type_, value), from_first, consumed - from_first))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concat() {
+        assert_eq!(concat_validated(&[&[0, 1, 128, 1, 2], &[], &[8, 3, 4]]).unwrap(),
+                   [0, 1, 128, 1, 2, 8, 3, 4]);
+        assert_eq!(concat_validated(&[]).unwrap(), []);
+    }
+
+    #[test]
+    fn truncated_buffer() {
+        // Naively joined, the second and third buffer would form a valid sequence.
+        let buffers: [&[u8]; 3] = [&[0, 1], &[0, 2, 128, 2, 3], &[4]];
+        assert_eq!(buffers.concat(), [0, 1, 0, 2, 128, 2, 3, 4]);
+        assert_eq!(concat_validated(&buffers),
+                   Err((1, DecodeError::UnexpectedEndOfInput, 2)));
+    }
+
+    #[test]
+    fn into() {
+        let buffers: [&[u8]; 4] = [&[], &[0, 1, 128, 1, 2], &[], &[8, 3, 4]];
+        let mut out = vec![42];
+        assert_eq!(concat_validated_into(&buffers, &mut out),
+                   Ok(ConcatInfo { frames: 3, bytes: 8 }));
+        assert_eq!(out, [42, 0, 1, 128, 1, 2, 8, 3, 4]);
+
+        let buffers: [&[u8]; 3] = [&[0, 1], &[0, 2, 128, 2, 3], &[4]];
+        assert_eq!(concat_validated_into(&buffers, &mut out),
+                   Err((1, DecodeError::UnexpectedEndOfInput, 2)));
+        assert_eq!(out, [42, 0, 1, 128, 1, 2, 8, 3, 4]);
+    }
+
+    #[test]
+    fn chain() {
+        let buffers: [&[u8]; 4] = [&[0, 1], &[], &[128, 1, 2], &[]];
+        assert_eq!(validate_then_chain(&buffers).unwrap().collect::<Vec<_>>(),
+                   [CtlvRef { type_: 0, value: &[1] }, CtlvRef { type_: 128, value: &[2] }]);
+        assert_eq!(validate_then_chain(&[]).unwrap().count(), 0);
+
+        let buffers: [&[u8]; 3] = [&[0, 1], &[0, 2, 128, 2, 3], &[4]];
+        assert_eq!(validate_then_chain(&buffers).err(),
+                   Some((1, DecodeError::UnexpectedEndOfInput, 2)));
+    }
+
+    #[test]
+    fn chained() {
+        let encoded = [Ctlv::new(300, vec![1, 2, 3]).encode_vec(), vec![0, 9]].concat();
+        for i in 0..=encoded.len() {
+            let (first, second) = encoded.split_at(i);
+            let (ctlv, from_first, from_second) = decode_chained(first, second).unwrap();
+            assert_eq!(ctlv, Ctlv::new(300, vec![1, 2, 3]));
+            assert_eq!(from_first, i.min(7));
+            assert_eq!(from_first + from_second, 7);
+        }
+
+        assert_eq!(decode_chained(&[], &[]), Err(DecodeError::UnexpectedEndOfInput));
+        assert_eq!(decode_chained(&[249], &[1]), Err(DecodeError::UnexpectedEndOfInput));
+        assert_eq!(decode_chained(&[128, 3, 1], &[2]), Err(DecodeError::UnexpectedEndOfInput));
+        assert_eq!(decode_chained(&[248], &[5, 0]),
+                   Err(DecodeError::Type(varu64::DecodeError::NonCanonical(5))));
+        assert_eq!(decode_chained(&[8], &[1, 2, 3]),
+                   Ok((Ctlv::new(8, vec![1, 2]), 1, 2)));
+    }
+}
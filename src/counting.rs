@@ -0,0 +1,60 @@
+use std::io;
+
+/// An `io::Write` that discards all bytes written to it, and counts them.
+///
+/// This computes the size of an encoding without storing it, e.g. the size of a sequence that
+/// is produced piece by piece:
+///
+/// ```
+/// # use ctlv::{Ctlv, CountingWriter};
+/// let ctlv = Ctlv::new(300, vec![1, 2, 3]);
+/// let mut counter = CountingWriter::default();
+/// ctlv.encode_write(&mut counter).unwrap();
+/// ctlv.encode_write(&mut counter).unwrap();
+/// assert_eq!(counter.count(), 2 * ctlv.encoding_length());
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct CountingWriter {
+    count: usize,
+}
+
+impl CountingWriter {
+    /// Returns how many bytes have been written so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+impl io::Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.count += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::CtlvRef;
+
+    #[test]
+    fn matches_encoding_length() {
+        for len in &[0, 1, 247, 248, 300, 70000] {
+            let value = vec![0; *len];
+            for type_ in &[128, 300, u64::MAX] {
+                let ctlv = CtlvRef { type_: *type_, value: &value };
+                let mut counter = CountingWriter::default();
+                assert_eq!(ctlv.encode_write(&mut counter).unwrap(), ctlv.encoding_length());
+                assert_eq!(counter.count(), ctlv.encoding_length());
+            }
+        }
+
+        let mut counter = CountingWriter::default();
+        CtlvRef { type_: 0, value: &[1] }.encode_write(&mut counter).unwrap();
+        assert_eq!(counter.count(), 2);
+    }
+}
@@ -0,0 +1,148 @@
+use std::borrow::Cow;
+use std::io::{self, BufRead, Cursor, Read, Seek, SeekFrom};
+
+use super::{Ctlv, CtlvGeneric, CtlvRef};
+
+/// An `io::Read`, `io::BufRead` and `io::Seek` over the value of a ctlv, which also knows the
+/// type of the ctlv.
+///
+/// Returned by [`value_reader`](struct.CtlvGeneric.html#method.value_reader), which borrows
+/// the value, and [`into_value_reader`](struct.CtlvGeneric.html#method.into_value_reader),
+/// which takes ownership of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueCursor<'a> {
+    type_: u64,
+    cursor: Cursor<Cow<'a, [u8]>>,
+}
+
+impl<'a> ValueCursor<'a> {
+    /// Returns the type of the ctlv.
+    pub fn type_(&self) -> u64 {
+        self.type_
+    }
+
+    /// Returns the whole value, regardless of the position of the cursor.
+    pub fn value(&self) -> &[u8] {
+        self.cursor.get_ref()
+    }
+
+    /// Returns the position of the cursor within the value.
+    pub fn position(&self) -> u64 {
+        self.cursor.position()
+    }
+
+    /// Returns how many bytes of the value have not been read yet. This is zero if the cursor
+    /// has been seeked past the end of the value.
+    pub fn remaining(&self) -> usize {
+        let len = self.cursor.get_ref().len() as u64;
+        len.saturating_sub(self.cursor.position()) as usize
+    }
+}
+
+impl<'a> Read for ValueCursor<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.cursor.read(buf)
+    }
+}
+
+impl<'a> BufRead for ValueCursor<'a> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.cursor.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.cursor.consume(amt)
+    }
+}
+
+impl<'a> Seek for ValueCursor<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.cursor.seek(pos)
+    }
+}
+
+impl<B: AsRef<[u8]>> CtlvGeneric<B> {
+    /// Returns a reader over the value of this ctlv.
+    pub fn value_reader(&self) -> ValueCursor<'_> {
+        self.as_ctlv_ref().value_reader()
+    }
+}
+
+impl Ctlv {
+    /// Converts this ctlv into a reader over its value.
+    pub fn into_value_reader(self) -> ValueCursor<'static> {
+        ValueCursor {
+            type_: self.type_,
+            cursor: Cursor::new(Cow::Owned(self.into_value())),
+        }
+    }
+}
+
+impl<'a> CtlvRef<'a> {
+    /// Returns a reader over the value of this ctlv.
+    pub fn value_reader(&self) -> ValueCursor<'a> {
+        ValueCursor {
+            type_: self.type_,
+            cursor: Cursor::new(Cow::Borrowed(self.value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read() {
+        let ctlv = Ctlv::new(300, (0..100).collect());
+        let mut r = ctlv.value_reader();
+        assert_eq!(r.type_(), 300);
+        let mut out = Vec::new();
+        let mut chunk = [0; 7];
+        loop {
+            let n = r.read(&mut chunk).unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&chunk[..n]);
+            assert_eq!(r.remaining(), 100 - out.len());
+        }
+        assert_eq!(out, ctlv.value());
+
+        let mut r = ctlv.clone().into_value_reader();
+        let mut out = Vec::new();
+        assert_eq!(r.read_to_end(&mut out).unwrap(), 100);
+        assert_eq!(out, ctlv.value());
+        assert_eq!(r.remaining(), 0);
+
+        let mut r = CtlvRef { type_: 8, value: &[1, 2] }.value_reader();
+        assert_eq!(r.fill_buf().unwrap(), [1, 2]);
+        r.consume(1);
+        assert_eq!(r.fill_buf().unwrap(), [2]);
+        assert_eq!(r.value(), [1, 2]);
+    }
+
+    #[test]
+    fn seek() {
+        let ctlv = CtlvRef {
+            type_: 128,
+            value: &[1, 2, 3, 4, 5],
+        };
+        let mut r = ctlv.value_reader();
+        assert_eq!(r.seek(SeekFrom::End(-2)).unwrap(), 3);
+        assert_eq!(r.remaining(), 2);
+        let mut byte = [0];
+        r.read_exact(&mut byte).unwrap();
+        assert_eq!(byte, [4]);
+
+        assert_eq!(r.seek(SeekFrom::Current(-3)).unwrap(), 1);
+        r.read_exact(&mut byte).unwrap();
+        assert_eq!(byte, [2]);
+        assert_eq!(r.position(), 2);
+
+        assert_eq!(r.seek(SeekFrom::Start(10)).unwrap(), 10);
+        assert_eq!(r.remaining(), 0);
+        assert_eq!(r.read(&mut byte).unwrap(), 0);
+        assert!(r.seek(SeekFrom::Current(-11)).is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use std::iter::FusedIterator;
+
+use super::{CtlvRef, CtlvSlice, DecodeError};
+
+/// Returns an iterator that packs ctlvs into datagrams of at most `mtu` bytes, each consisting
+/// of whole ctlvs.
+///
+/// The ctlvs are packed in order: each datagram is filled with as many ctlvs as fit, and
+/// yielded as soon as the next ctlv does not fit anymore. A ctlv whose encoding alone is longer
+/// than `mtu` can not be sent in any datagram; it is yielded as `Err` (after the datagram that
+/// precedes it), so that the caller can [`fragment`](fn.fragment.html) it, and packing
+/// continues with the following ctlvs.
+///
+/// Every datagram is a valid sequence of ctlvs, see
+/// [`unpack_datagram`](fn.unpack_datagram.html).
+pub fn pack_datagrams<'a, I>(frames: I, mtu: usize) -> PackDatagrams<'a, I::IntoIter>
+    where I: IntoIterator<Item = CtlvRef<'a>>
+{
+    PackDatagrams {
+        frames: frames.into_iter(),
+        mtu,
+        next: None,
+    }
+}
+
+/// The iterator returned by [`pack_datagrams`](fn.pack_datagrams.html).
+#[derive(Debug, Clone)]
+pub struct PackDatagrams<'a, I> {
+    frames: I,
+    mtu: usize,
+    // A ctlv that did not fit into the previous datagram.
+    next: Option<CtlvRef<'a>>,
+}
+
+impl<'a, I: Iterator<Item = CtlvRef<'a>>> Iterator for PackDatagrams<'a, I> {
+    type Item = Result<Vec<u8>, CtlvRef<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut datagram = Vec::new();
+        while let Some(frame) = self.next.take().or_else(|| self.frames.next()) {
+            let len = frame.encoding_length();
+            if datagram.len() + len > self.mtu {
+                if datagram.is_empty() {
+                    return Some(Err(frame));
+                }
+                self.next = Some(frame);
+                break;
+            }
+            let start = datagram.len();
+            datagram.resize(start + len, 0);
+            frame.encode(&mut datagram[start..]);
+        }
+
+        if datagram.is_empty() {
+            None
+        } else {
+            Some(Ok(datagram))
+        }
+    }
+}
+
+impl<'a, I: FusedIterator<Item = CtlvRef<'a>>> FusedIterator for PackDatagrams<'a, I> {}
+
+/// Validates that a received datagram consists of whole ctlvs, as produced by
+/// [`pack_datagrams`](fn.pack_datagrams.html).
+///
+/// This is the same as [`CtlvSlice::new`](struct.CtlvSlice.html#method.new), except that an
+/// empty datagram is rejected with `DecodeError::UnexpectedEndOfInput` at offset `0`, since
+/// `pack_datagrams` never produces one.
+pub fn unpack_datagram(datagram: &[u8]) -> Result<CtlvSlice<'_>, (DecodeError, usize)> {
+    if datagram.is_empty() {
+        return Err((DecodeError::UnexpectedEndOfInput, 0));
+    }
+    CtlvSlice::new(datagram)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ctlvs with encodings of 2, 4, 4 and 2 bytes.
+    const FRAMES: [CtlvRef<'static>; 4] = [CtlvRef { type_: 0, value: &[1] },
+                                           CtlvRef { type_: 128, value: &[2, 3] },
+                                           CtlvRef { type_: 300, value: &[] },
+                                           CtlvRef { type_: 0, value: &[5] }];
+
+    fn encode(frames: &[CtlvRef]) -> Vec<u8> {
+        frames.iter().map(CtlvRef::encode_vec).collect::<Vec<_>>().concat()
+    }
+
+    #[test]
+    fn boundaries() {
+        // The first two ctlvs fit exactly, as do the last two.
+        let datagrams: Vec<_> = pack_datagrams(FRAMES.iter().copied(), 6).collect();
+        assert_eq!(datagrams, [Ok(encode(&FRAMES[..2])), Ok(encode(&FRAMES[2..]))]);
+
+        // One byte less, and the ctlvs are packed differently.
+        let datagrams: Vec<_> = pack_datagrams(FRAMES.iter().copied(), 5).collect();
+        assert_eq!(datagrams,
+                   [Ok(encode(&FRAMES[..1])),
+                    Ok(encode(&FRAMES[1..2])),
+                    Ok(encode(&FRAMES[2..3])),
+                    Ok(encode(&FRAMES[3..]))]);
+
+        // Everything fits exactly into one datagram.
+        let datagrams: Vec<_> = pack_datagrams(FRAMES.iter().copied(), 12).collect();
+        assert_eq!(datagrams, [Ok(encode(&FRAMES))]);
+
+        assert_eq!(pack_datagrams(Vec::new(), 100).next(), None);
+    }
+
+    #[test]
+    fn frame_equal_to_mtu() {
+        let datagrams: Vec<_> = pack_datagrams(FRAMES.iter().copied(), 4).collect();
+        assert_eq!(datagrams,
+                   [Ok(encode(&FRAMES[..1])),
+                    Ok(encode(&FRAMES[1..2])),
+                    Ok(encode(&FRAMES[2..3])),
+                    Ok(encode(&FRAMES[3..]))]);
+    }
+
+    #[test]
+    fn oversized() {
+        let datagrams: Vec<_> = pack_datagrams(FRAMES.iter().copied(), 3).collect();
+        assert_eq!(datagrams,
+                   [Ok(encode(&FRAMES[..1])),
+                    Err(FRAMES[1]),
+                    Err(FRAMES[2]),
+                    Ok(encode(&FRAMES[3..]))]);
+
+        let mut datagrams = pack_datagrams(FRAMES.iter().copied(), 0);
+        assert_eq!(datagrams.next(), Some(Err(FRAMES[0])));
+        assert_eq!(datagrams.count(), 3);
+    }
+
+    #[test]
+    fn unpack() {
+        for datagram in pack_datagrams(FRAMES.iter().copied(), 6) {
+            let datagram = datagram.unwrap();
+            assert_eq!(unpack_datagram(&datagram).unwrap().as_bytes(), &datagram[..]);
+        }
+        let datagram = encode(&FRAMES);
+        assert!(unpack_datagram(&datagram).unwrap().iter().eq(FRAMES.iter().copied()));
+
+        assert_eq!(unpack_datagram(&[]), Err((DecodeError::UnexpectedEndOfInput, 0)));
+        assert_eq!(unpack_datagram(&[0, 1, 128, 2, 3]),
+                   Err((DecodeError::UnexpectedEndOfInput, 2)));
+    }
+}
@@ -0,0 +1,545 @@
+use std::convert::TryInto;
+use std::{error, fmt, mem};
+
+use varu64::DecodeError as VarU64Error;
+
+use super::DecodeError::{self, *};
+use super::{type_implied_length, Ctlv, DecodeConfig, EXPLICIT_LENGTH_MIN_TYPE};
+
+// What a `Decoder` is waiting for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+    Type,
+    Length { type_: u64 },
+    Value { type_: u64, len: usize, value: Vec<u8> },
+}
+
+/// The state of a [`Decoder`](struct.Decoder.html) apart from the buffered part of the value,
+/// as returned by [`Decoder::checkpoint`](struct.Decoder.html#method.checkpoint).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecoderState {
+    /// The configuration of the decoder.
+    pub config: DecodeConfig,
+    /// The bytes of the type or length that have been received so far.
+    pub header: Vec<u8>,
+    /// The type of the current ctlv, if it has been decoded already.
+    pub type_: Option<u64>,
+    /// The length of the value of the current ctlv, if it has been decoded already.
+    pub value_len: Option<usize>,
+    /// How many bytes of the value have been received so far.
+    pub value_received: usize,
+}
+
+/// The error returned by [`Decoder::restore`](struct.Decoder.html#method.restore) for a
+/// `DecoderState` that no decoder can be in, e.g. because it was corrupted while persisted.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RestoreError {
+    /// The number of buffered bytes is not `value_received`, or there are buffered bytes
+    /// without a value length.
+    BufferedLength,
+    /// The header bytes are not the beginning of a varu64 that needs more bytes, or follow a
+    /// complete header.
+    Header,
+    /// The state has a value length but no type, or a type that implies a length but no value
+    /// length.
+    MissingField,
+    /// The value length differs from the length implied by the type, exceeds the maximum of
+    /// the configuration, or has been received completely already.
+    ValueLength,
+}
+
+impl fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        match self {
+            RestoreError::BufferedLength => {
+                write!(f, "Invalid decoder state: wrong number of buffered bytes")
+            }
+            RestoreError::Header => write!(f, "Invalid decoder state: invalid header bytes"),
+            RestoreError::MissingField => {
+                write!(f, "Invalid decoder state: missing type or value length")
+            }
+            RestoreError::ValueLength => write!(f, "Invalid decoder state: invalid value length"),
+        }
+    }
+}
+
+impl error::Error for RestoreError {}
+
+/// A decoder of ctlv sequences that is fed the input piece by piece, without doing any I/O
+/// itself.
+///
+/// The input can be split arbitrarily: [`push`](#method.push) accepts anything from a single
+/// byte to many ctlvs at once, and returns as soon as a ctlv is complete. Only the value of the
+/// current ctlv is buffered, and its length is checked against the `max_value_len` of the
+/// [`DecodeConfig`](struct.DecodeConfig.html) before any of it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoder {
+    config: DecodeConfig,
+    state: State,
+    // The bytes of the varu64 that is being decoded.
+    varu64: Vec<u8>,
+    error: Option<DecodeError>,
+}
+
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Decoder::new()
+    }
+}
+
+impl Decoder {
+    /// Creates a `Decoder` with the default `DecodeConfig`.
+    pub fn new() -> Decoder {
+        Decoder::with_config(DecodeConfig::default())
+    }
+
+    /// Creates a `Decoder` that decodes with the given configuration.
+    pub fn with_config(config: DecodeConfig) -> Decoder {
+        Decoder {
+            config,
+            state: State::Type,
+            varu64: Vec::with_capacity(9),
+            error: None,
+        }
+    }
+
+    /// Feeds bytes into the decoder, returning how many of them were consumed, and the ctlv
+    /// they completed, if any.
+    ///
+    /// At most one ctlv is returned per call, so the bytes following it are not consumed and
+    /// need to be pushed again. An error is returned as soon as it is detected, the bytes
+    /// consumed include the one that caused it. After an error, the decoder consumes nothing and
+    /// returns the same error until it is [`reset`](#method.reset). Since incomplete input
+    /// just makes the decoder wait for more, it never returns
+    /// `DecodeError::UnexpectedEndOfInput`.
+    pub fn push(&mut self, bytes: &[u8]) -> (usize, Option<Result<Ctlv, DecodeError>>) {
+        if let Some(e) = self.error {
+            return (0, Some(Err(e)));
+        }
+
+        let mut consumed = 0;
+        while consumed < bytes.len() {
+            let result = if let State::Value { type_, len, value } = &mut self.state {
+                let take = (*len - value.len()).min(bytes.len() - consumed);
+                value.extend_from_slice(&bytes[consumed..consumed + take]);
+                consumed += take;
+                if value.len() < *len {
+                    continue;
+                }
+                let ctlv = Ctlv::new(*type_, mem::take(value));
+                self.state = State::Type;
+                Ok(ctlv)
+            } else {
+                consumed += 1;
+                match self.header_byte(bytes[consumed - 1]) {
+                    None => continue,
+                    Some(result) => result,
+                }
+            };
+
+            if let Err(e) = result {
+                self.error = Some(e);
+            }
+            return (consumed, Some(result));
+        }
+        (consumed, None)
+    }
+
+    // Processes a byte of the type or length, returning the ctlv if it has an empty value.
+    fn header_byte(&mut self, byte: u8) -> Option<Result<Ctlv, DecodeError>> {
+        self.varu64.push(byte);
+        let first = self.varu64[0];
+        if first >= 248 && self.varu64.len() < (first - 246) as usize {
+            return None;
+        }
+
+        let decoded = match varu64::decode(&self.varu64) {
+            Ok((n, _)) => Ok(n),
+            Err((VarU64Error::NonCanonical(n), _)) if !self.config.require_canonical => Ok(n),
+            Err((e, _)) => Err(e),
+        };
+        self.varu64.clear();
+
+        let (type_, len) = match (&self.state, decoded) {
+            (State::Type, Err(e)) => return Some(Err(Type(e))),
+            (State::Type, Ok(type_)) if type_ >= EXPLICIT_LENGTH_MIN_TYPE => {
+                self.state = State::Length { type_ };
+                return None;
+            }
+            (State::Type, Ok(type_)) => (type_, type_implied_length(type_) as u64),
+            (_, Err(e)) => return Some(Err(Length(e))),
+            (State::Length { type_ }, Ok(len)) => (*type_, len),
+            (State::Value { .. }, Ok(_)) => unreachable!(),
+        };
+
+        let max = self.config.max_value_len.unwrap_or(usize::MAX);
+        let len = match len.try_into() {
+            Ok(len) if len <= max => len,
+            _ => return Some(Err(LengthTooLarge { declared: len, max })),
+        };
+        if len == 0 {
+            self.state = State::Type;
+            return Some(Ok(Ctlv::new(type_, Vec::new())));
+        }
+        self.state = State::Value {
+            type_,
+            len,
+            value: Vec::new(),
+        };
+        None
+    }
+
+    /// Returns how many more bytes the current ctlv needs, if that is known already, i.e. if
+    /// its header is complete.
+    pub fn pending_bytes_needed(&self) -> Option<usize> {
+        match &self.state {
+            State::Value { len, value, .. } if self.error.is_none() => Some(len - value.len()),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the decoder has consumed part of a ctlv, i.e. whether the input would
+    /// be incomplete if it ended now.
+    pub fn is_mid_frame(&self) -> bool {
+        self.state != State::Type || !self.varu64.is_empty()
+    }
+
+    /// Returns the state of the decoder, so that it can be persisted and later
+    /// [restored](#method.restore) together with the [buffered value](#method.buffered_value).
+    ///
+    /// Errors are not part of the state: a decoder that has returned an error is checkpointed
+    /// as if it had been [reset](#method.reset).
+    pub fn checkpoint(&self) -> DecoderState {
+        let mut state = DecoderState {
+            config: self.config,
+            header: Vec::new(),
+            type_: None,
+            value_len: None,
+            value_received: 0,
+        };
+        if self.error.is_none() {
+            state.header = self.varu64.clone();
+            match &self.state {
+                State::Type => {}
+                State::Length { type_ } => state.type_ = Some(*type_),
+                State::Value { type_, len, value } => {
+                    state.type_ = Some(*type_);
+                    state.value_len = Some(*len);
+                    state.value_received = value.len();
+                }
+            }
+        }
+        state
+    }
+
+    /// Returns the bytes of the value of the current ctlv that have been received so far.
+    pub fn buffered_value(&self) -> &[u8] {
+        match &self.state {
+            State::Value { value, .. } if self.error.is_none() => value,
+            _ => &[],
+        }
+    }
+
+    /// Creates a decoder from a [checkpoint](#method.checkpoint) and the value bytes that were
+    /// buffered at the time. Pushing the remaining input into it yields the same ctlvs as
+    /// pushing it into the checkpointed decoder.
+    ///
+    /// Since the state may have been persisted, it is validated first: this errors if no
+    /// decoder can be in the state, e.g. if `buffered` is not `value_received` bytes long, or if
+    /// the state has both header bytes and a value length.
+    pub fn restore(state: DecoderState, buffered: Vec<u8>) -> Result<Decoder, RestoreError> {
+        if buffered.len() != state.value_received {
+            return Err(RestoreError::BufferedLength);
+        }
+        if let Some(first) = state.header.first() {
+            // A varu64 of more than one byte starts with 247 plus the number of bytes after the
+            // first one; shorter prefixes would have been decoded already.
+            if *first < 248 || state.header.len() >= (*first - 246) as usize {
+                return Err(RestoreError::Header);
+            }
+        }
+
+        let decoder_state = match (state.type_, state.value_len) {
+            (Some(type_), Some(len)) => {
+                if !state.header.is_empty() {
+                    return Err(RestoreError::Header);
+                }
+                let implied = type_implied_length(type_);
+                let max = state.config.max_value_len.unwrap_or(usize::MAX);
+                if (implied != 0 && len != implied) || len > max || buffered.len() >= len {
+                    return Err(RestoreError::ValueLength);
+                }
+                State::Value {
+                    type_,
+                    len,
+                    value: buffered,
+                }
+            }
+            (None, Some(_)) => return Err(RestoreError::MissingField),
+            (_, None) if !buffered.is_empty() => return Err(RestoreError::BufferedLength),
+            (None, None) => State::Type,
+            (Some(type_), None) if type_ < EXPLICIT_LENGTH_MIN_TYPE => {
+                return Err(RestoreError::MissingField)
+            }
+            (Some(type_), None) => State::Length { type_ },
+        };
+
+        Ok(Decoder {
+               config: state.config,
+               state: decoder_state,
+               varu64: state.header,
+               error: None,
+           })
+    }
+
+    /// Discards any partially decoded ctlv and any error, so that the decoder expects the start
+    /// of a new ctlv.
+    pub fn reset(&mut self) {
+        self.state = State::Type;
+        self.varu64.clear();
+        self.error = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::decode_all;
+
+    fn fixture() -> Vec<u8> {
+        [Ctlv::new(0, vec![1]),
+         Ctlv::new(128, vec![]),
+         Ctlv::new(300, vec![2; 300]),
+         Ctlv::new(16, vec![3, 4, 5, 6]),
+         Ctlv::new(u64::MAX, vec![7; 3]),
+         Ctlv::new(129, vec![8])]
+            .iter()
+            .map(Ctlv::encode_vec)
+            .collect::<Vec<_>>()
+            .concat()
+    }
+
+    // Pushes all pieces, collecting the decoded ctlvs.
+    fn push_all(decoder: &mut Decoder, pieces: &[&[u8]]) -> Vec<Result<Ctlv, DecodeError>> {
+        let mut out = Vec::new();
+        for piece in pieces {
+            let mut piece = *piece;
+            loop {
+                let (consumed, result) = decoder.push(piece);
+                piece = &piece[consumed..];
+                match result {
+                    Some(result) => {
+                        let failed = result.is_err();
+                        out.push(result);
+                        if failed {
+                            return out;
+                        }
+                    }
+                    None => break,
+                }
+            }
+            assert!(piece.is_empty());
+        }
+        out
+    }
+
+    #[test]
+    fn every_split() {
+        let input = fixture();
+        let expected: Vec<_> = decode_all(&input).unwrap().into_iter().map(Ok).collect();
+
+        for i in 0..=input.len() {
+            for j in i..=input.len() {
+                let mut decoder = Decoder::new();
+                let pieces = [&input[..i], &input[i..j], &input[j..]];
+                assert_eq!(push_all(&mut decoder, &pieces), expected);
+                assert!(!decoder.is_mid_frame());
+            }
+        }
+
+        let mut decoder = Decoder::new();
+        let bytes: Vec<_> = input.chunks(1).collect();
+        assert_eq!(push_all(&mut decoder, &bytes), expected);
+    }
+
+    #[test]
+    fn pending() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.pending_bytes_needed(), None);
+        assert_eq!(decoder.push(&[249, 1]), (2, None));
+        assert_eq!(decoder.pending_bytes_needed(), None);
+        assert!(decoder.is_mid_frame());
+        assert_eq!(decoder.push(&[44, 3]), (2, None));
+        assert_eq!(decoder.pending_bytes_needed(), Some(3));
+        assert_eq!(decoder.push(&[1, 2]), (2, None));
+        assert_eq!(decoder.pending_bytes_needed(), Some(1));
+        assert_eq!(decoder.push(&[3, 0]),
+                   (1, Some(Ok(Ctlv::new(300, vec![1, 2, 3])))));
+        assert_eq!(decoder.pending_bytes_needed(), None);
+        assert_eq!(decoder.push(&[16]), (1, None));
+        assert_eq!(decoder.pending_bytes_needed(), Some(4));
+    }
+
+    #[test]
+    fn errors() {
+        let config = DecodeConfig {
+            max_value_len: Some(10),
+            ..DecodeConfig::default()
+        };
+        let mut decoder = Decoder::with_config(config);
+        // The length is rejected before any of the value is consumed.
+        assert_eq!(decoder.push(&[0, 1, 128, 11, 0, 0]),
+                   (2, Some(Ok(Ctlv::new(0, vec![1])))));
+        assert_eq!(decoder.push(&[128, 11, 0, 0]),
+                   (2, Some(Err(LengthTooLarge { declared: 11, max: 10 }))));
+        assert_eq!(decoder.push(&[0, 0]),
+                   (0, Some(Err(LengthTooLarge { declared: 11, max: 10 }))));
+        assert_eq!(decoder.pending_bytes_needed(), None);
+
+        decoder.reset();
+        assert!(!decoder.is_mid_frame());
+        assert_eq!(decoder.push(&[8, 1, 2]), (3, Some(Ok(Ctlv::new(8, vec![1, 2])))));
+
+        assert_eq!(decoder.push(&[248, 5, 0]),
+                   (2, Some(Err(Type(VarU64Error::NonCanonical(5))))));
+        decoder.reset();
+        assert_eq!(decoder.push(&[128, 249, 0, 1]),
+                   (4, Some(Err(Length(VarU64Error::NonCanonical(1))))));
+
+        let mut lenient = Decoder::with_config(DecodeConfig {
+                                                   require_canonical: false,
+                                                   ..DecodeConfig::default()
+                                               });
+        assert_eq!(lenient.push(&[248, 5, 0]), (3, Some(Ok(Ctlv::new(5, vec![0])))));
+    }
+
+    #[test]
+    fn checkpoint() {
+        let input = fixture();
+        let expected: Vec<_> = decode_all(&input).unwrap().into_iter().map(Ok).collect();
+
+        for i in 0..=input.len() {
+            let mut decoder = Decoder::new();
+            let mut out = push_all(&mut decoder, &[&input[..i]]);
+            let state = decoder.checkpoint();
+            let buffered = decoder.buffered_value().to_vec();
+            let mut restored = Decoder::restore(state.clone(), buffered).unwrap();
+            assert_eq!(restored, decoder);
+            assert_eq!(restored.checkpoint(), state);
+
+            out.extend(push_all(&mut restored, &[&input[i..]]));
+            assert_eq!(out, expected);
+        }
+
+        let mut decoder = Decoder::new();
+        // Within the type 300, then within its length 300.
+        push_all(&mut decoder, &[&[0, 1, 128, 0, 249, 1]]);
+        assert_eq!(decoder.checkpoint(),
+                   DecoderState {
+                       config: DecodeConfig::default(),
+                       header: vec![249, 1],
+                       type_: None,
+                       value_len: None,
+                       value_received: 0,
+                   });
+        push_all(&mut decoder, &[&[44, 249]]);
+        assert_eq!(decoder.checkpoint().header, [249]);
+        assert_eq!(decoder.checkpoint().type_, Some(300));
+        // Within the value.
+        push_all(&mut decoder, &[&[1, 44, 2, 2]]);
+        assert_eq!(decoder.checkpoint().value_len, Some(300));
+        assert_eq!(decoder.checkpoint().value_received, 2);
+        assert_eq!(decoder.buffered_value(), [2, 2]);
+
+        // Errors are not checkpointed.
+        let mut failed = Decoder::new();
+        failed.push(&[248, 5]);
+        assert_eq!(failed.checkpoint(), Decoder::new().checkpoint());
+    }
+
+    #[test]
+    fn restore_invalid() {
+        let valid = DecoderState {
+            config: DecodeConfig {
+                max_value_len: Some(100),
+                ..DecodeConfig::default()
+            },
+            header: Vec::new(),
+            type_: Some(300),
+            value_len: Some(10),
+            value_received: 2,
+        };
+        assert!(Decoder::restore(valid.clone(), vec![1, 2]).is_ok());
+
+        let restore = |state: DecoderState, buffered: &[u8]| {
+            Decoder::restore(state, buffered.to_vec()).unwrap_err()
+        };
+        assert_eq!(restore(valid.clone(), &[1]), RestoreError::BufferedLength);
+        let no_len = DecoderState {
+            value_len: None,
+            ..valid.clone()
+        };
+        assert_eq!(restore(no_len, &[1, 2]), RestoreError::BufferedLength);
+
+        for header in &[vec![1], vec![249, 1, 44], vec![255; 9], vec![249, 1]] {
+            let state = DecoderState {
+                header: header.clone(),
+                type_: None,
+                value_len: None,
+                value_received: 0,
+                ..valid.clone()
+            };
+            assert_eq!(Decoder::restore(state, Vec::new()).is_ok(), header == &[249, 1]);
+        }
+        let header_after_len = DecoderState {
+            header: vec![249],
+            ..valid.clone()
+        };
+        assert_eq!(restore(header_after_len, &[1, 2]), RestoreError::Header);
+
+        let len_without_type = DecoderState {
+            type_: None,
+            ..valid.clone()
+        };
+        assert_eq!(restore(len_without_type, &[1, 2]), RestoreError::MissingField);
+        let implied_without_len = DecoderState {
+            type_: Some(8),
+            value_len: None,
+            value_received: 0,
+            ..valid.clone()
+        };
+        assert_eq!(restore(implied_without_len, &[]), RestoreError::MissingField);
+
+        for (type_, len) in &[(8, 10), (300, 101), (300, 2), (300, 0)] {
+            let state = DecoderState {
+                type_: Some(*type_),
+                value_len: Some(*len),
+                ..valid.clone()
+            };
+            assert_eq!(restore(state, &[1, 2]), RestoreError::ValueLength);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serializable() {
+        let input = fixture();
+        let expected: Vec<_> = decode_all(&input).unwrap().into_iter().map(Ok).collect();
+
+        // Stop within the value of the ctlv of type 300, and resume from the serialized state.
+        let mut decoder = Decoder::new();
+        let mut out = push_all(&mut decoder, &[&input[..10]]);
+        assert!(decoder.pending_bytes_needed().is_some());
+        let mut serialized = Vec::new();
+        ciborium::into_writer(&decoder.checkpoint(), &mut serialized).unwrap();
+        let buffered = decoder.buffered_value().to_vec();
+        drop(decoder);
+
+        let state: DecoderState = ciborium::from_reader(&serialized[..]).unwrap();
+        assert_eq!(state.value_received, buffered.len());
+        let mut restored = Decoder::restore(state, buffered).unwrap();
+        out.extend(push_all(&mut restored, &[&input[10..]]));
+        assert_eq!(out, expected);
+    }
+}
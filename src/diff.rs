@@ -0,0 +1,342 @@
+use std::collections::{HashMap, VecDeque};
+use std::{error, fmt};
+
+use super::{Ctlv, CtlvIter, CtlvRef, DecodeError};
+
+/// The position of a ctlv inside an encoded sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FrameLocation {
+    /// The index of the ctlv within the sequence.
+    pub index: usize,
+    /// The offset of the first byte of the ctlv's encoding within the sequence.
+    pub offset: usize,
+    /// The length of the ctlv's encoding.
+    pub len: usize,
+}
+
+impl FrameLocation {
+    /// Returns the encoding of the located ctlv, given the sequence it belongs to.
+    ///
+    /// # Panics
+    /// Panics if the location lies outside of the input.
+    pub fn bytes<'a>(&self, input: &'a [u8]) -> &'a [u8] {
+        &input[self.offset..self.offset + self.len]
+    }
+}
+
+/// The structural difference between two encoded sequences of ctlvs, as computed by
+/// [`diff`](fn.diff.html).
+///
+/// Two ctlvs are considered equal if their encodings are byte-wise equal. Sequences are
+/// treated as multisets: a ctlv that occurs twice in `old` but only once in `new` is reported
+/// as common once and as only in `old` once.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SeqDiff {
+    /// The ctlvs that only occur in the old sequence, ordered by their index.
+    pub only_old: Vec<FrameLocation>,
+    /// The ctlvs that only occur in the new sequence, ordered by their index.
+    pub only_new: Vec<FrameLocation>,
+    /// The ctlvs that occur in both sequences, as pairs of their location in the old and the
+    /// new sequence, ordered by their index in the new sequence.
+    pub common: Vec<(FrameLocation, FrameLocation)>,
+}
+
+impl SeqDiff {
+    /// Returns whether both sequences contained the same ctlvs.
+    pub fn is_empty(&self) -> bool {
+        self.only_old.is_empty() && self.only_new.is_empty()
+    }
+
+    /// Returns the concatenated encodings of all ctlvs that only occur in the new sequence,
+    /// in the order in which they occur there.
+    ///
+    /// Together with the diff itself, this is all that [`apply`](fn.apply.html) needs to
+    /// reconstruct the new sequence from the old one.
+    ///
+    /// # Panics
+    /// Panics if `new` is not the sequence this diff was computed against.
+    pub fn added_bytes(&self, new: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.only_new.iter().map(|loc| loc.len).sum());
+        for loc in &self.only_new {
+            out.extend_from_slice(loc.bytes(new));
+        }
+        out
+    }
+}
+
+/// Everything that can go wrong when computing a [`diff`](fn.diff.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DiffError {
+    /// The old sequence contained invalid data at the given offset.
+    Old(DecodeError, usize),
+    /// The new sequence contained invalid data at the given offset.
+    New(DecodeError, usize),
+}
+
+impl fmt::Display for DiffError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        match self {
+            DiffError::Old(e, offset) => write!(f, "Invalid old sequence at offset {}: {}", offset, e),
+            DiffError::New(e, offset) => write!(f, "Invalid new sequence at offset {}: {}", offset, e),
+        }
+    }
+}
+
+impl error::Error for DiffError {}
+
+// Decode the locations of all ctlvs in the input, and whether they are sorted by their encoding.
+fn locate(input: &[u8]) -> Result<(Vec<FrameLocation>, bool), (DecodeError, usize)> {
+    let mut locations: Vec<FrameLocation> = Vec::new();
+    let mut sorted = true;
+    let mut iter = CtlvIter::new(input);
+
+    loop {
+        let offset = iter.offset();
+        match iter.next() {
+            None => return Ok((locations, sorted)),
+            Some(Err(e)) => return Err(e),
+            Some(Ok(_)) => {
+                let loc = FrameLocation {
+                    index: locations.len(),
+                    offset,
+                    len: iter.offset() - offset,
+                };
+
+                if let Some(prev) = locations.last() {
+                    sorted = sorted && prev.bytes(input) <= loc.bytes(input);
+                }
+                locations.push(loc);
+            }
+        }
+    }
+}
+
+/// Compute which ctlvs only occur in `old`, which only occur in `new`, and which occur in both.
+///
+/// If both sequences are sorted by their encodings (as canonical sequences are), this runs in
+/// a single merging pass. Otherwise, the ctlvs of `old` are indexed in a hash map first.
+pub fn diff(old: &[u8], new: &[u8]) -> Result<SeqDiff, DiffError> {
+    let (old_locs, old_sorted) = locate(old).map_err(|(e, offset)| DiffError::Old(e, offset))?;
+    let (new_locs, new_sorted) = locate(new).map_err(|(e, offset)| DiffError::New(e, offset))?;
+    let mut d = SeqDiff::default();
+
+    if old_sorted && new_sorted {
+        let (mut i, mut j) = (0, 0);
+        while i < old_locs.len() && j < new_locs.len() {
+            let (o, n) = (old_locs[i], new_locs[j]);
+            match o.bytes(old).cmp(n.bytes(new)) {
+                std::cmp::Ordering::Less => {
+                    d.only_old.push(o);
+                    i += 1;
+                }
+                std::cmp::Ordering::Greater => {
+                    d.only_new.push(n);
+                    j += 1;
+                }
+                std::cmp::Ordering::Equal => {
+                    d.common.push((o, n));
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        d.only_old.extend_from_slice(&old_locs[i..]);
+        d.only_new.extend_from_slice(&new_locs[j..]);
+    } else {
+        let mut by_bytes: HashMap<&[u8], VecDeque<FrameLocation>> = HashMap::new();
+        for loc in &old_locs {
+            by_bytes.entry(loc.bytes(old)).or_default().push_back(*loc);
+        }
+
+        for n in &new_locs {
+            match by_bytes.get_mut(n.bytes(new)).and_then(VecDeque::pop_front) {
+                Some(o) => d.common.push((o, *n)),
+                None => d.only_new.push(*n),
+            }
+        }
+
+        d.only_old = by_bytes.into_values().flatten().collect();
+        d.only_old.sort();
+    }
+
+    Ok(d)
+}
+
+/// Reconstruct the new sequence of a [`diff`](fn.diff.html) from the old sequence, the diff,
+/// and the encodings of the ctlvs that only occur in the new sequence (as returned by
+/// [`SeqDiff::added_bytes`](struct.SeqDiff.html#method.added_bytes)).
+///
+/// Returns `None` if the diff does not fit `old` and `added`.
+pub fn apply(old: &[u8], diff: &SeqDiff, added: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(old.len() + added.len());
+    let mut common = diff.common.iter().peekable();
+    let mut only_new = diff.only_new.iter().peekable();
+    let mut added_offset: usize = 0;
+
+    for index in 0..diff.common.len() + diff.only_new.len() {
+        match (common.peek(), only_new.peek()) {
+            (Some((o, n)), _) if n.index == index => {
+                out.extend_from_slice(old.get(o.offset..o.offset.checked_add(o.len)?)?);
+                common.next();
+            }
+            (_, Some(n)) if n.index == index => {
+                let end = added_offset.checked_add(n.len)?;
+                out.extend_from_slice(added.get(added_offset..end)?);
+                added_offset = end;
+                only_new.next();
+            }
+            _ => return None,
+        }
+    }
+
+    if added_offset == added.len() {
+        Some(out)
+    } else {
+        None
+    }
+}
+
+/// A difference between two sequences of ctlvs at a single position, as computed by
+/// [`diff_positional`](fn.diff_positional.html).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CtlvDiff {
+    /// The new sequence is longer than the old one, and contains this ctlv past the end of the
+    /// old sequence.
+    Added(Ctlv),
+    /// The old sequence is longer than the new one, and contains this ctlv past the end of the
+    /// new sequence.
+    Removed(Ctlv),
+    /// The ctlvs at the given index differ.
+    Changed {
+        /// The index of the ctlvs within both sequences.
+        index: usize,
+        /// The ctlv in the old sequence.
+        old: Ctlv,
+        /// The ctlv in the new sequence.
+        new: Ctlv,
+    },
+}
+
+/// Compare two encoded sequences of ctlvs position by position, for showing a human which
+/// ctlvs differ between two versions of a record.
+///
+/// The ctlvs at the same index are compared with each other, yielding a `Changed` for every
+/// index at which they differ. The tail of the longer sequence then yields an `Added` or a
+/// `Removed` per ctlv. The differences are ordered by index.
+///
+/// This does not try to find a minimal diff: inserting a single ctlv at the start of a sequence
+/// changes all positions after it. Use [`diff`](fn.diff.html) to compare sequences as
+/// multisets instead.
+///
+/// If `old` is invalid, returns the error and the offset at which it occurred, otherwise the
+/// same for `new`.
+pub fn diff_positional(old: &[u8], new: &[u8]) -> Result<Vec<CtlvDiff>, (DecodeError, usize)> {
+    let old: Vec<CtlvRef> = CtlvIter::new(old).collect::<Result<_, _>>()?;
+    let new: Vec<CtlvRef> = CtlvIter::new(new).collect::<Result<_, _>>()?;
+
+    let mut diffs = Vec::new();
+    for (index, (o, n)) in old.iter().zip(new.iter()).enumerate() {
+        if o != n {
+            diffs.push(CtlvDiff::Changed {
+                           index,
+                           old: o.to_owned(),
+                           new: n.to_owned(),
+                       });
+        }
+    }
+    diffs.extend(old.iter().skip(new.len()).map(|o| CtlvDiff::Removed(o.to_owned())));
+    diffs.extend(new.iter().skip(old.len()).map(|n| CtlvDiff::Added(n.to_owned())));
+
+    Ok(diffs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_apply(old: &[u8], new: &[u8], d: &SeqDiff) {
+        assert_eq!(apply(old, d, &d.added_bytes(new)).unwrap(), new);
+    }
+
+    #[test]
+    fn identical() {
+        let seq = [0, 1, 8, 2, 3, 128, 1, 4];
+        let d = diff(&seq, &seq).unwrap();
+
+        assert!(d.is_empty());
+        assert_eq!(d.common.len(), 3);
+        assert_eq!(d.common[1],
+                   (FrameLocation { index: 1, offset: 2, len: 3 },
+                    FrameLocation { index: 1, offset: 2, len: 3 }));
+        check_apply(&seq, &seq, &d);
+    }
+
+    #[test]
+    fn disjoint() {
+        let old = [0, 1, 128, 1, 2];
+        let new = [1, 3, 129, 0];
+        let d = diff(&old, &new).unwrap();
+
+        assert_eq!(d.only_old.len(), 2);
+        assert_eq!(d.only_new.len(), 2);
+        assert!(d.common.is_empty());
+        check_apply(&old, &new, &d);
+    }
+
+    #[test]
+    fn single_change() {
+        // Sorted inputs take the merging path.
+        let old = [0, 1, 1, 2, 128, 1, 4];
+        let new = [0, 1, 1, 3, 128, 1, 4];
+        let d = diff(&old, &new).unwrap();
+
+        assert_eq!(d.only_old, vec![FrameLocation { index: 1, offset: 2, len: 2 }]);
+        assert_eq!(d.only_new, vec![FrameLocation { index: 1, offset: 2, len: 2 }]);
+        assert_eq!(d.common.len(), 2);
+        check_apply(&old, &new, &d);
+
+        // Unsorted inputs take the hashing path.
+        let old = [128, 1, 4, 1, 2, 0, 1];
+        let new = [128, 1, 4, 1, 3, 0, 1];
+        let d = diff(&old, &new).unwrap();
+
+        assert_eq!(d.only_old, vec![FrameLocation { index: 1, offset: 3, len: 2 }]);
+        assert_eq!(d.only_new, vec![FrameLocation { index: 1, offset: 3, len: 2 }]);
+        assert_eq!(d.common.len(), 2);
+        check_apply(&old, &new, &d);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(diff(&[0, 1, 128, 3, 0], &[]).unwrap_err(),
+                   DiffError::Old(DecodeError::UnexpectedEndOfInput, 2));
+        assert_eq!(diff(&[], &[128, 2, 0]).unwrap_err(),
+                   DiffError::New(DecodeError::UnexpectedEndOfInput, 0));
+        assert_eq!(apply(&[], &SeqDiff::default(), &[0]), None);
+
+        assert_eq!(diff_positional(&[128, 1], &[0]).unwrap_err(),
+                   (DecodeError::UnexpectedEndOfInput, 0));
+        assert_eq!(diff_positional(&[0, 1], &[0, 1, 8, 1]).unwrap_err(),
+                   (DecodeError::UnexpectedEndOfInput, 2));
+    }
+
+    #[test]
+    fn positional() {
+        let old = [0, 1, 1, 2, 128, 1, 4];
+        assert_eq!(diff_positional(&old, &old).unwrap(), vec![]);
+
+        let new = [0, 1, 1, 3, 128, 1, 4, 0, 5, 0, 6];
+        assert_eq!(diff_positional(&old, &new).unwrap(),
+                   vec![CtlvDiff::Changed {
+                            index: 1,
+                            old: Ctlv::new(1, vec![2]),
+                            new: Ctlv::new(1, vec![3]),
+                        },
+                        CtlvDiff::Added(Ctlv::new(0, vec![5])),
+                        CtlvDiff::Added(Ctlv::new(0, vec![6]))]);
+
+        assert_eq!(diff_positional(&old, &[0, 1]).unwrap(),
+                   vec![CtlvDiff::Removed(Ctlv::new(1, vec![2])),
+                        CtlvDiff::Removed(Ctlv::new(128, vec![4]))]);
+    }
+}
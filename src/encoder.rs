@@ -0,0 +1,253 @@
+use std::{error, fmt};
+
+use super::{check_implied_length, encode_header, CtlvRef, MAX_HEADER_LENGTH};
+
+/// Everything that can go wrong when using an [`Encoder`](struct.Encoder.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EncoderError {
+    /// A frame was started before the previous one was finished.
+    FrameInProgress,
+    /// Value bytes were pushed, or a frame was finished, without starting a frame first.
+    NoFrame,
+    /// The type implies a different length than the declared one.
+    ImpliedLength {
+        /// The type of the frame.
+        type_: u64,
+        /// The length the type implies.
+        implied: usize,
+        /// The declared length.
+        declared: usize,
+    },
+    /// More value bytes were pushed than declared, or fewer when finishing the frame.
+    LengthMismatch {
+        /// The declared length of the value.
+        declared: usize,
+        /// How many bytes were (or would have been) pushed.
+        pushed: usize,
+    },
+}
+
+impl fmt::Display for EncoderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        match self {
+            EncoderError::FrameInProgress => write!(f, "The previous frame is not finished"),
+            EncoderError::NoFrame => write!(f, "No frame has been started"),
+            EncoderError::ImpliedLength { type_, implied, declared } => {
+                write!(f,
+                       "Type {} implies a length of {}, but {} was declared",
+                       type_,
+                       implied,
+                       declared)
+            }
+            EncoderError::LengthMismatch { declared, pushed } => {
+                write!(f, "Declared a value of {} bytes, but got {}", declared, pushed)
+            }
+        }
+    }
+}
+
+impl error::Error for EncoderError {}
+
+/// An encoder that produces the encoding of a sequence of ctlvs in chunks of any size, without
+/// doing any I/O itself.
+///
+/// A frame is started with [`start_frame`](#method.start_frame), which declares its type and
+/// the length of its value, then the value is pushed in any number of pieces with
+/// [`push_value`](#method.push_value), and the frame is completed with
+/// [`finish`](#method.finish). Meanwhile, [`next_chunk`](#method.next_chunk) moves the encoded
+/// bytes into output buffers of arbitrary sizes. Only one frame can be in progress, so the bytes
+/// of different frames are never interleaved.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Encoder {
+    // The encoded bytes that have not been taken out yet, starting at `pos`.
+    out: Vec<u8>,
+    pos: usize,
+    // The declared and the pushed length of the value of the current frame.
+    frame: Option<(usize, usize)>,
+}
+
+impl Encoder {
+    /// Creates an `Encoder` without any frame in progress.
+    pub fn new() -> Encoder {
+        Encoder::default()
+    }
+
+    /// Starts a frame with the given type and length of value, queueing its header for output.
+    ///
+    /// Errors if the previous frame has not been finished, or if the type implies a different
+    /// length.
+    pub fn start_frame(&mut self, type_: u64, value_len: usize) -> Result<(), EncoderError> {
+        if self.frame.is_some() {
+            return Err(EncoderError::FrameInProgress);
+        }
+        check_implied_length(type_, value_len).map_err(|e| {
+            EncoderError::ImpliedLength {
+                type_,
+                implied: e.implied,
+                declared: value_len,
+            }
+        })?;
+
+        let mut header = [0; MAX_HEADER_LENGTH];
+        let header_len = encode_header(type_, value_len as u64, &mut header);
+        self.out.extend_from_slice(&header[..header_len]);
+        self.frame = Some((value_len, 0));
+        Ok(())
+    }
+
+    /// Queues bytes of the value of the current frame for output.
+    ///
+    /// Errors, without queueing anything, if no frame has been started, or if this would
+    /// exceed the declared length of the value.
+    pub fn push_value(&mut self, bytes: &[u8]) -> Result<(), EncoderError> {
+        match &mut self.frame {
+            None => Err(EncoderError::NoFrame),
+            Some((declared, pushed)) => {
+                if *pushed + bytes.len() > *declared {
+                    return Err(EncoderError::LengthMismatch {
+                                   declared: *declared,
+                                   pushed: *pushed + bytes.len(),
+                               });
+                }
+                *pushed += bytes.len();
+                self.out.extend_from_slice(bytes);
+                Ok(())
+            }
+        }
+    }
+
+    /// Finishes the current frame, so that the next one can be started.
+    ///
+    /// Errors if no frame has been started, or if fewer value bytes than declared have been
+    /// pushed; in the latter case the frame stays in progress.
+    pub fn finish(&mut self) -> Result<(), EncoderError> {
+        match self.frame {
+            None => Err(EncoderError::NoFrame),
+            Some((declared, pushed)) if pushed != declared => {
+                Err(EncoderError::LengthMismatch { declared, pushed })
+            }
+            Some(_) => {
+                self.frame = None;
+                Ok(())
+            }
+        }
+    }
+
+    /// Starts a frame, pushes its value and finishes it in one go.
+    pub fn push_frame(&mut self, frame: CtlvRef) -> Result<(), EncoderError> {
+        self.start_frame(frame.type_, frame.value.len())?;
+        self.push_value(frame.value)?;
+        self.finish()
+    }
+
+    /// Moves queued bytes into `out`, returning how many were moved. This is less than the
+    /// length of `out` only if no more bytes are queued.
+    pub fn next_chunk(&mut self, out: &mut [u8]) -> usize {
+        let len = out.len().min(self.out.len() - self.pos);
+        out[..len].copy_from_slice(&self.out[self.pos..self.pos + len]);
+        self.pos += len;
+
+        if self.pos == self.out.len() {
+            self.out.clear();
+            self.pos = 0;
+        } else if self.pos >= 4096 && 2 * self.pos >= self.out.len() {
+            self.out.drain(..self.pos);
+            self.pos = 0;
+        }
+        len
+    }
+
+    /// Returns how many bytes are queued for output.
+    pub fn pending_output(&self) -> usize {
+        self.out.len() - self.pos
+    }
+
+    /// Returns whether a frame has been started but not finished.
+    pub fn is_mid_frame(&self) -> bool {
+        self.frame.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Ctlv;
+
+    fn drain(encoder: &mut Encoder, out: &mut Vec<u8>) {
+        let mut window = [0; 7];
+        loop {
+            let len = encoder.next_chunk(&mut window);
+            out.extend_from_slice(&window[..len]);
+            if len < window.len() {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn seven_byte_windows() {
+        let frames = [Ctlv::new(0, vec![1]),
+                      Ctlv::new(300, (0..=255).collect()),
+                      Ctlv::new(128, vec![]),
+                      Ctlv::new(16, vec![2, 3, 4, 5]),
+                      Ctlv::new(u64::MAX, vec![6; 10000])];
+        let expected: Vec<_> = frames.iter().map(Ctlv::encode_vec).collect();
+        let expected = expected.concat();
+
+        let mut encoder = Encoder::new();
+        let mut out = Vec::new();
+        for frame in &frames {
+            encoder.start_frame(frame.type_, frame.value().len()).unwrap();
+            drain(&mut encoder, &mut out);
+            for piece in frame.value().chunks(13) {
+                encoder.push_value(piece).unwrap();
+                if piece[0] % 2 == 0 {
+                    drain(&mut encoder, &mut out);
+                }
+            }
+            encoder.finish().unwrap();
+        }
+        drain(&mut encoder, &mut out);
+        assert_eq!(out, expected);
+        assert_eq!(encoder.pending_output(), 0);
+
+        let mut encoder = Encoder::new();
+        for frame in &frames {
+            encoder.push_frame(frame.as_ctlv_ref()).unwrap();
+        }
+        assert_eq!(encoder.pending_output(), expected.len());
+        let mut out = Vec::new();
+        drain(&mut encoder, &mut out);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn errors() {
+        let mut encoder = Encoder::new();
+        assert_eq!(encoder.push_value(&[1]), Err(EncoderError::NoFrame));
+        assert_eq!(encoder.finish(), Err(EncoderError::NoFrame));
+        assert_eq!(encoder.start_frame(8, 3),
+                   Err(EncoderError::ImpliedLength {
+                           type_: 8,
+                           implied: 2,
+                           declared: 3,
+                       }));
+        assert_eq!(encoder.pending_output(), 0);
+
+        encoder.start_frame(300, 2).unwrap();
+        assert_eq!(encoder.start_frame(300, 2), Err(EncoderError::FrameInProgress));
+        encoder.push_value(&[1]).unwrap();
+        assert_eq!(encoder.push_value(&[2, 3]),
+                   Err(EncoderError::LengthMismatch { declared: 2, pushed: 3 }));
+        assert_eq!(encoder.finish(),
+                   Err(EncoderError::LengthMismatch { declared: 2, pushed: 1 }));
+        assert!(encoder.is_mid_frame());
+        encoder.push_value(&[2]).unwrap();
+        encoder.finish().unwrap();
+        assert!(!encoder.is_mid_frame());
+
+        let mut out = [0; 10];
+        assert_eq!(encoder.next_chunk(&mut out), 6);
+        assert_eq!(out[..6], [249, 1, 44, 2, 1, 2]);
+    }
+}
@@ -0,0 +1,97 @@
+use std::{error, fmt};
+
+use super::{check_implied_length, Ctlv, CtlvIter, CtlvRef, DecodeError};
+
+/// The error returned when the value of a ctlv of a type below 128 would not have the length
+/// implied by its type.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ImpliedLengthError {
+    /// The type of the ctlv.
+    pub type_: u64,
+    /// The length implied by the type.
+    pub implied: usize,
+    /// The actual length of the value.
+    pub actual: usize,
+}
+
+impl fmt::Display for ImpliedLengthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        write!(f,
+               "Invalid value length for ctlv type {}: expected {} bytes, got {}",
+               self.type_,
+               self.implied,
+               self.actual)
+    }
+}
+
+impl error::Error for ImpliedLengthError {}
+
+/// Seal a sequence of ctlvs inside a single ctlv of the given type, so that it can travel as
+/// one unit.
+///
+/// The children are encoded directly into the value of the envelope. Fails if the type
+/// implies a length other than the combined length of the children's encodings.
+pub fn envelope(type_: u64, frames: &[CtlvRef]) -> Result<Ctlv, ImpliedLengthError> {
+    let len = frames.iter().map(|frame| frame.encoding_length()).sum();
+    check_implied_length(type_, len)?;
+
+    let mut value = Vec::with_capacity(len);
+    for frame in frames {
+        frame.encode_write(&mut value).unwrap();
+    }
+    Ok(Ctlv::new(type_, value))
+}
+
+/// Open an envelope created by [`envelope`](fn.envelope.html), returning an iterator over
+/// the sealed ctlvs.
+///
+/// The whole value is checked to be a valid sequence first, so the iterator never yields an
+/// error. Otherwise, this returns the error and the offset within the value at which it
+/// occurred.
+pub fn unseal<'a>(frame: &CtlvRef<'a>) -> Result<CtlvIter<'a>, (DecodeError, usize)> {
+    for result in CtlvIter::new(frame.value) {
+        result?;
+    }
+    Ok(CtlvIter::new(frame.value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let frames = [CtlvRef { type_: 0, value: &[1] },
+                      CtlvRef { type_: 8, value: &[2, 3] },
+                      CtlvRef { type_: 128, value: &[] },
+                      CtlvRef { type_: 300, value: &[4; 200] }];
+        let sealed = envelope(400, &frames).unwrap();
+        assert_eq!(sealed.value().len(), 2 + 3 + 2 + 204);
+
+        let opened = unseal(&sealed.as_ctlv_ref())
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(opened, frames);
+
+        assert_eq!(envelope(400, &[]).unwrap().value(), []);
+    }
+
+    #[test]
+    fn implied_length() {
+        let frames = [CtlvRef { type_: 0, value: &[1] }, CtlvRef { type_: 0, value: &[2] }];
+        assert_eq!(envelope(16, &frames).unwrap().encode_vec(), [16, 0, 1, 0, 2]);
+        assert_eq!(envelope(0, &frames),
+                   Err(ImpliedLengthError {
+                           type_: 0,
+                           implied: 1,
+                           actual: 4,
+                       }));
+    }
+
+    #[test]
+    fn invalid_value() {
+        let frame = CtlvRef { type_: 400, value: &[0, 1, 128, 2, 3] };
+        assert_eq!(unseal(&frame).unwrap_err(), (DecodeError::UnexpectedEndOfInput, 2));
+    }
+}
@@ -0,0 +1,221 @@
+use std::iter::FusedIterator;
+
+use super::nested::{DecoderOptions, NestedError};
+use super::CtlvRef;
+
+/// An event emitted by an [`EventParser`](struct.EventParser.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Event<'a> {
+    /// The start of a container. The events for its children follow, and then an `Exit`.
+    Enter {
+        /// The type of the container.
+        type_: u64,
+        /// The length of the value of the container.
+        len: usize,
+        /// The offset of the container, relative to the start of the input.
+        offset: usize,
+    },
+    /// A ctlv that is not a container.
+    Leaf {
+        /// The ctlv itself.
+        frame: CtlvRef<'a>,
+        /// The offset of the ctlv, relative to the start of the input.
+        offset: usize,
+    },
+    /// The end of the innermost container that has not ended yet.
+    Exit,
+}
+
+/// A pull parser for nested ctlvs, yielding `Event`s in document order.
+///
+/// The values of ctlvs for which `is_container` returns true are parsed as sequences. The
+/// parser keeps an explicit stack instead of recursing, so it can be driven step by step, e.g.
+/// from a state machine.
+///
+/// Every `Enter` event is matched by exactly one `Exit` event. This also holds if the input
+/// turns out to be invalid: after yielding the error, the parser yields the `Exit` events of
+/// all containers that are still open, and then ends. A caller that wants to stop early can
+/// call [`stop`](#method.stop) to get the same behavior.
+#[derive(Debug, Clone)]
+pub struct EventParser<'a, F> {
+    is_container: F,
+    options: DecoderOptions,
+    // For each open sequence, the part that has not been parsed yet, and its offset.
+    stack: Vec<(&'a [u8], usize)>,
+}
+
+impl<'a, F: Fn(u64) -> bool> EventParser<'a, F> {
+    /// Creates a parser over the given input, using the default `DecoderOptions`.
+    pub fn new(input: &'a [u8], is_container: F) -> EventParser<'a, F> {
+        EventParser::with_options(input, is_container, DecoderOptions::default())
+    }
+
+    /// Creates a parser over the given input, using the given `DecoderOptions`.
+    pub fn with_options(input: &'a [u8],
+                        is_container: F,
+                        options: DecoderOptions)
+                        -> EventParser<'a, F> {
+        EventParser {
+            is_container,
+            options,
+            stack: vec![(input, 0)],
+        }
+    }
+
+    /// Returns how many containers have been entered but not exited yet.
+    pub fn depth(&self) -> usize {
+        self.stack.len().saturating_sub(1)
+    }
+
+    /// Discards the remaining input. Afterwards, the parser only yields the `Exit` events of
+    /// the containers that are still open.
+    pub fn stop(&mut self) {
+        for level in self.stack.iter_mut() {
+            level.0 = &[];
+        }
+    }
+}
+
+impl<'a, F: Fn(u64) -> bool> Iterator for EventParser<'a, F> {
+    type Item = Result<Event<'a>, NestedError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (rest, offset) = *self.stack.last()?;
+
+        if rest.is_empty() {
+            self.stack.pop();
+            return if self.stack.is_empty() {
+                None
+            } else {
+                Some(Ok(Event::Exit))
+            };
+        }
+
+        let (frame, tail) = match CtlvRef::decode(rest) {
+            Ok(decoded) => decoded,
+            Err((e, _)) => {
+                self.stop();
+                return Some(Err(NestedError::Decode(e, offset)));
+            }
+        };
+        let next_offset = offset + (rest.len() - tail.len());
+        *self.stack.last_mut().unwrap() = (tail, next_offset);
+
+        if (self.is_container)(frame.type_) {
+            if self.depth() >= self.options.max_depth {
+                self.stop();
+                return Some(Err(NestedError::DepthExceeded(offset)));
+            }
+
+            self.stack.push((frame.value, next_offset - frame.value.len()));
+            Some(Ok(Event::Enter {
+                        type_: frame.type_,
+                        len: frame.value.len(),
+                        offset,
+                    }))
+        } else {
+            Some(Ok(Event::Leaf { frame, offset }))
+        }
+    }
+}
+
+impl<'a, F: Fn(u64) -> bool> FusedIterator for EventParser<'a, F> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Ctlv, DecodeError};
+    use super::super::test_util::ctlv;
+
+    fn is_container(type_: u64) -> bool {
+        type_ == 300 || type_ == 200 || type_ == 16
+    }
+
+    // Reassemble the encoding from a stream of events.
+    fn replay<'a, I: Iterator<Item = Result<Event<'a>, NestedError>>>(events: I) -> Vec<u8> {
+        let mut open: Vec<(u64, Vec<u8>)> = vec![(0, Vec::new())];
+        for event in events {
+            match event.unwrap() {
+                Event::Enter { type_, len, .. } => open.push((type_, Vec::with_capacity(len))),
+                Event::Leaf { frame, .. } => {
+                    frame.encode_write(&mut open.last_mut().unwrap().1).unwrap();
+                }
+                Event::Exit => {
+                    let (type_, value) = open.pop().unwrap();
+                    Ctlv::new(type_, value)
+                        .encode_write(&mut open.last_mut().unwrap().1)
+                        .unwrap();
+                }
+            }
+        }
+        assert_eq!(open.len(), 1);
+        open.pop().unwrap().1
+    }
+
+    #[test]
+    fn lossless() {
+        let inner = [ctlv(0, vec![1]), ctlv(128, vec![2, 3])].concat();
+        let middle = [ctlv(200, inner), ctlv(1, vec![4]), ctlv(16, vec![0, 5, 0, 6])].concat();
+        let input = [ctlv(300, middle), ctlv(0, vec![5]), ctlv(200, vec![])].concat();
+
+        let events: Vec<_> = EventParser::new(&input, is_container).collect();
+        assert_eq!(events[0],
+                   Ok(Event::Enter {
+                          type_: 300,
+                          len: 15,
+                          offset: 0,
+                      }));
+        assert_eq!(events[3],
+                   Ok(Event::Leaf {
+                          frame: CtlvRef { type_: 128, value: &[2, 3] },
+                          offset: 8,
+                      }));
+        assert_eq!(events.len(), 14);
+
+        assert_eq!(replay(events.into_iter()), input);
+    }
+
+    #[test]
+    fn errors_close_containers() {
+        let input = [ctlv(0, vec![5]), ctlv(300, ctlv(200, vec![128, 5]))].concat();
+        let events: Vec<_> = EventParser::new(&input, is_container).collect();
+        assert_eq!(events,
+                   vec![Ok(Event::Leaf {
+                               frame: CtlvRef { type_: 0, value: &[5] },
+                               offset: 0,
+                           }),
+                        Ok(Event::Enter {
+                               type_: 300,
+                               len: 4,
+                               offset: 2,
+                           }),
+                        Ok(Event::Enter {
+                               type_: 200,
+                               len: 2,
+                               offset: 6,
+                           }),
+                        Err(NestedError::Decode(DecodeError::UnexpectedEndOfInput, 8)),
+                        Ok(Event::Exit),
+                        Ok(Event::Exit)]);
+
+        let options = DecoderOptions { max_depth: 1 };
+        let events: Vec<_> = EventParser::with_options(&input, is_container, options).collect();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[2], Err(NestedError::DepthExceeded(6)));
+        assert_eq!(events[3], Ok(Event::Exit));
+    }
+
+    #[test]
+    fn stop_early() {
+        let input = ctlv(300, ctlv(200, ctlv(0, vec![1])));
+        let mut parser = EventParser::new(&input, is_container);
+        assert!(parser.next().unwrap().is_ok());
+        assert!(parser.next().unwrap().is_ok());
+        assert_eq!(parser.depth(), 2);
+
+        parser.stop();
+        assert_eq!(parser.next(), Some(Ok(Event::Exit)));
+        assert_eq!(parser.next(), Some(Ok(Event::Exit)));
+        assert_eq!(parser.next(), None);
+    }
+}
@@ -0,0 +1,223 @@
+use std::{error, fmt};
+
+use super::{Ctlv, CtlvRef, EXPLICIT_LENGTH_MIN_TYPE};
+
+// How many bytes the fragment headers take up at most, if there are `count` fragments.
+fn overhead(frag_type: u64, max_fragment: usize, type_: u64, count: usize) -> usize {
+    varu64::encoding_length(frag_type) + varu64::encoding_length(max_fragment as u64) +
+    varu64::encoding_length(type_) + varu64::encoding_length(count as u64 - 1) +
+    varu64::encoding_length(count as u64)
+}
+
+/// Split a ctlv into fragments of type `frag_type`, each of whose encodings takes up at most
+/// `max_fragment` bytes.
+///
+/// The value of each fragment consists of the varu64 encodings of the original type, the index
+/// of the fragment, and the total number of fragments, followed by a chunk of the original
+/// value. Every fragment but the last carries a chunk of the same size. The number of
+/// fragments is the smallest `n` for which `n` chunks of size `max_fragment - o(n)` can hold
+/// the value, where `o(n)` is the length of the varu64 encodings of `frag_type`,
+/// `max_fragment`, the original type, `n - 1`, and `n`. The fragmentation is thus fully
+/// determined by the arguments. An empty value results in a single fragment.
+///
+/// # Panics
+/// Panics if `frag_type` is below 128 (those types imply a length), or if `max_fragment` is
+/// too small to hold any part of the value.
+pub fn fragment(frame: &CtlvRef, max_fragment: usize, frag_type: u64) -> Vec<Ctlv> {
+    assert!(frag_type >= EXPLICIT_LENGTH_MIN_TYPE, "fragment type must not imply a length");
+    let chunk_len = |count| {
+        max_fragment
+            .checked_sub(overhead(frag_type, max_fragment, frame.type_, count))
+            .expect("max_fragment is too small for the fragment headers")
+    };
+
+    let len = frame.value.len();
+    let mut count = 1;
+    let mut chunk = chunk_len(count);
+    if len > 0 {
+        loop {
+            assert!(chunk > 0, "max_fragment is too small for the fragment headers");
+            let needed = len.div_ceil(chunk);
+            if needed <= count {
+                break;
+            }
+            count = needed;
+            chunk = chunk_len(count);
+        }
+    }
+
+    let mut chunks: Vec<&[u8]> = frame.value.chunks(chunk.max(1)).collect();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+
+    chunks.iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut value = Vec::with_capacity(max_fragment);
+            varu64::encode_write(frame.type_, &mut value).unwrap();
+            varu64::encode_write(index as u64, &mut value).unwrap();
+            varu64::encode_write(chunks.len() as u64, &mut value).unwrap();
+            value.extend_from_slice(chunk);
+            Ctlv::new(frag_type, value)
+        })
+        .collect()
+}
+
+/// Everything that can go wrong when reassembling fragments.
+///
+/// Positions count the fragments passed to [`reassemble`](fn.reassemble.html), starting at
+/// zero. Indices are the indices of fragments as stored in their headers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ReassemblyError {
+    /// There were no fragments at all.
+    Empty,
+    /// The header of the fragment at the given position could not be decoded, or its index is
+    /// not smaller than its fragment count.
+    Malformed(usize),
+    /// The fragment at the given position disagrees with the first fragment about the
+    /// fragment type, the original type, or the number of fragments.
+    Inconsistent(usize),
+    /// A fragment with the given index occurred more than once.
+    Duplicate(usize),
+    /// A fragment with index `found` occurred where the fragment with index `expected` was
+    /// due.
+    OutOfOrder {
+        /// The index of the fragment that was due.
+        expected: usize,
+        /// The index of the fragment that occurred instead.
+        found: usize,
+    },
+    /// The fragments ended before the fragment with the given index.
+    Missing(usize),
+}
+
+impl fmt::Display for ReassemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        match self {
+            ReassemblyError::Empty => write!(f, "No fragments to reassemble"),
+            ReassemblyError::Malformed(position) => {
+                write!(f, "Malformed fragment at position {}", position)
+            }
+            ReassemblyError::Inconsistent(position) => {
+                write!(f, "Fragment at position {} does not belong to the others", position)
+            }
+            ReassemblyError::Duplicate(index) => write!(f, "Duplicate fragment {}", index),
+            ReassemblyError::OutOfOrder { expected, found } => {
+                write!(f, "Expected fragment {}, got fragment {}", expected, found)
+            }
+            ReassemblyError::Missing(index) => write!(f, "Missing fragment {}", index),
+        }
+    }
+}
+
+impl error::Error for ReassemblyError {}
+
+/// Reassemble a ctlv from the fragments created by [`fragment`](fn.fragment.html), which
+/// must be given in order and without any other ctlvs in between.
+pub fn reassemble<'a, I>(frames: I) -> Result<Ctlv, ReassemblyError>
+    where I: IntoIterator<Item = CtlvRef<'a>>
+{
+    // The fragment type, the original type, the fragment count, and the reassembled value.
+    let mut state: Option<(u64, u64, usize, Vec<u8>)> = None;
+    let mut expected = 0;
+
+    for (position, frame) in frames.into_iter().enumerate() {
+        let (type_, index, count, chunk) =
+            decode_header(frame.value).ok_or(ReassemblyError::Malformed(position))?;
+
+        let (frag_type, original_type, total, value) =
+            state.get_or_insert_with(|| (frame.type_, type_, count, Vec::new()));
+        if frame.type_ != *frag_type || type_ != *original_type || count != *total {
+            return Err(ReassemblyError::Inconsistent(position));
+        }
+
+        if index < expected {
+            return Err(ReassemblyError::Duplicate(index));
+        } else if index > expected {
+            return Err(ReassemblyError::OutOfOrder { expected, found: index });
+        }
+        value.extend_from_slice(chunk);
+        expected += 1;
+    }
+
+    match state {
+        None => Err(ReassemblyError::Empty),
+        Some((_, _, count, _)) if expected < count => Err(ReassemblyError::Missing(expected)),
+        Some((_, type_, _, value)) => Ok(Ctlv::new(type_, value)),
+    }
+}
+
+// Decode the original type, index and count of a fragment, followed by its chunk.
+fn decode_header(value: &[u8]) -> Option<(u64, usize, usize, &[u8])> {
+    let (type_, tail) = varu64::decode(value).ok()?;
+    let (index, tail) = varu64::decode(tail).ok()?;
+    let (count, chunk) = varu64::decode(tail).ok()?;
+    if index >= count || count > usize::MAX as u64 {
+        return None;
+    }
+    Some((type_, index as usize, count as usize, chunk))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(value: &[u8], max_fragment: usize, chunks: &[usize]) {
+        let frame = CtlvRef { type_: 300, value };
+        let fragments = fragment(&frame, max_fragment, 500);
+
+        assert_eq!(fragments.len(), chunks.len());
+        for (fragment, chunk) in fragments.iter().zip(chunks.iter()) {
+            assert!(fragment.encoding_length() <= max_fragment);
+            assert_eq!(fragment.value().len(), 5 + chunk);
+        }
+
+        assert_eq!(reassemble(fragments.iter().map(|f| f.as_ctlv_ref())).unwrap(),
+                   frame.to_owned());
+    }
+
+    #[test]
+    fn boundaries() {
+        // Each fragment takes 4 bytes of frame header and 5 bytes of fragment header.
+        let value: Vec<u8> = (0..100).collect();
+        check(&value[..22], 20, &[11, 11]);
+        check(&value[..23], 20, &[11, 11, 1]);
+        check(&value[..11], 20, &[11]);
+        check(&value[..5], 20, &[5]);
+        check(&[], 20, &[0]);
+        check(&value, 50, &[41, 41, 18]);
+
+        let fragments = fragment(&CtlvRef { type_: 300, value: &value[..22] }, 20, 500);
+        assert_eq!(fragments[1].value()[..5], [249, 1, 44, 1, 2]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn too_small() {
+        fragment(&CtlvRef { type_: 300, value: &[1] }, 9, 500);
+    }
+
+    #[test]
+    fn errors() {
+        let value: Vec<u8> = (0..30).collect();
+        let fragments = fragment(&CtlvRef { type_: 300, value: &value }, 20, 500);
+        let refs: Vec<_> = fragments.iter().map(|f| f.as_ctlv_ref()).collect();
+        assert_eq!(refs.len(), 3);
+
+        assert_eq!(reassemble(vec![refs[0], refs[1]]), Err(ReassemblyError::Missing(2)));
+        assert_eq!(reassemble(vec![refs[0], refs[2], refs[1]]),
+                   Err(ReassemblyError::OutOfOrder {
+                           expected: 1,
+                           found: 2,
+                       }));
+        assert_eq!(reassemble(vec![refs[0], refs[0]]), Err(ReassemblyError::Duplicate(0)));
+        assert_eq!(reassemble(vec![]), Err(ReassemblyError::Empty));
+        assert_eq!(reassemble(vec![CtlvRef { type_: 500, value: &[1, 2] }]),
+                   Err(ReassemblyError::Malformed(0)));
+
+        let other = fragment(&CtlvRef { type_: 301, value: &value }, 20, 500);
+        assert_eq!(reassemble(vec![refs[0], other[1].as_ctlv_ref()]),
+                   Err(ReassemblyError::Inconsistent(1)));
+    }
+}
@@ -0,0 +1,82 @@
+use std::io;
+
+use varu64::DecodeError as VarU64Error;
+
+use super::{Ctlv, CtlvRef, DecodeError};
+
+/// Encodes a sequence of ctlvs as a self-delimiting message: a varu64 holding the combined
+/// length of their encodings, followed by the encodings. Returns how many bytes have been
+/// written.
+pub fn encode_framed<W: io::Write>(ctlvs: &[Ctlv], w: &mut W) -> io::Result<usize> {
+    let len: usize = ctlvs.iter().map(Ctlv::encoding_length).sum();
+    let mut total = varu64::encode_write(len as u64, &mut *w)?;
+    for ctlv in ctlvs {
+        total += ctlv.encode_write(&mut *w)?;
+    }
+    Ok(total)
+}
+
+/// Decodes a message written by [`encode_framed`](fn.encode_framed.html), returning its
+/// ctlvs and the input following the message.
+///
+/// Errors with `DecodeError::UnexpectedEndOfInput` if the declared length of the message runs
+/// past the end of the input, and with the error of the first invalid ctlv if the message is
+/// not a valid sequence. Malformed length prefixes are reported as `DecodeError::Length`.
+#[allow(clippy::type_complexity)]
+pub fn decode_framed(input: &[u8]) -> Result<(Vec<Ctlv>, &[u8]), (DecodeError, &[u8])> {
+    let (len, tail) = match varu64::decode(input) {
+        Ok(decoded) => decoded,
+        Err((VarU64Error::UnexpectedEndOfInput, _)) => {
+            return Err((DecodeError::UnexpectedEndOfInput, input))
+        }
+        Err((e, tail)) => return Err((DecodeError::Length(e), tail)),
+    };
+    if len > tail.len() as u64 {
+        return Err((DecodeError::UnexpectedEndOfInput, tail));
+    }
+
+    let (mut body, rest) = tail.split_at(len as usize);
+    let mut ctlvs = Vec::new();
+    while !body.is_empty() {
+        let (ctlv, remaining) = CtlvRef::decode(body)?;
+        ctlvs.push(ctlv.to_owned());
+        body = remaining;
+    }
+    Ok((ctlvs, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let ctlvs = vec![Ctlv::new(0, vec![1]),
+                         Ctlv::new(300, vec![2; 300])];
+        let mut out = Vec::new();
+        assert_eq!(encode_framed(&ctlvs, &mut out).unwrap(), out.len());
+        assert_eq!(out[..5], [249, 1, 52, 0, 1]);
+
+        out.extend_from_slice(&[9, 9]);
+        let (decoded, rest) = decode_framed(&out).unwrap();
+        assert_eq!(decoded, ctlvs);
+        assert_eq!(rest, [9, 9]);
+
+        let mut out = Vec::new();
+        encode_framed(&[], &mut out).unwrap();
+        assert_eq!(out, [0]);
+        assert_eq!(decode_framed(&out).unwrap(), (vec![], &[][..]));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(decode_framed(&[]), Err((DecodeError::UnexpectedEndOfInput, &[][..])));
+        assert_eq!(decode_framed(&[3, 0, 1]),
+                   Err((DecodeError::UnexpectedEndOfInput, &[0, 1][..])));
+        // The length of the message ends in the middle of a ctlv.
+        assert_eq!(decode_framed(&[3, 0, 1, 128, 1, 2]),
+                   Err((DecodeError::UnexpectedEndOfInput, &[][..])));
+        assert_eq!(decode_framed(&[248, 1]).unwrap_err().0,
+                   DecodeError::Length(VarU64Error::NonCanonical(1)));
+    }
+}
@@ -0,0 +1,403 @@
+use std::convert::TryInto;
+use std::error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use super::{CtlvIter, CtlvRef, DecodeError};
+
+// The first bytes of a saved index: a magic string and the version of the format.
+const MAGIC: [u8; 8] = *b"CTLVIDX\x01";
+
+// How many ctlvs `verify` checks at most.
+const VERIFY_SAMPLES: usize = 16;
+
+/// Everything that can go wrong when loading a [`CtlvIndex`](struct.CtlvIndex.html) with
+/// `CtlvIndex::load`.
+#[derive(Debug)]
+pub enum IndexLoadError {
+    /// The input does not start with the magic bytes of a saved index.
+    Magic,
+    /// The input is a saved index, but of a version of the format that is not supported.
+    Version(u8),
+    /// The input is truncated, or does not match its checksum, or is otherwise invalid.
+    Corrupt,
+    /// Reading failed with the wrapped error.
+    Io(io::Error),
+}
+
+impl fmt::Display for IndexLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        match self {
+            IndexLoadError::Magic => write!(f, "Not a saved ctlv index"),
+            IndexLoadError::Version(version) => {
+                write!(f, "Unsupported version {} of the ctlv index format", version)
+            }
+            IndexLoadError::Corrupt => write!(f, "Corrupt ctlv index"),
+            IndexLoadError::Io(e) => write!(f, "Could not read ctlv index: {}", e),
+        }
+    }
+}
+
+impl error::Error for IndexLoadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            IndexLoadError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// FNV-1a, which is simple enough to never change, to detect corrupt saved indices.
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// The offsets of the indexed ctlvs, as narrow as the input allows.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Offsets {
+    Narrow(Vec<u32>),
+    Wide(Vec<u64>),
+}
+
+/// An index of the ctlvs in an encoded sequence, for random access.
+///
+/// The index stores the offset and the type of every ctlv, but not the input itself. Offsets
+/// take up four bytes each if the input is less than 4 GiB long, eight bytes otherwise.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CtlvIndex {
+    offsets: Offsets,
+    types: Vec<u64>,
+    // The offset right after the last indexed ctlv.
+    end: u64,
+}
+
+impl CtlvIndex {
+    /// Index all ctlvs of the input. If the input is invalid, returns the error and the offset
+    /// at which it occurred.
+    pub fn build(input: &[u8]) -> Result<CtlvIndex, (DecodeError, usize)> {
+        match CtlvIndex::build_partial(input) {
+            (index, None) => Ok(index),
+            (_, Some(e)) => Err(e),
+        }
+    }
+
+    /// Index the ctlvs of the input up to the first invalid one, returning the index of the
+    /// valid prefix together with the error (and its offset), if any.
+    pub fn build_partial(input: &[u8]) -> (CtlvIndex, Option<(DecodeError, usize)>) {
+        CtlvIndex::build_impl(input, input.len() > u32::MAX as usize)
+    }
+
+    fn build_impl(input: &[u8], wide: bool) -> (CtlvIndex, Option<(DecodeError, usize)>) {
+        let mut index = CtlvIndex {
+            offsets: if wide {
+                Offsets::Wide(Vec::new())
+            } else {
+                Offsets::Narrow(Vec::new())
+            },
+            types: Vec::new(),
+            end: 0,
+        };
+
+        let mut iter = CtlvIter::new(input);
+        loop {
+            let offset = iter.offset();
+            match iter.next() {
+                None => return (index, None),
+                Some(Err(e)) => return (index, Some(e)),
+                Some(Ok(frame)) => {
+                    index.push(offset as u64, frame.type_);
+                    index.end = iter.offset() as u64;
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, offset: u64, type_: u64) {
+        match &mut self.offsets {
+            Offsets::Narrow(offsets) => offsets.push(offset as u32),
+            Offsets::Wide(offsets) => offsets.push(offset),
+        }
+        self.types.push(type_);
+    }
+
+    /// Writes this index to `w` in a compact binary format, for loading it with
+    /// [`load`](#method.load) instead of building it again.
+    ///
+    /// The format consists of magic bytes and a version, the length of the indexed input and
+    /// the number of ctlvs, then the offset (relative to the previous one) and the type of
+    /// every ctlv, all as varu64s, and finally a checksum of all that.
+    pub fn save<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let mut out = MAGIC.to_vec();
+        varu64::encode_write(self.end, &mut out)?;
+        varu64::encode_write(self.len() as u64, &mut out)?;
+        let mut previous = 0;
+        for i in 0..self.len() {
+            let offset = self.offset_u64(i);
+            varu64::encode_write(offset - previous, &mut out)?;
+            varu64::encode_write(self.types[i], &mut out)?;
+            previous = offset;
+        }
+        let sum = checksum(&out);
+        out.extend_from_slice(&sum.to_be_bytes());
+        w.write_all(&out)
+    }
+
+    /// Reads an index written by [`save`](#method.save).
+    ///
+    /// Errors with `IndexLoadError::Corrupt` rather than returning a wrong index if the input
+    /// has been modified or truncated. That can not detect an index which is intact but has
+    /// been built from different data, use [`verify`](#method.verify) for that.
+    pub fn load<R: Read>(mut r: R) -> Result<CtlvIndex, IndexLoadError> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data).map_err(IndexLoadError::Io)?;
+        if data.len() < MAGIC.len() || data[..MAGIC.len() - 1] != MAGIC[..MAGIC.len() - 1] {
+            return Err(IndexLoadError::Magic);
+        }
+        if data[MAGIC.len() - 1] != MAGIC[MAGIC.len() - 1] {
+            return Err(IndexLoadError::Version(data[MAGIC.len() - 1]));
+        }
+        if data.len() < MAGIC.len() + 8 {
+            return Err(IndexLoadError::Corrupt);
+        }
+        let (body, sum) = data.split_at(data.len() - 8);
+        if checksum(body).to_be_bytes() != sum {
+            return Err(IndexLoadError::Corrupt);
+        }
+
+        let mut input = &body[MAGIC.len()..];
+        let mut next = || match varu64::decode(input) {
+            Ok((n, tail)) => {
+                input = tail;
+                Ok(n)
+            }
+            Err(_) => Err(IndexLoadError::Corrupt),
+        };
+        let end = next()?;
+        let count = next()?;
+        let mut index = CtlvIndex {
+            offsets: if end > u32::MAX as u64 {
+                Offsets::Wide(Vec::new())
+            } else {
+                Offsets::Narrow(Vec::new())
+            },
+            types: Vec::new(),
+            end,
+        };
+        let mut offset = 0u64;
+        for i in 0..count {
+            let delta = next()?;
+            offset = match offset.checked_add(delta) {
+                // Offsets are strictly increasing, and every ctlv ends at the latest at `end`.
+                Some(offset) if (delta > 0 || i == 0) && offset < end => offset,
+                _ => return Err(IndexLoadError::Corrupt),
+            };
+            let type_ = next()?;
+            index.push(offset, type_);
+        }
+        if !input.is_empty() {
+            return Err(IndexLoadError::Corrupt);
+        }
+        Ok(index)
+    }
+
+    /// Spot-checks whether this index still matches the input, returning `false` if the input
+    /// has changed since the index was built.
+    ///
+    /// The input must be at least as long as the indexed part, and a small sample of the
+    /// indexed ctlvs must decode at their offsets with their types, each ending where the next
+    /// one begins. This is cheap even for large inputs, but can not detect changes to the
+    /// values of ctlvs or to ctlvs that are not part of the sample.
+    pub fn verify(&self, input: &[u8]) -> bool {
+        if (input.len() as u64) < self.end {
+            return false;
+        }
+        let len = self.len();
+        let step = (len / VERIFY_SAMPLES).max(1);
+        // Always check the last ctlv, so that the end of the indexed part is checked as well.
+        (0..len).step_by(step).chain(len.checked_sub(1)).all(|i| {
+            let next = if i + 1 < len {
+                self.offset_u64(i + 1)
+            } else {
+                self.end
+            };
+            match self.get(i, input) {
+                Some(frame) => self.offset_u64(i) + frame.encoding_length() as u64 == next,
+                None => false,
+            }
+        })
+    }
+
+    fn offset_u64(&self, i: usize) -> u64 {
+        match &self.offsets {
+            Offsets::Narrow(offsets) => offsets[i] as u64,
+            Offsets::Wide(offsets) => offsets[i],
+        }
+    }
+
+    /// Returns how many ctlvs have been indexed.
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    /// Returns whether no ctlvs have been indexed.
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    /// Returns the offset of the ctlv at the given index.
+    pub fn offset(&self, i: usize) -> Option<usize> {
+        match &self.offsets {
+            Offsets::Narrow(offsets) => offsets.get(i).map(|offset| *offset as usize),
+            Offsets::Wide(offsets) => offsets.get(i).and_then(|offset| (*offset).try_into().ok()),
+        }
+    }
+
+    /// Returns the type of the ctlv at the given index.
+    pub fn type_(&self, i: usize) -> Option<u64> {
+        self.types.get(i).cloned()
+    }
+
+    /// Decodes the ctlv at the given index from the input the index was built from.
+    ///
+    /// Returns `None` if there is no ctlv at that index, or if it can not be decoded from the
+    /// given input (which happens if the input is not the one the index was built from).
+    pub fn get<'a>(&self, i: usize, input: &'a [u8]) -> Option<CtlvRef<'a>> {
+        let (frame, _) = CtlvRef::decode(input.get(self.offset(i)?..)?).ok()?;
+        if frame.type_ == self.types[i] {
+            Some(frame)
+        } else {
+            None
+        }
+    }
+
+    /// Returns an iterator over the indices of all ctlvs of the given type, in ascending order.
+    pub fn frames_of_type(&self, type_: u64) -> impl Iterator<Item = usize> + '_ {
+        self.types
+            .iter()
+            .enumerate()
+            .filter(move |(_, t)| **t == type_)
+            .map(|(i, _)| i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Ctlv;
+
+    fn fixture() -> Vec<u8> {
+        (0..3000u64)
+            .map(|i| if i % 3 == 0 {
+                     Ctlv::new(i % 7, vec![i as u8])
+                 } else {
+                     Ctlv::new(128 + i % 500, vec![i as u8; (i % 13) as usize])
+                 })
+            .map(|ctlv| ctlv.encode_vec())
+            .collect::<Vec<_>>()
+            .concat()
+    }
+
+    fn check(index: &CtlvIndex, input: &[u8]) {
+        let scanned: Vec<_> = CtlvIter::new(input).map(Result::unwrap).collect();
+        assert_eq!(index.len(), scanned.len());
+
+        // Visit the indices in a scrambled order.
+        for k in 0..scanned.len() {
+            let i = (k * 1103) % scanned.len();
+            assert_eq!(index.get(i, input), Some(scanned[i]));
+        }
+        assert_eq!(index.get(scanned.len(), input), None);
+
+        let of_type: Vec<_> = index.frames_of_type(3).collect();
+        assert_eq!(of_type,
+                   (0..scanned.len()).filter(|i| scanned[*i].type_ == 3).collect::<Vec<_>>());
+        assert!(!of_type.is_empty());
+    }
+
+    #[test]
+    fn random_access() {
+        let input = fixture();
+        assert!(input.len() > 10000);
+
+        let index = CtlvIndex::build(&input).unwrap();
+        assert!(matches!(index.offsets, Offsets::Narrow(_)));
+        check(&index, &input);
+
+        let (wide, error) = CtlvIndex::build_impl(&input, true);
+        assert_eq!(error, None);
+        check(&wide, &input);
+    }
+
+    #[test]
+    fn save_load() {
+        let input = fixture();
+        let index = CtlvIndex::build(&input).unwrap();
+        assert!(index.verify(&input));
+
+        let mut saved = Vec::new();
+        index.save(&mut saved).unwrap();
+        assert!(saved.len() < 4 * index.len());
+        let loaded = CtlvIndex::load(&saved[..]).unwrap();
+        assert_eq!(loaded, index);
+        check(&loaded, &input);
+
+        let (wide, _) = CtlvIndex::build_impl(&input, true);
+        let mut saved_wide = Vec::new();
+        wide.save(&mut saved_wide).unwrap();
+        assert_eq!(saved_wide, saved);
+
+        let (partial, _) = CtlvIndex::build_partial(&[0, 1, 128, 2]);
+        let mut saved_partial = Vec::new();
+        partial.save(&mut saved_partial).unwrap();
+        assert_eq!(CtlvIndex::load(&saved_partial[..]).unwrap(), partial);
+        assert!(partial.verify(&[0, 1]));
+        assert!(CtlvIndex::build(&[]).unwrap().verify(&[]));
+    }
+
+    #[test]
+    fn tampering() {
+        let input = fixture();
+        let index = CtlvIndex::build(&input).unwrap();
+        let mut saved = Vec::new();
+        index.save(&mut saved).unwrap();
+
+        for i in 0..saved.len() {
+            let mut tampered = saved.clone();
+            tampered[i] ^= 0x10;
+            assert!(CtlvIndex::load(&tampered[..]).is_err());
+        }
+        for len in 0..saved.len() {
+            assert!(CtlvIndex::load(&saved[..len]).is_err());
+        }
+        assert!(matches!(CtlvIndex::load(&b"CTLVIDX\x02"[..]), Err(IndexLoadError::Version(2))));
+        assert!(matches!(CtlvIndex::load(&b"not an index"[..]), Err(IndexLoadError::Magic)));
+
+        // Stale indices.
+        assert!(!index.verify(&input[..input.len() - 1]));
+        assert!(!index.verify(&input[1..]));
+        let mut shifted = vec![0, 0];
+        shifted.extend_from_slice(&input);
+        assert!(!index.verify(&shifted));
+        let mut retyped = input.clone();
+        retyped[index.offset(index.len() - 1).unwrap()] ^= 1;
+        assert!(!index.verify(&retyped));
+    }
+
+    #[test]
+    fn partial() {
+        let input = [0, 1, 128, 2, 3, 4, 128, 5, 0];
+        assert_eq!(CtlvIndex::build(&input), Err((DecodeError::UnexpectedEndOfInput, 6)));
+
+        let (index, error) = CtlvIndex::build_partial(&input);
+        assert_eq!(error, Some((DecodeError::UnexpectedEndOfInput, 6)));
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.offset(1), Some(2));
+        assert_eq!(index.type_(1), Some(128));
+        assert_eq!(index.get(1, &input), Some(CtlvRef { type_: 128, value: &[3, 4] }));
+        assert_eq!(index.get(1, &[]), None);
+
+        assert!(CtlvIndex::build(&[]).unwrap().is_empty());
+    }
+}
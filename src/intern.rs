@@ -0,0 +1,128 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use super::{CtlvArc, CtlvGeneric, CtlvIter, DecodeError};
+
+/// A ctlv whose value may be shared with other ctlvs, as produced by
+/// [`decode_all_interned`](fn.decode_all_interned.html).
+pub type InternedCtlv = CtlvArc;
+
+/// How much a [`ValuePool`](struct.ValuePool.html) deduplicated.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct PoolStats {
+    /// How many values have been interned.
+    pub values: usize,
+    /// How many distinct values there are, i.e. how many allocations the pool holds.
+    pub unique: usize,
+    /// The combined length of all interned values.
+    pub total_bytes: usize,
+    /// The combined length of the distinct values.
+    pub unique_bytes: usize,
+}
+
+impl PoolStats {
+    /// Returns how many bytes of values did not need to be allocated thanks to sharing.
+    pub fn saved_bytes(&self) -> usize {
+        self.total_bytes - self.unique_bytes
+    }
+}
+
+/// A set of values, handing out a single shared allocation for all equal values.
+#[derive(Debug, Clone, Default)]
+pub struct ValuePool {
+    values: HashSet<Arc<[u8]>>,
+    stats: PoolStats,
+}
+
+impl ValuePool {
+    /// Creates an empty pool.
+    pub fn new() -> ValuePool {
+        ValuePool::default()
+    }
+
+    /// Returns the shared allocation for the given value, creating it if the value has not
+    /// been interned before.
+    pub fn intern(&mut self, value: &[u8]) -> Arc<[u8]> {
+        self.stats.values += 1;
+        self.stats.total_bytes += value.len();
+
+        if let Some(shared) = self.values.get(value) {
+            return shared.clone();
+        }
+        let shared: Arc<[u8]> = value.into();
+        self.values.insert(shared.clone());
+        self.stats.unique += 1;
+        self.stats.unique_bytes += value.len();
+        shared
+    }
+
+    /// Returns how many distinct values the pool holds.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns whether the pool holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns statistics about all values interned so far.
+    pub fn stats(&self) -> PoolStats {
+        self.stats
+    }
+}
+
+/// Decode all ctlvs of an encoded sequence, with all equal values sharing a single allocation.
+///
+/// This saves memory over [`decode_all`](fn.decode_all.html) if the same values occur many
+/// times. The returned pool reports how much was saved, and can be used to intern further
+/// values. If the input is invalid, returns the error and the offset at which it occurred.
+pub fn decode_all_interned(input: &[u8])
+                           -> Result<(Vec<InternedCtlv>, ValuePool), (DecodeError, usize)> {
+    let mut pool = ValuePool::new();
+    let mut ctlvs = Vec::new();
+    for result in CtlvIter::new(input) {
+        let frame = result?;
+        ctlvs.push(CtlvGeneric::new(frame.type_, pool.intern(frame.value)));
+    }
+    Ok((ctlvs, pool))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Ctlv;
+    use std::ptr;
+
+    #[test]
+    fn shared() {
+        let input: Vec<u8> = (0..100u64)
+            .map(|i| Ctlv::new(128 + i, vec![1; (i % 3) as usize * 10]).encode_vec())
+            .collect::<Vec<_>>()
+            .concat();
+
+        let (ctlvs, pool) = decode_all_interned(&input).unwrap();
+        assert_eq!(ctlvs.len(), 100);
+        assert_eq!(pool.len(), 3);
+        assert!(ptr::eq(ctlvs[1].value(), ctlvs[4].value()));
+        assert!(ptr::eq(ctlvs[2].value(), ctlvs[98].value()));
+        assert!(!ptr::eq(ctlvs[1].value(), ctlvs[2].value()));
+
+        let stats = pool.stats();
+        assert_eq!(stats.values, 100);
+        assert_eq!(stats.unique, 3);
+        assert_eq!(stats.total_bytes, 33 * 10 + 33 * 20);
+        assert_eq!(stats.unique_bytes, 30);
+        assert_eq!(stats.saved_bytes(), 960);
+
+        let encoded: Vec<_> = ctlvs.iter().map(|ctlv| ctlv.encode_vec()).collect();
+        assert_eq!(encoded.concat(), input);
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(decode_all_interned(&[0, 1, 128]).err(),
+                   Some((DecodeError::UnexpectedEndOfInput, 2)));
+        assert!(decode_all_interned(&[]).unwrap().1.is_empty());
+    }
+}
@@ -0,0 +1,160 @@
+use std::iter::FusedIterator;
+
+use super::{Ctlv, CtlvRef, DecodeConfig, DecodeError};
+
+/// An iterator over the ctlvs of an encoded sequence, i.e. a concatenation of ctlv encodings.
+///
+/// Yields the decoded ctlvs as `CtlvRef`s borrowing from the input. If decoding fails, the
+/// error is yielded together with the offset of the ctlv that could not be decoded, and the
+/// iterator ends afterwards.
+#[derive(Debug, Clone)]
+pub struct CtlvIter<'a> {
+    input: &'a [u8],
+    offset: usize,
+    failed: bool,
+}
+
+impl<'a> CtlvIter<'a> {
+    /// Creates an iterator over the ctlvs encoded in the given input.
+    pub fn new(input: &'a [u8]) -> CtlvIter<'a> {
+        CtlvIter {
+            input,
+            offset: 0,
+            failed: false,
+        }
+    }
+
+    /// Returns the offset (relative to the start of the input) of the next ctlv.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the part of the input that has not been decoded yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.input[self.offset..]
+    }
+}
+
+impl<'a> Iterator for CtlvIter<'a> {
+    type Item = Result<CtlvRef<'a>, (DecodeError, usize)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.offset == self.input.len() {
+            return None;
+        }
+
+        match CtlvRef::decode(self.remaining()) {
+            Ok((ctlv, tail)) => {
+                self.offset = self.input.len() - tail.len();
+                Some(Ok(ctlv))
+            }
+            Err((e, _)) => {
+                self.failed = true;
+                Some(Err((e, self.offset)))
+            }
+        }
+    }
+}
+
+impl<'a> FusedIterator for CtlvIter<'a> {}
+
+/// Decode all ctlvs of an encoded sequence into owned `Ctlv`s.
+///
+/// If the input is invalid, returns the error and the offset at which it occurred.
+pub fn decode_all(input: &[u8]) -> Result<Vec<Ctlv>, (DecodeError, usize)> {
+    CtlvIter::new(input).map(|result| result.map(CtlvRef::into_owned)).collect()
+}
+
+/// Returns whether all ctlvs of an encoded sequence are encoded canonically, i.e. with the
+/// shortest possible varu64s for their types and lengths.
+///
+/// `CtlvRef::decode` and friends already reject non-canonical varu64s with an error, this
+/// instead accepts them just long enough to tell whether there are any. The length of a ctlv
+/// with a type below 128 is implied rather than encoded, so for those only the type can be
+/// non-canonical; a ctlv with a type of 128 or more always has an explicit length, there is no
+/// alternative form to choose from.
+///
+/// Returns `false` as soon as a non-canonical ctlv is found, without looking at the rest of the
+/// input. Errors if the input is invalid for any other reason before that point.
+pub fn is_canonical_encoding(input: &[u8]) -> Result<bool, DecodeError> {
+    let lenient = DecodeConfig {
+        require_canonical: false,
+        ..DecodeConfig::default()
+    };
+
+    let mut remaining = input;
+    while !remaining.is_empty() {
+        let (frame, tail) = CtlvRef::decode_with(remaining, &lenient).map_err(|(e, _)| e)?;
+        if remaining.len() - tail.len() != frame.encoding_length() {
+            return Ok(false);
+        }
+        remaining = tail;
+    }
+    Ok(true)
+}
+
+/// Returns the first ctlv of the given type in an encoded sequence, or `None` if there is none.
+///
+/// Only the ctlvs up to the first match are decoded, so invalid data after it is not detected.
+/// If the sequence is invalid before the first match, returns the error and the offset at
+/// which it occurred.
+pub fn find_type(input: &[u8], type_: u64) -> Result<Option<CtlvRef<'_>>, (DecodeError, usize)> {
+    for result in CtlvIter::new(input) {
+        let frame = result?;
+        if frame.type_ == type_ {
+            return Ok(Some(frame));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::DecodeError::*;
+
+    #[test]
+    fn iterate() {
+        let input = [0, 42, 128, 2, 1, 2, 128, 3, 0];
+        let mut iter = CtlvIter::new(&input);
+
+        assert_eq!(iter.next(), Some(Ok(CtlvRef { type_: 0, value: &[42] })));
+        assert_eq!(iter.offset(), 2);
+        assert_eq!(iter.next(), Some(Ok(CtlvRef { type_: 128, value: &[1, 2] })));
+        assert_eq!(iter.next(), Some(Err((UnexpectedEndOfInput, 6))));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.remaining(), &[128, 3, 0][..]);
+
+        assert_eq!(CtlvIter::new(&[]).next(), None);
+    }
+
+    #[test]
+    fn find() {
+        let input = [0, 42, 128, 2, 1, 2, 128, 0, 128, 3];
+        assert_eq!(find_type(&input, 128), Ok(Some(CtlvRef { type_: 128, value: &[1, 2] })));
+        assert_eq!(find_type(&input, 0), Ok(Some(CtlvRef { type_: 0, value: &[42] })));
+        assert_eq!(find_type(&input, 1), Err((UnexpectedEndOfInput, 8)));
+        assert_eq!(find_type(&input[..8], 1), Ok(None));
+    }
+
+    #[test]
+    fn owned() {
+        assert_eq!(decode_all(&[0, 42, 128, 1, 2]).unwrap(),
+                   vec![Ctlv::new(0, vec![42]), Ctlv::new(128, vec![2])]);
+        assert_eq!(decode_all(&[0, 42, 128, 1]), Err((UnexpectedEndOfInput, 2)));
+    }
+
+    #[test]
+    fn canonical() {
+        assert_eq!(is_canonical_encoding(&[0, 42, 128, 1, 2, 249, 1, 44, 0]), Ok(true));
+        assert_eq!(is_canonical_encoding(&[]), Ok(true));
+        // Non-canonical type.
+        assert_eq!(is_canonical_encoding(&[0, 42, 248, 0, 42, 0, 1]), Ok(false));
+        // Non-canonical length.
+        assert_eq!(is_canonical_encoding(&[128, 248, 1, 2]), Ok(false));
+        assert_eq!(is_canonical_encoding(&[128, 249, 0, 1, 2, 0]), Ok(false));
+        // Only the part before the first non-canonical ctlv is checked.
+        assert_eq!(is_canonical_encoding(&[128, 248, 1, 2, 128]), Ok(false));
+        assert_eq!(is_canonical_encoding(&[0, 42, 128]), Err(UnexpectedEndOfInput));
+    }
+}
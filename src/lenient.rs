@@ -0,0 +1,111 @@
+use super::{type_implied_length, CtlvRef, DecodeError, EXPLICIT_LENGTH_MIN_TYPE};
+
+/// A ctlv decoded by [`decode_lenient`](fn.decode_lenient.html), together with the form its
+/// length was encoded in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LenientCtlv<'a> {
+    /// The decoded ctlv.
+    pub frame: CtlvRef<'a>,
+    /// Whether the type implies the length of the value, but the length was encoded
+    /// explicitly nonetheless.
+    pub redundant_length: bool,
+}
+
+impl<'a> LenientCtlv<'a> {
+    /// Return how many bytes the encoding of this ctlv in its original form takes up.
+    pub fn encoding_length(&self) -> usize {
+        if self.redundant_length {
+            self.frame.encoding_length() + varu64::encoding_length(self.frame.value.len() as u64)
+        } else {
+            self.frame.encoding_length()
+        }
+    }
+
+    /// Encodes this ctlv in the form it was decoded from, i.e. with the redundant length if
+    /// there was one.
+    pub fn encode_vec(&self) -> Vec<u8> {
+        if !self.redundant_length {
+            return self.frame.encode_vec();
+        }
+        let mut out = vec![0; self.encoding_length()];
+        let mut len = varu64::encode(self.frame.type_, &mut out);
+        len += varu64::encode(self.frame.value.len() as u64, &mut out[len..]);
+        out[len..].copy_from_slice(self.frame.value);
+        out
+    }
+}
+
+/// Decode a ctlv from the input buffer, also accepting a length for types that imply it.
+///
+/// This is **not** a decoder for valid ctlvs, but a compatibility shim for producers that
+/// encode ctlvs with a type below 128 in the explicit-length form, i.e. followed by a
+/// redundant varu64 of the implied length. If the varu64 after such a type equals the implied
+/// length and is followed by enough input, it is taken as a redundant length, otherwise the
+/// ctlv is decoded as usual. The returned [`LenientCtlv`](struct.LenientCtlv.html) records
+/// which form was used, so that it can be encoded again in the same form.
+///
+/// Some inputs can be read in both forms, e.g. `[0, 1, 2]` is either a ctlv of type `0` with
+/// the value `[1]` followed by the byte `2`, or one with a redundant length and the value
+/// `[2]`. This function always prefers the latter, so only use it on input that is known to
+/// come from such a producer.
+///
+/// Errors like [`CtlvRef::decode`](struct.CtlvRef.html#method.decode) otherwise.
+#[allow(clippy::type_complexity)]
+pub fn decode_lenient(input: &[u8]) -> Result<(LenientCtlv<'_>, &[u8]), (DecodeError, &[u8])> {
+    if let Ok((type_, tail)) = varu64::decode(input) {
+        if type_ < EXPLICIT_LENGTH_MIN_TYPE {
+            let implied = type_implied_length(type_);
+            if let Ok((len, remaining)) = varu64::decode(tail) {
+                if len == implied as u64 && remaining.len() >= implied {
+                    let (value, tail) = remaining.split_at(implied);
+                    let lenient = LenientCtlv {
+                        frame: CtlvRef { type_, value },
+                        redundant_length: true,
+                    };
+                    return Ok((lenient, tail));
+                }
+            }
+        }
+    }
+
+    let (frame, tail) = CtlvRef::decode(input)?;
+    let lenient = LenientCtlv {
+        frame,
+        redundant_length: false,
+    };
+    Ok((lenient, tail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_forms() {
+        let frame = CtlvRef { type_: 8, value: &[5, 6] };
+        for (input, redundant_length) in [(&[8, 5, 6, 42][..], false), (&[8, 2, 5, 6, 42], true)] {
+            let (decoded, tail) = decode_lenient(input).unwrap();
+            assert_eq!(decoded.frame, frame);
+            assert_eq!(decoded.redundant_length, redundant_length);
+            assert_eq!(tail, [42]);
+            assert_eq!(decoded.encoding_length(), input.len() - 1);
+            assert_eq!(decoded.encode_vec(), input[..input.len() - 1]);
+        }
+
+        // Not enough input for the redundant form, or a different length.
+        let (decoded, _) = decode_lenient(&[8, 2, 5]).unwrap();
+        assert_eq!(decoded.frame.value, [2, 5]);
+        assert!(!decoded.redundant_length);
+        let (decoded, _) = decode_lenient(&[8, 3, 5, 6]).unwrap();
+        assert_eq!(decoded.frame.value, [3, 5]);
+
+        let (decoded, tail) = decode_lenient(&[0, 1, 2]).unwrap();
+        assert_eq!(decoded.frame, CtlvRef { type_: 0, value: &[2] });
+        assert!(tail.is_empty());
+
+        let (decoded, _) = decode_lenient(&[249, 1, 44, 1, 7]).unwrap();
+        assert_eq!(decoded.frame, CtlvRef { type_: 300, value: &[7] });
+        assert!(!decoded.redundant_length);
+        assert_eq!(decode_lenient(&[8, 5]).unwrap_err().0, DecodeError::UnexpectedEndOfInput);
+    }
+}
@@ -4,13 +4,178 @@
 //! user to ensure that ctlvs with a type below 128 contain data of the correct length.
 
 extern crate varu64;
+#[cfg(feature = "rayon")]
+extern crate rayon;
 
 use varu64::DecodeError as VarU64Error;
 
 use std::{fmt, error, io};
+use std::cmp::Ordering;
+use std::hash::Hasher;
+use std::io::IoSlice;
+use std::mem::MaybeUninit;
+use std::ops::Deref;
+use std::convert::{TryFrom, TryInto};
+use std::sync::Arc;
+
+#[cfg(feature = "futures-io")]
+mod async_io;
+#[cfg(any(feature = "tokio", feature = "asynchronous-codec"))]
+mod codec;
+mod concat;
+mod counting;
+mod cursor;
+mod datagram;
+mod decoder;
+mod diff;
+mod encoder;
+mod envelope;
+mod events;
+mod fragment;
+mod framed;
+mod index;
+mod intern;
+mod iter;
+mod lenient;
+mod log;
+mod map;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod nested;
+#[cfg(feature = "rayon")]
+mod par;
+mod pretty;
+mod read;
+mod reader;
+mod schema;
+mod seq;
+#[cfg(feature = "tokio")]
+mod sink;
+mod slice;
+mod split;
+mod stats;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+mod transform;
+mod value;
+mod vectored;
+mod visit;
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+mod write_buffer;
+mod writer;
+
+#[cfg(feature = "futures-io")]
+pub use self::async_io::{decode_async, decode_async_with, encode_async, frame_sink, frame_stream,
+                         read_frame_streaming_async, read_header_async, read_header_async_with,
+                         AsyncCtlvReader, FrameSink, FrameStream, ValueBody};
+#[cfg(any(feature = "tokio", feature = "asynchronous-codec"))]
+pub use self::codec::{CtlvCodec, DEFAULT_MAX_VALUE_LEN};
+pub use self::concat::{concat_validated, concat_validated_into, decode_chained, validate_then_chain,
+                       ConcatInfo};
+pub use self::counting::CountingWriter;
+pub use self::cursor::ValueCursor;
+pub use self::datagram::{pack_datagrams, unpack_datagram, PackDatagrams};
+pub use self::decoder::{Decoder, DecoderState, RestoreError};
+pub use self::diff::{apply, diff, diff_positional, CtlvDiff, DiffError, FrameLocation, SeqDiff};
+pub use self::encoder::{Encoder, EncoderError};
+pub use self::envelope::{envelope, unseal, ImpliedLengthError};
+pub use self::events::{Event, EventParser};
+pub use self::fragment::{fragment, reassemble, ReassemblyError};
+pub use self::framed::{decode_framed, encode_framed};
+pub use self::index::{CtlvIndex, IndexLoadError};
+pub use self::intern::{decode_all_interned, InternedCtlv, PoolStats, ValuePool};
+pub use self::iter::{decode_all, find_type, is_canonical_encoding, CtlvIter};
+pub use self::lenient::{decode_lenient, LenientCtlv};
+pub use self::log::{CtlvLogReader, CtlvLogWriter, LogOptions, LogReadError, SyncPolicy};
+pub use self::map::{from_map, to_map, CtlvMap, CtlvMapIter, DuplicatePolicy, MapDecodeError};
+#[cfg(feature = "mmap")]
+pub use self::mmap::MappedCtlvFile;
+pub use self::nested::{canonicalize_nested, get_path, CtlvTree, DecoderOptions, DepthFirst,
+                       NestedError, PathResult};
+#[cfg(feature = "rayon")]
+pub use self::par::par_decode_all;
+pub use self::pretty::{dump, format_tree, FormatOptions};
+pub use self::read::{copy_value, decode_from_bufread, read_frame_streaming, read_header,
+                     read_header_with, skip_value, skip_value_seek, transfer_frame,
+                     with_next_frame, ReadDecodeError};
+pub use self::reader::{read_iter, CtlvReader, ReadIter};
+pub use self::schema::{ContainerRule, Document, DocumentError, Schema, SchemaViolation,
+                       UnknownTypes, ViolationKind};
+pub use self::seq::CtlvSeq;
+#[cfg(feature = "tokio")]
+pub use self::sink::CtlvSink;
+pub use self::slice::{CtlvSlice, CtlvSliceIter};
+pub use self::split::{chunks_by_frames, split_at_frame, FrameChunks};
+pub use self::stats::{analyze, analyze_nested, analyze_nested_with, Stats, TypeStats};
+pub use self::transform::{remap_types, remap_types_nested, retain_encoded,
+                          rewrite_type_in_place, strip_types, strip_types_in_place, RewriteError,
+                          StripStats};
+pub use self::value::{Interp, Value, ValueError};
+pub use self::vectored::{encode_all_vectored, encode_refs_vectored};
+pub use self::visit::{walk, walk_with, Visit, VisitFlow};
+pub use self::writer::{start_frame, CtlvStreamWriter, CtlvWriter, DeferredFrameWriter,
+                        ValueWriter};
+
+/// The maximum length of the header of a ctlv, i.e. of its type and length varu64s.
+pub const MAX_HEADER_LENGTH: usize = 18;
+
+/// The smallest type whose ctlvs encode the length of their value explicitly. Smaller types
+/// imply the length of their value instead, see
+/// [`type_implied_length`](fn.type_implied_length.html).
+pub const EXPLICIT_LENGTH_MIN_TYPE: u64 = 128;
+
+// Values up to this length are written together with the header by `encode_write`.
+const COALESCE_LENGTH: usize = 64;
+
+/// Returns the length implied by a type below 128, i.e. `2 ^ (type_ >> 3)`.
+///
+/// Types of 128 or more do not imply a length (it is encoded explicitly instead), for those
+/// this returns `0`. Since this is a `const fn`, it can be used to size arrays:
+///
+/// ```
+/// # use ctlv::type_implied_length;
+/// let buf = [0u8; type_implied_length(8)];
+/// assert_eq!(buf.len(), 2);
+/// ```
+pub const fn type_implied_length(type_: u64) -> usize {
+    if type_ < EXPLICIT_LENGTH_MIN_TYPE {
+        1 << (type_ >> 3)
+    } else {
+        0
+    }
+}
+
+// Checks that a value of length `len` fits the type, i.e. that the type implies no length or
+// exactly `len`.
+pub(crate) fn check_implied_length(type_: u64, len: usize) -> Result<(), ImpliedLengthError> {
+    match type_implied_length(type_) {
+        0 => Ok(()),
+        implied if implied == len => Ok(()),
+        implied => {
+            Err(ImpliedLengthError {
+                    type_,
+                    implied,
+                    actual: len,
+                })
+        }
+    }
+}
+
+// Encodes the header of a ctlv with the given type and length of value, returning how many
+// bytes have been written. Panics if `out` is too short to hold the header.
+pub(crate) fn encode_header(type_: u64, len: u64, out: &mut [u8]) -> usize {
+    let mut total = varu64::encode(type_, out);
+    if type_ >= EXPLICIT_LENGTH_MIN_TYPE {
+        total += varu64::encode(len, &mut out[total..]);
+    }
+    total
+}
 
 /// Everything that can go wrong when decoding a ctlv.
+///
+/// More variants may be added in the future, so matches on this need a wildcard arm.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
 pub enum DecodeError {
     /// Decoding the type failed with the wrapped error.
     Type(VarU64Error),
@@ -18,15 +183,31 @@ pub enum DecodeError {
     Length(VarU64Error),
     /// The slice contains less data than the encoding needs.
     ///
-    /// This is only used in these three cases:
-    ///
-    /// - the input is the empty slice
-    /// - the input ended after the type
-    /// - not enough data for the `value` is available
-    ///
-    /// End of input inside the `type` or `length` varu64 is signaled via the
-    /// `Type` and `Length` variants respectively.
+    /// This is used whenever the input ends before the ctlv is complete, be it inside the
+    /// `type`, inside the `length`, or inside the `value`. The `Type` and `Length` variants
+    /// are thus only used for malformed (non-canonical) varu64s, never for incomplete ones, so
+    /// that streaming decoders can tell "wait for more data" apart from "reject the input".
     UnexpectedEndOfInput,
+    /// The (declared or implied) length of the value exceeds the maximum the decoder was
+    /// configured to accept.
+    ///
+    /// This is reported right after decoding the header, before any space for the value is
+    /// allocated or any of it is read.
+    LengthTooLarge {
+        /// The length of the value according to the header.
+        declared: u64,
+        /// The maximum length of a value.
+        max: usize,
+    },
+    /// The declared length of the value exceeds the remaining input. Only reported by
+    /// [`decode_checked`](struct.CtlvRef.html#method.decode_checked), other functions report
+    /// this as `UnexpectedEndOfInput`.
+    LengthExceedsInput {
+        /// The length of the value according to the header.
+        declared: u64,
+        /// How many bytes of input follow the header.
+        available: usize,
+    },
 }
 use self::DecodeError::*;
 
@@ -36,22 +217,218 @@ impl fmt::Display for DecodeError {
             Type(e) => write!(f, "Invalid ctlv type: {}", e),
             Length(e) => write!(f, "Invalid ctlv length: {}", e),
             UnexpectedEndOfInput => write!(f, "Invalid ctlv: Not enough input bytes"),
+            LengthTooLarge { declared, max } => {
+                write!(f,
+                       "Invalid ctlv: value of {} bytes exceeds the maximum of {}",
+                       declared,
+                       max)
+            }
+            LengthExceedsInput { declared, available } => {
+                write!(f,
+                       "Invalid ctlv: value of {} bytes exceeds the {} remaining input bytes",
+                       declared,
+                       available)
+            }
         }
     }
 }
 
 impl error::Error for DecodeError {}
 
-/// A type-length-value triple that owns its value.
+/// Configures the checks performed by
+/// [`CtlvRef::decode_with`](struct.CtlvRef.html#method.decode_with).
+///
+/// The default configuration performs the same checks as `CtlvRef::decode`. Input that ends
+/// too early is reported as `DecodeError::UnexpectedEndOfInput` regardless of the
+/// configuration.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecodeConfig {
+    /// The maximum length of a value, explicit or implied. Longer values are rejected with
+    /// `DecodeError::LengthTooLarge`, before checking whether the input contains them. `None`
+    /// (the default) accepts any length.
+    pub max_value_len: Option<usize>,
+    /// Whether to reject types and lengths that are not encoded as the shortest possible
+    /// varu64, with `DecodeError::Type` or `DecodeError::Length`. Defaults to `true`. If
+    /// `false`, such encodings are accepted, so re-encoding a decoded ctlv may not reproduce
+    /// its input.
+    pub require_canonical: bool,
+}
+
+impl Default for DecodeConfig {
+    /// Accepts values of any length, and only canonical encodings.
+    fn default() -> DecodeConfig {
+        DecodeConfig {
+            max_value_len: None,
+            require_canonical: true,
+        }
+    }
+}
+
+/// The error returned by `encode_exact` if the output buffer does not have exactly the
+/// length of the encoding.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BufferSizeError {
+    /// The length of the encoding.
+    pub expected: usize,
+    /// The length of the output buffer.
+    pub actual: usize,
+}
+
+impl fmt::Display for BufferSizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        write!(f,
+               "Invalid buffer size: expected {} bytes, got {}",
+               self.expected,
+               self.actual)
+    }
+}
+
+impl error::Error for BufferSizeError {}
+
+/// Everything that can go wrong in [`Ctlv::from_hex`](struct.CtlvGeneric.html#method.from_hex).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HexDecodeError {
+    /// The string contains an odd number of hex digits.
+    OddLength,
+    /// The string contains a character at the given byte offset that is neither a hex digit
+    /// nor ASCII whitespace between two bytes.
+    InvalidChar(usize),
+    /// The decoded bytes do not start with a valid ctlv.
+    Decode(DecodeError),
+}
+
+impl fmt::Display for HexDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        match self {
+            HexDecodeError::OddLength => write!(f, "Invalid hex: odd number of digits"),
+            HexDecodeError::InvalidChar(offset) => {
+                write!(f, "Invalid hex: unexpected character at offset {}", offset)
+            }
+            HexDecodeError::Decode(e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for HexDecodeError {}
+
+/// Everything that can go wrong in
+/// [`CtlvRef::decode_one`](struct.CtlvRef.html#method.decode_one).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DecodeOneError {
+    /// The input does not start with a valid ctlv.
+    Decode(DecodeError),
+    /// The input contains more bytes after the ctlv.
+    Trailing {
+        /// The length of the encoding of the ctlv.
+        consumed: usize,
+        /// How many bytes follow the ctlv.
+        remaining: usize,
+    },
+}
+
+impl fmt::Display for DecodeOneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        match self {
+            DecodeOneError::Decode(e) => e.fmt(f),
+            DecodeOneError::Trailing { consumed, remaining } => {
+                write!(f,
+                       "Expected a single ctlv of {} bytes, got {} trailing bytes",
+                       consumed,
+                       remaining)
+            }
+        }
+    }
+}
+
+impl error::Error for DecodeOneError {}
+
+/// The type of a ctlv and the length of its value, i.e. everything of it but the value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Header {
+    /// The type of the ctlv.
+    pub type_: u64,
+    /// The length of the value of the ctlv.
+    pub len: u64,
+}
+
+/// A type-length-value triple that owns its value, stored in a container of type `B`.
+///
+/// Most code uses the [`Ctlv`](type.Ctlv.html) alias, which stores the value in a `Vec<u8>`.
+/// Other containers trade flexibility for memory: a `CtlvGeneric<Box<[u8]>>` carries no
+/// spare capacity, which adds up when keeping many decoded ctlvs around.
+///
+/// The value is only accessible through methods such as [`value`](#method.value),
+/// [`value_mut`](#method.value_mut) and [`into_value`](#method.into_value), so that future
+/// versions can enforce invariants on it. Create a ctlv with [`new`](#method.new).
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct Ctlv {
+pub struct CtlvGeneric<B> {
     /// The type of the triple.
     pub type_: u64,
-    /// The value, from which the length can be derived.
-    pub value: Vec<u8>,
+    // The value, from which the length can be derived.
+    value: B,
 }
 
-impl Ctlv {
+/// A type-length-value triple that owns its value.
+pub type Ctlv = CtlvGeneric<Vec<u8>>;
+
+/// A type-length-value triple that shares ownership of its value.
+///
+/// Cloning a `CtlvArc` only clones the `Arc`, so the value can be handed out to many
+/// consumers without copying it.
+pub type CtlvArc = CtlvGeneric<Arc<[u8]>>;
+
+impl<B> CtlvGeneric<B> {
+    /// Creates a ctlv of the given type and value.
+    ///
+    /// This does not check that the length of the value matches the length implied by the type,
+    /// if any. Encoding a ctlv for which it does not yields bytes that do not decode to it.
+    ///
+    /// ```
+    /// # use ctlv::Ctlv;
+    /// let ctlv = Ctlv::new(300, vec![0xab]);
+    /// assert_eq!(ctlv.value(), [0xab]);
+    /// ```
+    pub fn new(type_: u64, value: B) -> CtlvGeneric<B> {
+        CtlvGeneric { type_, value }
+    }
+
+    /// Consumes this ctlv, returning its value without copying it.
+    pub fn into_value(self) -> B {
+        self.value
+    }
+
+    /// Returns a mutable reference to the value container.
+    ///
+    /// For a `Ctlv`, this is the `Vec<u8>` itself, so the value can be resized in place.
+    pub fn value_mut(&mut self) -> &mut B {
+        &mut self.value
+    }
+
+    /// Replaces the value.
+    pub fn set_value(&mut self, value: B) {
+        self.value = value;
+    }
+
+    /// Returns this ctlv with its value replaced.
+    ///
+    /// ```
+    /// # use ctlv::Ctlv;
+    /// let ctlv = Ctlv::new(128, vec![]).with_value(vec![1, 2]);
+    /// assert_eq!(ctlv.encode_vec(), [128, 2, 1, 2]);
+    /// ```
+    pub fn with_value(mut self, value: B) -> CtlvGeneric<B> {
+        self.set_value(value);
+        self
+    }
+}
+
+impl<B: AsRef<[u8]>> CtlvGeneric<B> {
+    /// Returns the value of this ctlv.
+    pub fn value(&self) -> &[u8] {
+        self.value.as_ref()
+    }
+
     /// Return how many bytes the encoding of the `Ctlv` will take up.
     pub fn encoding_length(&self) -> usize {
         self.as_ctlv_ref().encoding_length()
@@ -65,11 +442,35 @@ impl Ctlv {
         self.as_ctlv_ref().encode(out)
     }
 
+    /// Returns how many bytes the header of the encoding takes up, see
+    /// [`CtlvRef::header_len`](struct.CtlvRef.html#method.header_len).
+    pub fn header_len(&self) -> usize {
+        self.as_ctlv_ref().header_len()
+    }
+
+    /// Encodes only the header of this `Ctlv` into the output buffer, see
+    /// [`CtlvRef::encode_header`](struct.CtlvRef.html#method.encode_header).
+    pub fn encode_header(&self, out: &mut [u8]) -> usize {
+        self.as_ctlv_ref().encode_header(out)
+    }
+
+    /// Encodes this `Ctlv` into the output buffer, which must have exactly the length of the
+    /// encoding.
+    pub fn encode_exact(&self, out: &mut [u8]) -> Result<(), BufferSizeError> {
+        self.as_ctlv_ref().encode_exact(out)
+    }
+
     /// Encodes this `Ctlv` into the writer, returning how many bytes have been written.
     pub fn encode_write<W: io::Write>(&self, w: W) -> Result<usize, io::Error> {
         self.as_ctlv_ref().encode_write(w)
     }
 
+    /// Encodes this `Ctlv` into the writer with a single vectored write, returning how many
+    /// bytes have been written.
+    pub fn encode_write_vectored<W: io::Write>(&self, w: &mut W) -> Result<usize, io::Error> {
+        self.as_ctlv_ref().encode_write_vectored(w)
+    }
+
     /// Encodes this `Ctlv` as an owned `Vec<u8>`.
     pub fn encode_vec(&self) -> Vec<u8> {
         self.as_ctlv_ref().encode_vec()
@@ -80,33 +481,272 @@ impl Ctlv {
         self.as_ctlv_ref().encode_string()
     }
 
+    /// Returns a `CtlvRef` that borrows its value from this `Ctlv`.
+    pub fn as_ctlv_ref(&self) -> CtlvRef<'_> {
+        CtlvRef {
+            type_: self.type_,
+            value: self.value.as_ref(),
+        }
+    }
+
+    /// Returns the value as a reference to an array, if it is exactly `N` bytes long.
+    pub fn as_array<const N: usize>(&self) -> Option<&[u8; N]> {
+        self.value.as_ref().try_into().ok()
+    }
+
+    /// Returns an iterator over the bytes of the value.
+    pub fn bytes(&self) -> std::slice::Iter<'_, u8> {
+        self.value.as_ref().iter()
+    }
+
+    /// Returns whether this ctlv has the given type.
+    pub fn matches_type(&self, type_: u64) -> bool {
+        self.as_ctlv_ref().matches_type(type_)
+    }
+
+    /// Returns whether the value of this ctlv equals `other`, regardless of the type.
+    pub fn value_eq(&self, other: &[u8]) -> bool {
+        self.as_ctlv_ref().value_eq(other)
+    }
+
+    /// Returns whether the length of the value is implied by the type, i.e. whether the type is
+    /// less than 128.
+    pub fn is_implied_length(&self) -> bool {
+        self.as_ctlv_ref().is_implied_length()
+    }
+
+    /// Returns whether the length of the value is encoded explicitly, i.e. whether the type is
+    /// at least 128.
+    pub fn is_explicit_length(&self) -> bool {
+        self.as_ctlv_ref().is_explicit_length()
+    }
+
+    /// Compares the encodings of two ctlvs, without actually encoding them.
+    pub fn cmp_encoded<C: AsRef<[u8]>>(&self, other: &CtlvGeneric<C>) -> Ordering {
+        self.as_ctlv_ref().cmp_encoded(&other.as_ctlv_ref())
+    }
+
+    /// Feeds the encoding of this ctlv into the hasher, without actually encoding it.
+    ///
+    /// See [`CtlvRef::hash_into`](struct.CtlvRef.html#method.hash_into) for how this
+    /// differs from the derived `Hash`.
+    pub fn hash_into<H: Hasher>(&self, h: &mut H) {
+        self.as_ctlv_ref().hash_into(h)
+    }
+
+    /// Returns the BLAKE3 hash of the encoding of this ctlv.
+    ///
+    /// See [`CtlvRef::canonical_id`](struct.CtlvRef.html#method.canonical_id) for details.
+    #[cfg(feature = "blake3")]
+    pub fn canonical_id(&self) -> [u8; 32] {
+        self.as_ctlv_ref().canonical_id()
+    }
+}
+
+impl<B: AsMut<[u8]>> CtlvGeneric<B> {
+    /// Returns a `CtlvRefMut` that mutably borrows its value from this `Ctlv`.
+    pub fn as_ctlv_ref_mut(&mut self) -> CtlvRefMut<'_> {
+        CtlvRefMut {
+            type_: self.type_,
+            value: self.value.as_mut(),
+        }
+    }
+}
+
+impl Ctlv {
     /// Decode a `Ctlv` from the input buffer, returning it and the remaining input.
+    #[allow(clippy::type_complexity)]
     pub fn decode(input: &[u8]) -> Result<(Ctlv, &[u8]), (DecodeError, &[u8])> {
         let (tmp, tail) = CtlvRef::decode(input)?;
-        let mut value = Vec::with_capacity(tmp.value.len());
-        value.extend_from_slice(&tmp.value);
+        Ok((tmp.into_owned(), tail))
+    }
+
+    /// Decode a `Ctlv` from an input that must consist of exactly one ctlv, see
+    /// [`CtlvRef::decode_one`](struct.CtlvRef.html#method.decode_one).
+    pub fn decode_one(input: &[u8]) -> Result<Ctlv, DecodeOneError> {
+        CtlvRef::decode_one(input).map(CtlvRef::into_owned)
+    }
 
+    /// Decode a `Ctlv` from the input buffer like [`decode`](#method.decode), but with no
+    /// spare capacity in the value, i.e. `value.capacity() == value.len()`.
+    #[allow(clippy::type_complexity)]
+    pub fn decode_compact(input: &[u8]) -> Result<(Ctlv, &[u8]), (DecodeError, &[u8])> {
+        let (tmp, tail) = CtlvRef::decode(input)?;
         Ok((Ctlv {
                 type_: tmp.type_,
-                value,
+                value: Box::<[u8]>::from(tmp.value).into_vec(),
             },
             tail))
     }
 
-    /// Returns a `CtlvRef` that borrows its value from this `Ctlv`.
-    pub fn as_ctlv_ref(&self) -> CtlvRef {
-        CtlvRef {
-            type_: self.type_,
-            value: &self.value,
-        }
+    /// Decode a `Ctlv` from the input buffer like
+    /// [`CtlvRef::decode_checked`](struct.CtlvRef.html#method.decode_checked).
+    pub fn decode_checked(input: &[u8]) -> Result<(Ctlv, &[u8]), DecodeError> {
+        let (tmp, tail) = CtlvRef::decode_checked(input)?;
+        Ok((tmp.into_owned(), tail))
     }
 
-    /// Returns a `CtlvRefMut` that mutably borrows its value from this `Ctlv`.
-    pub fn as_ctlv_ref_mut(&mut self) -> CtlvRefMut {
-        CtlvRefMut {
-            type_: self.type_,
-            value: &mut self.value,
+    /// Decode a `Ctlv` from the input buffer into `dst`, returning the remaining input.
+    ///
+    /// This reuses the allocation of the value of `dst`, so decoding many ctlvs one after the
+    /// other only allocates when a value is larger than all previous ones. On error, `dst` is
+    /// left unchanged.
+    pub fn decode_into<'a>(input: &'a [u8],
+                           dst: &mut Ctlv)
+                           -> Result<&'a [u8], (DecodeError, &'a [u8])> {
+        let (tmp, tail) = CtlvRef::decode(input)?;
+        dst.type_ = tmp.type_;
+        dst.value.clear();
+        dst.value.extend_from_slice(tmp.value);
+        Ok(tail)
+    }
+
+    /// Shortens the value to `len` bytes. Has no effect if the value is not longer than that.
+    pub fn truncate_value(&mut self, len: usize) {
+        self.value.truncate(len);
+    }
+
+    /// Removes all bytes from the value.
+    pub fn clear_value(&mut self) {
+        self.value.clear();
+    }
+
+    /// Shrinks the capacity of the value as much as possible, see `Vec::shrink_to_fit`.
+    pub fn shrink_to_fit(&mut self) {
+        self.value.shrink_to_fit();
+    }
+
+    /// Decode a `Ctlv` from a string of hex digits, returning it and how many bytes (pairs of
+    /// digits) of its encoding were consumed.
+    ///
+    /// Upper- and lowercase digits are accepted, and ASCII whitespace is ignored between bytes,
+    /// so dumps such as `"80 02 ab 01"` can be pasted as they are. All of the string must be
+    /// valid hex, even the part following the ctlv.
+    pub fn from_hex(s: &str) -> Result<(Ctlv, usize), HexDecodeError> {
+        let mut bytes = Vec::with_capacity(s.len() / 2);
+        let mut high = None;
+        for (offset, c) in s.char_indices() {
+            match (c.to_digit(16), high) {
+                (Some(digit), None) => high = Some(digit as u8),
+                (Some(digit), Some(h)) => {
+                    bytes.push(h << 4 | digit as u8);
+                    high = None;
+                }
+                (None, None) if c.is_ascii_whitespace() => {}
+                (None, _) => return Err(HexDecodeError::InvalidChar(offset)),
+            }
+        }
+        if high.is_some() {
+            return Err(HexDecodeError::OddLength);
         }
+
+        let (tmp, _) = CtlvRef::decode(&bytes).map_err(|(e, _)| HexDecodeError::Decode(e))?;
+        Ok((tmp.to_owned(), tmp.encoding_length()))
+    }
+}
+
+impl CtlvArc {
+    /// Decode a `CtlvArc` from the input buffer, returning it and the remaining input.
+    #[allow(clippy::type_complexity)]
+    pub fn decode(input: &[u8]) -> Result<(CtlvArc, &[u8]), (DecodeError, &[u8])> {
+        let (tmp, tail) = CtlvRef::decode(input)?;
+        Ok((CtlvGeneric {
+                type_: tmp.type_,
+                value: tmp.value.into(),
+            },
+            tail))
+    }
+}
+
+impl CtlvGeneric<Box<[u8]>> {
+    /// Decode a `CtlvGeneric<Box<[u8]>>` from the input buffer, returning it and the remaining
+    /// input. The allocation of the value is exactly as large as the value.
+    #[allow(clippy::type_complexity)]
+    pub fn decode(input: &[u8]) -> Result<(CtlvGeneric<Box<[u8]>>, &[u8]), (DecodeError, &[u8])> {
+        let (tmp, tail) = CtlvRef::decode(input)?;
+        Ok((CtlvGeneric {
+                type_: tmp.type_,
+                value: tmp.value.into(),
+            },
+            tail))
+    }
+}
+
+/// A wrapper around a ctlv that compares by encoding (see
+/// [`CtlvRef::cmp_encoded`](struct.CtlvRef.html#method.cmp_encoded)) rather than by fields.
+///
+/// This allows keeping ctlvs in sorted collections such as a `BTreeSet` in the order of their
+/// encodings, without encoding them for every comparison.
+#[derive(Debug, Clone)]
+pub struct OrdByEncoding<B>(pub CtlvGeneric<B>);
+
+impl<B: AsRef<[u8]>> std::hash::Hash for OrdByEncoding<B> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.type_.hash(state);
+        self.0.value().hash(state);
+    }
+}
+
+impl<B: AsRef<[u8]>> PartialEq for OrdByEncoding<B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<B: AsRef<[u8]>> Eq for OrdByEncoding<B> {}
+
+impl<B: AsRef<[u8]>> PartialOrd for OrdByEncoding<B> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<B: AsRef<[u8]>> Ord for OrdByEncoding<B> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp_encoded(&other.0)
+    }
+}
+
+/// A wrapper around a reference to a ctlv that compares and hashes **only the value**,
+/// ignoring the type.
+///
+/// Two ctlvs of different types with the same value are equal as `ByValue`s, unlike with the
+/// derived implementations on the ctlv itself. This is useful for grouping ctlvs by value, e.g.
+/// as the key of a `HashMap`. Values are ordered lexicographically.
+#[derive(Debug)]
+pub struct ByValue<'a, B = Vec<u8>>(pub &'a CtlvGeneric<B>);
+
+impl<'a, B> Clone for ByValue<'a, B> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, B> Copy for ByValue<'a, B> {}
+
+impl<'a, B: AsRef<[u8]>> std::hash::Hash for ByValue<'a, B> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.value().hash(state);
+    }
+}
+
+impl<'a, B: AsRef<[u8]>> PartialEq for ByValue<'a, B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.value() == other.0.value()
+    }
+}
+
+impl<'a, B: AsRef<[u8]>> Eq for ByValue<'a, B> {}
+
+impl<'a, B: AsRef<[u8]>> PartialOrd for ByValue<'a, B> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a, B: AsRef<[u8]>> Ord for ByValue<'a, B> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.value().cmp(other.0.value())
     }
 }
 
@@ -123,13 +763,13 @@ impl<'a> CtlvRef<'a> {
     /// Return how many bytes the encoding of the `CtlvRef` will take up.
     pub fn encoding_length(&self) -> usize {
         let length = self.value.len();
-        let length_len = if self.type_ < 128 {
+        let length_len = if self.is_implied_length() {
             0
         } else {
             varu64::encoding_length(length as u64)
         };
 
-        return varu64::encoding_length(self.type_) + length_len + length;
+        varu64::encoding_length(self.type_) + length_len + length
     }
 
     /// Encodes this `CtlvRef` into the output buffer, returning how many bytes have been written.
@@ -140,27 +780,127 @@ impl<'a> CtlvRef<'a> {
         let mut total = varu64::encode(self.type_, out);
         let length: usize = self.value.len();
 
-        if self.type_ >= 128 {
+        if self.is_explicit_length() {
             total += varu64::encode(length as u64, &mut out[total..]);
         }
 
-        &mut out[total..total + length].copy_from_slice(self.value);
+        out[total..total + length].copy_from_slice(self.value);
+
+        total + length
+    }
+
+    /// Encodes this `CtlvRef` into the output buffer, which must have exactly the length of the
+    /// encoding.
+    ///
+    /// Unlike `encode`, this also rejects buffers that are too large, so no stale trailing
+    /// bytes can end up after the encoding.
+    pub fn encode_exact(&self, out: &mut [u8]) -> Result<(), BufferSizeError> {
+        let expected = self.encoding_length();
+        if out.len() == expected {
+            self.encode(out);
+            Ok(())
+        } else {
+            Err(BufferSizeError {
+                    expected,
+                    actual: out.len(),
+                })
+        }
+    }
+
+    /// Encodes this `CtlvRef` into uninitialized memory, returning how many bytes have been
+    /// written.
+    ///
+    /// This spares zeroing a buffer before encoding into it. When this returns `n`, the first
+    /// `n` bytes of `out` have been initialized, so the caller may treat them as initialized,
+    /// e.g. via `MaybeUninit::assume_init` or by setting the length of the `Vec` whose spare
+    /// capacity `out` is. The remaining bytes of `out` are not touched.
+    ///
+    /// # Panics
+    /// Panics if the buffer is not large enough to hold the encoding, in which case it is
+    /// unspecified how many bytes have been initialized.
+    pub fn encode_uninit(&self, out: &mut [MaybeUninit<u8>]) -> usize {
+        let mut header = [0; MAX_HEADER_LENGTH];
+        let header_len = self.encode_header(&mut header);
+        let total = header_len + self.value.len();
+        let (out_header, out_value) = out[..total].split_at_mut(header_len);
+
+        for (slot, byte) in out_header.iter_mut().zip(&header[..header_len]) {
+            slot.write(*byte);
+        }
+        for (slot, byte) in out_value.iter_mut().zip(self.value) {
+            slot.write(*byte);
+        }
 
-        return total + length;
+        total
     }
 
     /// Encodes this `CtlvRef` into the writer, returning how many bytes have been written.
+    ///
+    /// The type and length are assembled in a buffer on the stack and written together. Values
+    /// of up to 64 bytes are appended to that buffer, so that small ctlvs are written with a
+    /// single `write_all` call, larger values are written with a second one.
     pub fn encode_write<W: io::Write>(&self, mut w: W) -> Result<usize, io::Error> {
-        let mut total = varu64::encode_write(self.type_, &mut w)?;
-        let length: usize = self.value.len();
+        let mut buf = [0; MAX_HEADER_LENGTH + COALESCE_LENGTH];
+        let header_len = self.encode_header(&mut buf);
+        let total = header_len + self.value.len();
 
-        if self.type_ >= 128 {
-            total += varu64::encode_write(length as u64, &mut w)?;
+        if self.value.len() <= COALESCE_LENGTH {
+            buf[header_len..total].copy_from_slice(self.value);
+            w.write_all(&buf[..total])?;
+        } else {
+            w.write_all(&buf[..header_len])?;
+            w.write_all(self.value)?;
         }
 
-        w.write_all(self.value)?;
+        Ok(total)
+    }
+
+    /// Encodes this `CtlvRef` into the writer, returning how many bytes have been written.
+    ///
+    /// The type and length are assembled in a buffer on the stack, and then written together
+    /// with the value via `write_vectored`. If the writer only accepts part of the data, the
+    /// remainder is passed to `write_vectored` again. Writers that do not support vectored
+    /// writes fall back to writing the header and the value one after the other.
+    pub fn encode_write_vectored<W: io::Write>(&self, w: &mut W) -> Result<usize, io::Error> {
+        let mut header = [0; MAX_HEADER_LENGTH];
+        let header_len = self.encode_header(&mut header);
+
+        vectored::write_all_vectored(w,
+                                     &mut [IoSlice::new(&header[..header_len]),
+                                           IoSlice::new(self.value)])?;
+        Ok(header_len + self.value.len())
+    }
 
-        Ok(total + length)
+    /// Returns how many bytes the header (the type and, if necessary, the length) of the
+    /// encoding takes up.
+    pub fn header_len(&self) -> usize {
+        self.encoding_length() - self.value.len()
+    }
+
+    /// Encodes only the header (the type and, if necessary, the length) of this `CtlvRef` into
+    /// the output buffer, returning how many bytes have been written.
+    ///
+    /// Together with writing the value from its own buffer, this produces the encoding without
+    /// copying the value:
+    ///
+    /// ```
+    /// # use ctlv::{CtlvRef, MAX_HEADER_LENGTH};
+    /// # use std::io::Write;
+    /// let ctlv = CtlvRef { type_: 300, value: &[1, 2, 3] };
+    /// let mut header = [0; MAX_HEADER_LENGTH];
+    /// let header_len = ctlv.encode_header(&mut header);
+    /// assert_eq!(header_len, ctlv.header_len());
+    ///
+    /// let mut out = Vec::new();
+    /// out.write_all(&header[..header_len]).unwrap();
+    /// out.write_all(ctlv.value).unwrap();
+    /// assert_eq!(out, ctlv.encode_vec());
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if the buffer is not large enough to hold the header.
+    pub fn encode_header(&self, out: &mut [u8]) -> usize {
+        encode_header(self.type_, self.value.len() as u64, out)
     }
 
     /// Encodes this `CtlvRef` as an owned `Vec<u8>`.
@@ -176,40 +916,247 @@ impl<'a> CtlvRef<'a> {
     }
 
     /// Decode a `CtlvRef` from the input buffer, returning it and the remaining input.
+    #[allow(clippy::type_complexity)]
     pub fn decode(input: &'a [u8]) -> Result<(CtlvRef<'a>, &'a [u8]), (DecodeError, &'a [u8])> {
-        let type_: u64;
-        let length: usize;
-        let remaining: &'a [u8];
+        CtlvRef::decode_with(input, &DecodeConfig::default())
+    }
 
-        match varu64::decode(input) {
-            Err((_, tail)) if tail.len() == 0 => return Err((UnexpectedEndOfInput, input)),
+    /// Decode a `CtlvRef` from the input buffer like [`decode`](#method.decode), but with the
+    /// checks configured by `config`.
+    #[allow(clippy::type_complexity)]
+    pub fn decode_with(input: &'a [u8],
+                       config: &DecodeConfig)
+                       -> Result<(CtlvRef<'a>, &'a [u8]), (DecodeError, &'a [u8])> {
+        let (type_, tail) = match varu64::decode(input) {
+            Ok(decoded) => decoded,
+            Err((VarU64Error::UnexpectedEndOfInput, _)) => {
+                return Err((UnexpectedEndOfInput, input))
+            }
+            Err((VarU64Error::NonCanonical(t), tail)) if !config.require_canonical => (t, tail),
             Err((e, tail)) => return Err((Type(e), tail)),
-            Ok((t @ 0...127, tail)) => {
-                type_ = t;
-                length = 1 << (type_ >> 3);
-                remaining = tail;
+        };
+
+        let (length, remaining) = if type_ < EXPLICIT_LENGTH_MIN_TYPE {
+            (type_implied_length(type_) as u64, tail)
+        } else {
+            match varu64::decode(tail) {
+                Ok(decoded) => decoded,
+                Err((VarU64Error::UnexpectedEndOfInput, _)) => {
+                    return Err((UnexpectedEndOfInput, tail))
+                }
+                Err((VarU64Error::NonCanonical(len), tail2)) if !config.require_canonical => {
+                    (len, tail2)
+                }
+                Err((e, tail2)) => return Err((Length(e), tail2)),
+            }
+        };
+
+        if let Some(max) = config.max_value_len {
+            if length > max as u64 {
+                return Err((LengthTooLarge { declared: length, max }, remaining));
             }
-            Ok((t, tail)) => {
-                type_ = t;
+        }
 
+        if (remaining.len() as u64) < length {
+            Err((UnexpectedEndOfInput, remaining))
+        } else {
+            let (value, tail) = remaining.split_at(length as usize);
+            Ok((CtlvRef { type_, value }, tail))
+        }
+    }
+
+    /// Decode a `CtlvRef` from an input that must consist of exactly one ctlv, erroring with
+    /// `DecodeOneError::Trailing` if there are more bytes after it.
+    pub fn decode_one(input: &'a [u8]) -> Result<CtlvRef<'a>, DecodeOneError> {
+        match CtlvRef::decode(input) {
+            Ok((frame, [])) => Ok(frame),
+            Ok((_, tail)) => {
+                Err(DecodeOneError::Trailing {
+                        consumed: input.len() - tail.len(),
+                        remaining: tail.len(),
+                    })
+            }
+            Err((e, _)) => Err(DecodeOneError::Decode(e)),
+        }
+    }
+
+    /// Decode a `CtlvRef` from the input buffer like [`decode`](#method.decode), but for ctlvs
+    /// with an explicit length, report a length that exceeds the remaining input as
+    /// `DecodeError::LengthExceedsInput` rather than `DecodeError::UnexpectedEndOfInput`.
+    ///
+    /// This tells apart an input that simply ends early from one with an absurd length, as long
+    /// as the input is known to be complete, e.g. when decoding a file.
+    pub fn decode_checked(input: &'a [u8]) -> Result<(CtlvRef<'a>, &'a [u8]), DecodeError> {
+        match CtlvRef::decode(input) {
+            Ok(decoded) => Ok(decoded),
+            Err((UnexpectedEndOfInput, _)) => {
+                // The header has been decoded successfully, unless the input ends within it.
+                let tail = match varu64::decode(input) {
+                    Ok((type_, tail)) if type_ >= EXPLICIT_LENGTH_MIN_TYPE => tail,
+                    _ => return Err(UnexpectedEndOfInput),
+                };
                 match varu64::decode(tail) {
-                    Err((e, tail2)) => return Err((Length(e), tail2)),
-                    Ok((len, tail2)) => {
-                        length = len as usize;
-                        remaining = tail2;
+                    Ok((declared, value)) => {
+                        Err(LengthExceedsInput {
+                                declared,
+                                available: value.len(),
+                            })
                     }
+                    Err(_) => Err(UnexpectedEndOfInput),
                 }
             }
+            Err((e, _)) => Err(e),
         }
+    }
 
-        if remaining.len() < length {
-            return Err((UnexpectedEndOfInput, remaining));
+    /// Decode a `CtlvRef` whose header and value are stored in separate buffers, e.g. because
+    /// they are split across the wrap point of a ring buffer.
+    ///
+    /// The type and length are decoded from the start of `header`, and the value consists of
+    /// the corresponding number of bytes from the start of `value`. Any bytes following the
+    /// header or the value are ignored. Errors with `DecodeError::UnexpectedEndOfInput` if
+    /// either buffer is too short.
+    pub fn decode_split(header: &'a [u8], value: &'a [u8]) -> Result<CtlvRef<'a>, DecodeError> {
+        let (type_, tail) = match varu64::decode(header) {
+            Ok(decoded) => decoded,
+            Err((VarU64Error::UnexpectedEndOfInput, _)) => return Err(UnexpectedEndOfInput),
+            Err((e, _)) => return Err(Type(e)),
+        };
+        let length = if type_ < EXPLICIT_LENGTH_MIN_TYPE {
+            type_implied_length(type_) as u64
         } else {
-            return Ok((CtlvRef {
-                           type_,
-                           value: &remaining[..length],
-                       },
-                       &remaining[length..]));
+            match varu64::decode(tail) {
+                Ok((length, _)) => length,
+                Err((VarU64Error::UnexpectedEndOfInput, _)) => return Err(UnexpectedEndOfInput),
+                Err((e, _)) => return Err(Length(e)),
+            }
+        };
+
+        if (value.len() as u64) < length {
+            Err(UnexpectedEndOfInput)
+        } else {
+            Ok(CtlvRef {
+                   type_,
+                   value: &value[..length as usize],
+               })
+        }
+    }
+
+    /// Decode a `CtlvRef` from the input buffer like [`decode`](#method.decode), but return how
+    /// many bytes have been consumed instead of the remaining input. On error, this is the
+    /// offset at which `decode` would have returned the remaining input.
+    pub fn decode_consumed(input: &'a [u8]) -> Result<(CtlvRef<'a>, usize), (DecodeError, usize)> {
+        match CtlvRef::decode(input) {
+            Ok((frame, tail)) => Ok((frame, input.len() - tail.len())),
+            Err((e, tail)) => Err((e, input.len() - tail.len())),
+        }
+    }
+
+    /// Returns the value as a reference to an array, if it is exactly `N` bytes long.
+    ///
+    /// This is convenient for the types with implied lengths:
+    ///
+    /// ```
+    /// # use ctlv::CtlvRef;
+    /// let ctlv = CtlvRef { type_: 8, value: &[1, 2] };
+    /// assert_eq!(ctlv.as_array::<2>().map(|arr| u16::from_be_bytes(*arr)), Some(258));
+    /// ```
+    pub fn as_array<const N: usize>(&self) -> Option<&'a [u8; N]> {
+        self.value.try_into().ok()
+    }
+
+    /// Returns an iterator over the bytes of the value.
+    pub fn bytes(&self) -> std::slice::Iter<'a, u8> {
+        self.value.iter()
+    }
+
+    /// Returns whether this ctlv has the given type.
+    pub fn matches_type(&self, type_: u64) -> bool {
+        self.type_ == type_
+    }
+
+    /// Returns whether the value of this ctlv equals `other`, regardless of the type.
+    ///
+    /// ```
+    /// # use ctlv::CtlvRef;
+    /// assert!(CtlvRef { type_: 0, value: &[0x2a] }.value_eq(&[0x2a]));
+    /// ```
+    pub fn value_eq(&self, other: &[u8]) -> bool {
+        self.value == other
+    }
+
+    /// Returns whether the length of the value is implied by the type, i.e. whether the type is
+    /// less than 128.
+    pub fn is_implied_length(&self) -> bool {
+        self.type_ < EXPLICIT_LENGTH_MIN_TYPE
+    }
+
+    /// Returns whether the length of the value is encoded explicitly, i.e. whether the type is
+    /// at least 128.
+    pub fn is_explicit_length(&self) -> bool {
+        !self.is_implied_length()
+    }
+
+    /// Compares the encodings of two ctlvs, without actually encoding them.
+    ///
+    /// This differs from the derived `Ord`, which compares the values lexicographically if the
+    /// types are equal. In the encoding, ctlvs with an explicit length are ordered by the length
+    /// first, so e.g. a value `[2]` sorts before a value `[1, 1]`.
+    pub fn cmp_encoded(&self, other: &CtlvRef) -> Ordering {
+        // No header is a proper prefix of another one, so comparing the headers first and then
+        // the values yields the same result as comparing the full encodings.
+        let mut header = [0; MAX_HEADER_LENGTH];
+        let header_len = self.encode_header(&mut header);
+        let mut other_header = [0; MAX_HEADER_LENGTH];
+        let other_header_len = other.encode_header(&mut other_header);
+
+        header[..header_len]
+            .cmp(&other_header[..other_header_len])
+            .then_with(|| self.value.cmp(other.value))
+    }
+
+    /// Feeds the encoding of this ctlv into the hasher, without allocating a buffer for it.
+    ///
+    /// The derived `Hash` hashes the fields of the struct, so the result depends on how `Hash`
+    /// is implemented for the value type (slices, for example, also hash their length). This
+    /// hashes exactly the bytes of the encoding instead, so any two ctlvs with the same encoding
+    /// hash the same, and the hash can be reproduced by anyone hashing the encoded bytes.
+    ///
+    /// The bytes are passed to the hasher in more than one `write` call, so hashers for which
+    /// this makes a difference do not compute the same result as when hashing the encoding in
+    /// a single call. Streaming hashers, such as the std `DefaultHasher`, are not affected.
+    pub fn hash_into<H: Hasher>(&self, h: &mut H) {
+        let mut header = [0; MAX_HEADER_LENGTH];
+        let header_len = self.encode_header(&mut header);
+        h.write(&header[..header_len]);
+        h.write(self.value);
+    }
+
+    /// Returns the BLAKE3 hash of the encoding of this ctlv, as a content id.
+    ///
+    /// Unlike the derived `Hash`, this only depends on the encoding, which is canonical, so it
+    /// is stable across platforms and versions of this crate, and anyone can compute it by
+    /// hashing the encoded bytes.
+    #[cfg(feature = "blake3")]
+    pub fn canonical_id(&self) -> [u8; 32] {
+        let mut header = [0; MAX_HEADER_LENGTH];
+        let header_len = self.encode_header(&mut header);
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&header[..header_len]);
+        hasher.update(self.value);
+        *hasher.finalize().as_bytes()
+    }
+
+    /// Converts this `CtlvRef` into a `Ctlv` that owns a copy of the value.
+    pub fn into_owned(self) -> Ctlv {
+        self.to_owned()
+    }
+
+    /// Returns a `Ctlv` that owns a copy of the value of this `CtlvRef`.
+    pub fn to_owned(&self) -> Ctlv {
+        Ctlv {
+            type_: self.type_,
+            value: self.value.to_vec(),
         }
     }
 }
@@ -237,11 +1184,23 @@ impl<'a> CtlvRefMut<'a> {
         self.as_ctlv_ref().encode(out)
     }
 
+    /// Encodes this `CtlvRefMut` into the output buffer, which must have exactly the length of
+    /// the encoding.
+    pub fn encode_exact(&self, out: &mut [u8]) -> Result<(), BufferSizeError> {
+        self.as_ctlv_ref().encode_exact(out)
+    }
+
     /// Encodes this `CtlvRefMut` into the writer, returning how many bytes have been written.
     pub fn encode_write<W: io::Write>(&self, w: W) -> Result<usize, io::Error> {
         self.as_ctlv_ref().encode_write(w)
     }
 
+    /// Encodes this `CtlvRefMut` into the writer with a single vectored write, returning how
+    /// many bytes have been written.
+    pub fn encode_write_vectored<W: io::Write>(&self, w: &mut W) -> Result<usize, io::Error> {
+        self.as_ctlv_ref().encode_write_vectored(w)
+    }
+
     /// Encodes this `CtlvRefMut` as an owned `Vec<u8>`.
     pub fn encode_vec(&self) -> Vec<u8> {
         self.as_ctlv_ref().encode_vec()
@@ -292,13 +1251,162 @@ impl<'a> CtlvRefMut<'a> {
     //     }
     // }
 
+    /// Returns whether this ctlv has the given type.
+    pub fn matches_type(&self, type_: u64) -> bool {
+        self.as_ctlv_ref().matches_type(type_)
+    }
+
+    /// Returns whether the value of this ctlv equals `other`, regardless of the type.
+    pub fn value_eq(&self, other: &[u8]) -> bool {
+        self.as_ctlv_ref().value_eq(other)
+    }
+
+    /// Returns whether the length of the value is implied by the type, i.e. whether the type is
+    /// less than 128.
+    pub fn is_implied_length(&self) -> bool {
+        self.as_ctlv_ref().is_implied_length()
+    }
+
+    /// Returns whether the length of the value is encoded explicitly, i.e. whether the type is
+    /// at least 128.
+    pub fn is_explicit_length(&self) -> bool {
+        self.as_ctlv_ref().is_explicit_length()
+    }
+
     /// Returns a `CtlvRef` that borrows the same value as this `CtlvRefMut`.
-    pub fn as_ctlv_ref(&self) -> CtlvRef {
+    pub fn as_ctlv_ref(&self) -> CtlvRef<'_> {
         CtlvRef {
             type_: self.type_,
-            value: &self.value,
+            value: self.value,
+        }
+    }
+}
+
+/// Decodes an encoding of exactly one ctlv, rejecting any trailing bytes.
+impl<'a> TryFrom<&'a [u8]> for CtlvRef<'a> {
+    type Error = DecodeOneError;
+
+    /// Decodes an input that must consist of exactly one ctlv, see
+    /// [`CtlvRef::decode_one`](struct.CtlvRef.html#method.decode_one).
+    fn try_from(input: &'a [u8]) -> Result<CtlvRef<'a>, DecodeOneError> {
+        CtlvRef::decode_one(input)
+    }
+}
+
+impl TryFrom<&[u8]> for Ctlv {
+    type Error = DecodeOneError;
+
+    /// Decodes an input that must consist of exactly one ctlv, see
+    /// [`CtlvRef::decode_one`](struct.CtlvRef.html#method.decode_one).
+    fn try_from(input: &[u8]) -> Result<Ctlv, DecodeOneError> {
+        Ctlv::decode_one(input)
+    }
+}
+
+impl<B: AsRef<[u8]>> AsRef<[u8]> for CtlvGeneric<B> {
+    fn as_ref(&self) -> &[u8] {
+        self.value.as_ref()
+    }
+}
+
+/// Dereferences to the value, *not* the encoding of the ctlv. So `ctlv.len()` is the length of
+/// the value rather than the `encoding_length`, for example.
+impl<B: AsRef<[u8]>> Deref for CtlvGeneric<B> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.value.as_ref()
+    }
+}
+
+/// Exposes the value, *not* the encoding of the ctlv.
+impl<'a> AsRef<[u8]> for CtlvRef<'a> {
+    fn as_ref(&self) -> &[u8] {
+        self.value
+    }
+}
+
+/// Dereferences to the value, *not* the encoding of the ctlv. So `ctlv.len()` is the length of
+/// the value rather than the `encoding_length`, for example.
+impl<'a> Deref for CtlvRef<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.value
+    }
+}
+
+/// Iterates over the bytes of the value.
+impl<'b, B: AsRef<[u8]>> IntoIterator for &'b CtlvGeneric<B> {
+    type Item = &'b u8;
+    type IntoIter = std::slice::Iter<'b, u8>;
+
+    fn into_iter(self) -> std::slice::Iter<'b, u8> {
+        self.bytes()
+    }
+}
+
+/// Iterates over the bytes of the value.
+impl<'a> IntoIterator for &CtlvRef<'a> {
+    type Item = &'a u8;
+    type IntoIter = std::slice::Iter<'a, u8>;
+
+    fn into_iter(self) -> std::slice::Iter<'a, u8> {
+        self.bytes()
+    }
+}
+
+// Writes the encoding of the ctlv as hex digits, prefixed with `0x` in the alternate form.
+fn fmt_hex(ctlv: CtlvRef,
+           f: &mut fmt::Formatter,
+           upper: bool)
+           -> std::result::Result<(), fmt::Error> {
+    if f.alternate() {
+        f.write_str("0x")?;
+    }
+
+    let mut header = [0; MAX_HEADER_LENGTH];
+    let header_len = ctlv.encode_header(&mut header);
+    for b in header[..header_len].iter().chain(ctlv.value.iter()) {
+        if upper {
+            write!(f, "{:02X}", b)?;
+        } else {
+            write!(f, "{:02x}", b)?;
         }
     }
+    Ok(())
+}
+
+/// Formats the encoding of the ctlv as lowercase hex digits, prefixed with `0x` in the
+/// alternate form (`{:#x}`).
+impl<'a> fmt::LowerHex for CtlvRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        fmt_hex(*self, f, false)
+    }
+}
+
+/// Formats the encoding of the ctlv as uppercase hex digits, prefixed with `0x` in the
+/// alternate form (`{:#X}`).
+impl<'a> fmt::UpperHex for CtlvRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        fmt_hex(*self, f, true)
+    }
+}
+
+/// Formats the encoding of the ctlv as lowercase hex digits, prefixed with `0x` in the
+/// alternate form (`{:#x}`).
+impl<B: AsRef<[u8]>> fmt::LowerHex for CtlvGeneric<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        fmt_hex(self.as_ctlv_ref(), f, false)
+    }
+}
+
+/// Formats the encoding of the ctlv as uppercase hex digits, prefixed with `0x` in the
+/// alternate form (`{:#X}`).
+impl<B: AsRef<[u8]>> fmt::UpperHex for CtlvGeneric<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        fmt_hex(self.as_ctlv_ref(), f, true)
+    }
 }
 
 #[cfg(test)]
@@ -307,6 +1415,7 @@ mod tests {
 
     // Assert that the given Ctlv encodes to the expected encoding, and that the
     // expected encoding decodes to the Ctlv.
+    #[allow(clippy::slow_vector_initialization)]
     fn test_fixture(ctlv: &Ctlv, exp: &[u8]) {
         assert_eq!(ctlv.encoding_length(), exp.len());
         let mut foo = Vec::with_capacity(exp.len());
@@ -359,4 +1468,545 @@ mod tests {
         assert_eq!(Ctlv::decode(&[248, 0, 1, 42]).unwrap_err(),
                    (Type(VarU64Error::NonCanonical(0)), &[1, 42][..]));
     }
+
+    #[test]
+    fn truncated() {
+        assert_eq!(Ctlv::decode(&[249, 1]).unwrap_err(),
+                   (UnexpectedEndOfInput, &[249, 1][..]));
+        assert_eq!(Ctlv::decode(&[128]).unwrap_err(), (UnexpectedEndOfInput, &[][..]));
+        assert_eq!(Ctlv::decode(&[128, 249, 1]).unwrap_err(),
+                   (UnexpectedEndOfInput, &[249, 1][..]));
+        assert_eq!(Ctlv::decode(&[128, 2, 1]).unwrap_err(), (UnexpectedEndOfInput, &[1][..]));
+
+        // Non-canonical varu64s stay errors of their own, even at the end of the input.
+        assert_eq!(Ctlv::decode(&[128, 248, 1]).unwrap_err(),
+                   (Length(VarU64Error::NonCanonical(1)), &[][..]));
+        assert_eq!(Ctlv::decode(&[248, 0]).unwrap_err(),
+                   (Type(VarU64Error::NonCanonical(0)), &[][..]));
+    }
+
+    #[test]
+    fn encode_uninit() {
+        let frames = [CtlvRef { type_: 0, value: &[42] },
+                      CtlvRef { type_: 300, value: &[1, 2, 3] },
+                      CtlvRef { type_: 128, value: &[] }];
+        for frame in &frames {
+            let mut out = Vec::with_capacity(frame.encoding_length() + 4);
+            let len = frame.encode_uninit(out.spare_capacity_mut());
+            assert_eq!(len, frame.encoding_length());
+            // `encode_uninit` initialized the first `len` bytes of the spare capacity.
+            unsafe { out.set_len(len) };
+            assert_eq!(out, frame.encode_vec());
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn encode_uninit_too_small() {
+        let mut out = [MaybeUninit::uninit(); 4];
+        CtlvRef { type_: 300, value: &[1, 2, 3] }.encode_uninit(&mut out);
+    }
+
+    #[test]
+    fn into_owned() {
+        let (borrowed, _) = CtlvRef::decode(&[128, 2, 42, 43]).unwrap();
+        let owned = borrowed.to_owned();
+        assert_eq!(owned.as_ctlv_ref(), borrowed);
+        assert_eq!(borrowed.into_owned(), owned);
+    }
+
+    #[test]
+    fn boxed() {
+        let (boxed, tail) = CtlvGeneric::<Box<[u8]>>::decode(&[128, 2, 42, 43, 0]).unwrap();
+        assert_eq!(tail, &[0][..]);
+        assert_eq!(boxed.type_, 128);
+        assert_eq!(&boxed.value[..], &[42, 43][..]);
+        assert_eq!(boxed.encode_vec(), [128, 2, 42, 43]);
+        assert_eq!(boxed.encoding_length(), 4);
+    }
+
+    #[test]
+    fn decode_with() {
+        let max = DecodeConfig {
+            max_value_len: Some(2),
+            ..DecodeConfig::default()
+        };
+        assert_eq!(CtlvRef::decode_with(&[128, 2, 1, 2], &max),
+                   Ok((CtlvRef { type_: 128, value: &[1, 2] }, &[][..])));
+        assert_eq!(CtlvRef::decode_with(&[128, 3, 1, 2, 3], &max),
+                   Err((LengthTooLarge { declared: 3, max: 2 }, &[1, 2, 3][..])));
+        assert_eq!(CtlvRef::decode_with(&[16, 1, 2, 3, 4], &max),
+                   Err((LengthTooLarge { declared: 4, max: 2 }, &[1, 2, 3, 4][..])));
+        // The length is checked before looking for the value.
+        assert_eq!(CtlvRef::decode_with(&[128, 250, 1, 0, 0], &max),
+                   Err((LengthTooLarge { declared: 65536, max: 2 }, &[][..])));
+
+        let lenient = DecodeConfig {
+            require_canonical: false,
+            ..DecodeConfig::default()
+        };
+        assert_eq!(CtlvRef::decode_with(&[248, 5, 42], &DecodeConfig::default()),
+                   Err((Type(VarU64Error::NonCanonical(5)), &[42][..])));
+        assert_eq!(CtlvRef::decode_with(&[248, 5, 42], &lenient),
+                   Ok((CtlvRef { type_: 5, value: &[42] }, &[][..])));
+        assert_eq!(CtlvRef::decode(&[128, 248, 1, 7]),
+                   Err((Length(VarU64Error::NonCanonical(1)), &[7][..])));
+        assert_eq!(CtlvRef::decode_with(&[128, 248, 1, 7], &lenient),
+                   Ok((CtlvRef { type_: 128, value: &[7] }, &[][..])));
+        assert_eq!(CtlvRef::decode_with(&[128, 248], &lenient),
+                   Err((UnexpectedEndOfInput, &[248][..])));
+    }
+
+    #[test]
+    fn decode_checked() {
+        assert_eq!(Ctlv::decode_checked(&[128, 2, 1, 2, 3]),
+                   Ok((Ctlv { type_: 128, value: vec![1, 2] }, &[3][..])));
+        assert_eq!(Ctlv::decode_checked(&[128, 3, 1, 2]),
+                   Err(LengthExceedsInput { declared: 3, available: 2 }));
+        assert_eq!(CtlvRef::decode_checked(&[128, 255, 255, 255, 255, 255, 255, 255, 255, 255]),
+                   Err(LengthExceedsInput { declared: u64::MAX, available: 0 }));
+        // Incomplete headers, and values of implied lengths, simply end early.
+        assert_eq!(CtlvRef::decode_checked(&[128]), Err(UnexpectedEndOfInput));
+        assert_eq!(CtlvRef::decode_checked(&[128, 249, 1]), Err(UnexpectedEndOfInput));
+        assert_eq!(CtlvRef::decode_checked(&[16, 1, 2]), Err(UnexpectedEndOfInput));
+        assert_eq!(CtlvRef::decode_checked(&[248, 5, 0]),
+                   Err(Type(VarU64Error::NonCanonical(5))));
+    }
+
+    #[test]
+    fn compact() {
+        let input = Ctlv { type_: 300, value: vec![1; 1000] }.encode_vec();
+        let (ctlv, tail) = Ctlv::decode_compact(&input).unwrap();
+        assert_eq!(ctlv.value, vec![1; 1000]);
+        assert_eq!(ctlv.value.capacity(), ctlv.value.len());
+        assert!(tail.is_empty());
+        assert_eq!(Ctlv::decode_compact(&[128, 1]), Err((UnexpectedEndOfInput, &[][..])));
+
+        let mut ctlv = Ctlv {
+            type_: 128,
+            value: Vec::with_capacity(100),
+        };
+        ctlv.value.push(1);
+        ctlv.shrink_to_fit();
+        assert!(ctlv.value.capacity() < 100);
+    }
+
+    #[test]
+    fn decode_one() {
+        assert_eq!(Ctlv::decode_one(&[128, 2, 1, 2]),
+                   Ok(Ctlv { type_: 128, value: vec![1, 2] }));
+        assert_eq!(Ctlv::decode_one(&[128, 2, 1, 2, 0, 1, 0, 1, 0]),
+                   Err(DecodeOneError::Trailing { consumed: 4, remaining: 5 }));
+        assert_eq!(CtlvRef::decode_one(&[128, 2, 1]),
+                   Err(DecodeOneError::Decode(UnexpectedEndOfInput)));
+        assert_eq!(CtlvRef::decode_one(&[]), Err(DecodeOneError::Decode(UnexpectedEndOfInput)));
+
+        assert_eq!(CtlvRef::try_from(&[0, 1][..]), Ok(CtlvRef { type_: 0, value: &[1] }));
+        assert_eq!(Ctlv::try_from(&[0, 1, 0][..]),
+                   Err(DecodeOneError::Trailing { consumed: 2, remaining: 1 }));
+    }
+
+    #[test]
+    fn decode_split() {
+        assert_eq!(CtlvRef::decode_split(&[249, 1, 44, 3], &[1, 2, 3]),
+                   Ok(CtlvRef { type_: 300, value: &[1, 2, 3] }));
+        assert_eq!(CtlvRef::decode_split(&[8], &[1, 2, 3]),
+                   Ok(CtlvRef { type_: 8, value: &[1, 2] }));
+        assert_eq!(CtlvRef::decode_split(&[128, 0, 7], &[]),
+                   Ok(CtlvRef { type_: 128, value: &[] }));
+
+        assert_eq!(CtlvRef::decode_split(&[16], &[1, 2, 3]), Err(UnexpectedEndOfInput));
+        assert_eq!(CtlvRef::decode_split(&[128], &[1]), Err(UnexpectedEndOfInput));
+        assert_eq!(CtlvRef::decode_split(&[249, 1], &[]), Err(UnexpectedEndOfInput));
+        assert_eq!(CtlvRef::decode_split(&[248, 5], &[1]),
+                   Err(Type(VarU64Error::NonCanonical(5))));
+        assert_eq!(CtlvRef::decode_split(&[128, 248, 1], &[1]),
+                   Err(Length(VarU64Error::NonCanonical(1))));
+    }
+
+    #[test]
+    fn decode_consumed() {
+        assert_eq!(CtlvRef::decode_consumed(&[128, 2, 1, 2, 9]),
+                   Ok((CtlvRef { type_: 128, value: &[1, 2] }, 4)));
+        assert_eq!(CtlvRef::decode_consumed(&[0, 1]), Ok((CtlvRef { type_: 0, value: &[1] }, 2)));
+        assert_eq!(CtlvRef::decode_consumed(&[]), Err((UnexpectedEndOfInput, 0)));
+        assert_eq!(CtlvRef::decode_consumed(&[128, 3, 1]), Err((UnexpectedEndOfInput, 2)));
+        assert_eq!(CtlvRef::decode_consumed(&[248, 5, 42]),
+                   Err((Type(VarU64Error::NonCanonical(5)), 2)));
+    }
+
+    #[test]
+    fn decode_into() {
+        let mut dst = Ctlv::decode(&[129, 3, 1, 2, 3]).unwrap().0;
+        let capacity = dst.value.capacity();
+
+        assert_eq!(Ctlv::decode_into(&[128, 2, 42, 43, 0], &mut dst), Ok(&[0][..]));
+        assert_eq!(dst,
+                   Ctlv {
+                       type_: 128,
+                       value: vec![42, 43],
+                   });
+        assert_eq!(dst.value.capacity(), capacity);
+
+        assert_eq!(Ctlv::decode_into(&[128, 2, 42], &mut dst),
+                   Err((UnexpectedEndOfInput, &[42][..])));
+        assert_eq!(dst.value, [42, 43]);
+    }
+
+    #[test]
+    fn arc() {
+        let (shared, tail) = CtlvArc::decode(&[0, 42, 1]).unwrap();
+        assert_eq!(tail, &[1][..]);
+        assert_eq!(&shared.value[..], &[42][..]);
+
+        let clone = shared.clone();
+        assert!(Arc::ptr_eq(&shared.value, &clone.value));
+        assert_eq!(clone.encode_vec(), [0, 42]);
+    }
+
+    #[test]
+    fn as_array() {
+        let (ctlv, _) = CtlvRef::decode(&[8, 1, 2]).unwrap();
+        assert_eq!(ctlv.as_array::<2>(), Some(&[1, 2]));
+        assert_eq!(ctlv.as_array::<4>(), None);
+        assert_eq!(ctlv.to_owned().as_array::<1>(), None);
+    }
+
+    // A writer that accepts at most `limit` bytes per call.
+    struct Trickle {
+        out: Vec<u8>,
+        limit: usize,
+    }
+
+    impl io::Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.limit);
+            self.out.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn vectored() {
+        let ctlv = CtlvRef { type_: 300, value: &[1, 2, 3, 4] };
+        let expected = ctlv.encode_vec();
+
+        let mut out = Vec::new();
+        assert_eq!(ctlv.encode_write_vectored(&mut out).unwrap(), expected.len());
+        assert_eq!(out, expected);
+
+        for limit in 1..expected.len() + 1 {
+            let mut trickle = Trickle { out: Vec::new(), limit };
+            assert_eq!(ctlv.encode_write_vectored(&mut trickle).unwrap(), expected.len());
+            assert_eq!(trickle.out, expected);
+        }
+    }
+
+    #[test]
+    fn encoded_order() {
+        let fixtures = [CtlvRef { type_: 0, value: &[42] },
+                        CtlvRef { type_: 1, value: &[0] },
+                        CtlvRef { type_: 127, value: &[] },
+                        CtlvRef { type_: 128, value: &[2] },
+                        CtlvRef { type_: 128, value: &[1, 1] },
+                        CtlvRef { type_: 247, value: &[] },
+                        CtlvRef { type_: 248, value: &[] },
+                        CtlvRef { type_: 300, value: &[0] }];
+
+        for a in fixtures.iter() {
+            for b in fixtures.iter() {
+                assert_eq!(a.cmp_encoded(b), a.encode_vec().cmp(&b.encode_vec()));
+            }
+        }
+
+        // Unlike the derived order, the encoded order takes the length into account first.
+        assert_eq!(fixtures[3].cmp(&fixtures[4]), Ordering::Greater);
+        assert_eq!(fixtures[3].cmp_encoded(&fixtures[4]), Ordering::Less);
+
+        let mut sorted: Vec<_> = fixtures.iter().map(|f| OrdByEncoding(f.to_owned())).collect();
+        sorted.reverse();
+        sorted.sort();
+        assert_eq!(sorted.iter().map(|f| f.0.encode_vec()).collect::<Vec<_>>(),
+                   fixtures.iter().map(|f| f.encode_vec()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn encode_header() {
+        let cases: [(CtlvRef, &[u8]); 4] = [(CtlvRef { type_: 0, value: &[1] }, &[0]),
+                                            (CtlvRef { type_: 128, value: &[] }, &[128, 0]),
+                                            (CtlvRef { type_: 300, value: &[2; 300] },
+                                             &[249, 1, 44, 249, 1, 44]),
+                                            (CtlvRef { type_: 248, value: &[3] }, &[248, 248, 1])];
+        for (ctlv, header) in &cases {
+            let mut out = [0; MAX_HEADER_LENGTH];
+            assert_eq!(ctlv.encode_header(&mut out), header.len());
+            assert_eq!(&out[..header.len()], *header);
+            assert_eq!(ctlv.header_len(), header.len());
+            assert_eq!(ctlv.to_owned().header_len(), header.len());
+            assert_eq!([*header, ctlv.value].concat(), ctlv.encode_vec());
+        }
+    }
+
+    #[test]
+    fn by_value() {
+        use std::collections::HashMap;
+
+        let ctlvs = [Ctlv { type_: 128, value: vec![1, 2] },
+                     Ctlv { type_: 8, value: vec![1, 2] },
+                     Ctlv { type_: 128, value: vec![3] },
+                     Ctlv { type_: 300, value: vec![1, 2] }];
+        let mut groups: HashMap<ByValue, Vec<u64>> = HashMap::new();
+        for ctlv in &ctlvs {
+            groups.entry(ByValue(ctlv)).or_default().push(ctlv.type_);
+        }
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[&ByValue(&ctlvs[0])], [128, 8, 300]);
+        assert_eq!(groups[&ByValue(&ctlvs[2])], [128]);
+
+        assert_eq!(ByValue(&ctlvs[1]), ByValue(&ctlvs[3]));
+        assert_ne!(ctlvs[1], ctlvs[3]);
+        assert!(ByValue(&ctlvs[0]) < ByValue(&ctlvs[2]));
+
+        let boxed = CtlvGeneric {
+            type_: 0,
+            value: Box::<[u8]>::from(&[3][..]),
+        };
+        assert_eq!(ByValue(&boxed), ByValue(&boxed.clone()));
+    }
+
+    #[test]
+    fn hash_into() {
+        use std::collections::hash_map::DefaultHasher;
+
+        fn hash_encoding(enc: &[u8]) -> u64 {
+            let mut h = DefaultHasher::new();
+            h.write(enc);
+            h.finish()
+        }
+
+        for ctlv in &[CtlvRef { type_: 0, value: &[42] },
+                      CtlvRef { type_: 128, value: &[1, 2] },
+                      CtlvRef { type_: 300, value: &[] }] {
+            let mut h = DefaultHasher::new();
+            ctlv.hash_into(&mut h);
+            assert_eq!(h.finish(), hash_encoding(&ctlv.encode_vec()));
+
+            let boxed = CtlvGeneric {
+                type_: ctlv.type_,
+                value: Box::<[u8]>::from(ctlv.value),
+            };
+            let mut h = DefaultHasher::new();
+            boxed.hash_into(&mut h);
+            assert_eq!(h.finish(), hash_encoding(&ctlv.encode_vec()));
+        }
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn canonical_id() {
+        fn hex(id: [u8; 32]) -> String {
+            id.iter().map(|byte| format!("{:02x}", byte)).collect()
+        }
+
+        // The encoding `[0, 1]` is the two-byte input of the official BLAKE3 test vectors.
+        let ctlv = Ctlv {
+            type_: 0,
+            value: vec![1],
+        };
+        assert_eq!(hex(ctlv.canonical_id()),
+                   "7b7015bb92cf0b318037702a6cdd81dee41224f734684c2c122cd6359cb1ee63");
+
+        let ctlv = CtlvRef {
+            type_: 300,
+            value: &[0xab, 0x01],
+        };
+        assert_eq!(hex(ctlv.canonical_id()),
+                   "a6a6746ab6a58335c24c5585acb5b228a817a23387458fd92341d605d7835ca4");
+        assert_eq!(ctlv.canonical_id(), *blake3::hash(&ctlv.encode_vec()).as_bytes());
+    }
+
+    #[test]
+    fn type_predicates() {
+        let mut ctlv = Ctlv { type_: 127, value: vec![0; 1 << 15] };
+        assert!(ctlv.matches_type(127));
+        assert!(!ctlv.matches_type(128));
+        assert!(ctlv.is_implied_length());
+        assert!(!ctlv.is_explicit_length());
+
+        let ctlv_ref = CtlvRef { type_: 128, value: &[] };
+        assert!(ctlv_ref.matches_type(128));
+        assert!(!ctlv_ref.is_implied_length());
+        assert!(ctlv_ref.is_explicit_length());
+
+        let ctlv_mut = ctlv.as_ctlv_ref_mut();
+        assert!(ctlv_mut.matches_type(127));
+        assert!(ctlv_mut.is_implied_length());
+
+        let ctlv = Ctlv::new(300, vec![0x2a]);
+        assert!(ctlv.value_eq(&[0x2a]));
+        assert!(!ctlv.value_eq(&[]));
+        assert!(ctlv.as_ctlv_ref().value_eq(&[0x2a]));
+    }
+
+    #[test]
+    fn hex() {
+        let ctlv = Ctlv {
+            type_: 300,
+            value: vec![0xab, 0x01],
+        };
+        assert_eq!(format!("{:x}", ctlv), "f9012c02ab01");
+        assert_eq!(format!("{:X}", ctlv), "F9012C02AB01");
+        assert_eq!(format!("{:#x}", ctlv), "0xf9012c02ab01");
+        assert_eq!(format!("{:#X}", ctlv.as_ctlv_ref()), "0xF9012C02AB01");
+        assert_eq!(format!("{:x}", CtlvRef { type_: 0, value: &[0xff] }), "00ff");
+    }
+
+    #[test]
+    fn mutate_value() {
+        let mut ctlv = Ctlv {
+            type_: 300,
+            value: vec![1, 2, 3],
+        };
+        ctlv.value_mut().push(4);
+        assert_eq!(ctlv.value, [1, 2, 3, 4]);
+        ctlv.truncate_value(2);
+        assert_eq!(ctlv.value, [1, 2]);
+        ctlv.truncate_value(5);
+        assert_eq!(ctlv.value, [1, 2]);
+        ctlv.clear_value();
+        assert_eq!(ctlv.encode_vec(), [249, 1, 44, 0]);
+        ctlv.set_value(vec![9]);
+        assert_eq!(ctlv.value, [9]);
+        assert_eq!(ctlv.clone().with_value(vec![]).value, []);
+        let ptr = ctlv.value.as_ptr();
+        let value = ctlv.into_value();
+        assert_eq!(value, [9]);
+        assert_eq!(value.as_ptr(), ptr);
+
+        let mut boxed = CtlvGeneric::new(0, Box::<[u8]>::from(&[1][..]));
+        boxed.value_mut()[0] = 2;
+        assert_eq!(boxed.value(), [2]);
+        boxed.set_value(Box::from(&[3][..]));
+        assert_eq!(boxed.value(), [3]);
+        assert_eq!(boxed.with_value(Box::from(&[4][..])).value(), [4]);
+    }
+
+    #[test]
+    fn bytes() {
+        let ctlv = Ctlv {
+            type_: 300,
+            value: vec![1, 2, 3],
+        };
+        let mut sum = 0;
+        for b in &ctlv {
+            sum += *b;
+        }
+        assert_eq!(sum, 6);
+        assert_eq!(ctlv.bytes().rev().collect::<Vec<_>>(), [&3, &2, &1]);
+
+        let frame = ctlv.as_ctlv_ref();
+        assert_eq!((&frame).into_iter().max(), Some(&3));
+        assert_eq!(frame.bytes().len(), 3);
+    }
+
+    #[test]
+    fn as_value() {
+        fn value_len<T: AsRef<[u8]>>(t: T) -> usize {
+            t.as_ref().len()
+        }
+
+        let ctlv = Ctlv {
+            type_: 300,
+            value: vec![1, 2, 3],
+        };
+        assert_eq!(value_len(&ctlv), 3);
+        assert_eq!(value_len(ctlv.as_ctlv_ref()), 3);
+        assert_eq!(ctlv.len(), 3);
+        assert_eq!(ctlv.encoding_length(), 7);
+        assert_eq!(ctlv.as_ctlv_ref().first(), Some(&1));
+        assert!(ctlv.starts_with(&[1, 2]));
+        assert_eq!(&*ctlv.as_ctlv_ref(), &[1, 2, 3][..]);
+    }
+
+    #[test]
+    fn encode_write_calls() {
+        // Counts the `write` calls.
+        struct Calls(usize, Vec<u8>);
+
+        impl io::Write for Calls {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0 += 1;
+                self.1.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        for (len, calls) in &[(0, 1), (1, 1), (64, 1), (65, 2), (1000, 2)] {
+            for type_ in &[128, 300, u64::MAX] {
+                let value = vec![7; *len];
+                let ctlv = CtlvRef { type_: *type_, value: &value };
+                let mut w = Calls(0, Vec::new());
+                assert_eq!(ctlv.encode_write(&mut w).unwrap(), ctlv.encoding_length());
+                assert_eq!(w.0, *calls);
+                assert_eq!(w.1.len(), ctlv.encoding_length());
+
+                let mut expected = vec![0; ctlv.encoding_length()];
+                ctlv.encode(&mut expected);
+                assert_eq!(w.1, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn from_hex() {
+        let ctlv = Ctlv {
+            type_: 300,
+            value: vec![0xab, 0x01],
+        };
+        assert_eq!(Ctlv::from_hex(&format!("{:x}", ctlv)), Ok((ctlv.clone(), 6)));
+        assert_eq!(Ctlv::from_hex(" F9 01 2c\n02\tAB01 00ff "), Ok((ctlv, 6)));
+
+        assert_eq!(Ctlv::from_hex("00f"), Err(HexDecodeError::OddLength));
+        assert_eq!(Ctlv::from_hex("00 ff xy"), Err(HexDecodeError::InvalidChar(6)));
+        assert_eq!(Ctlv::from_hex("0 0ff"), Err(HexDecodeError::InvalidChar(1)));
+        assert_eq!(Ctlv::from_hex("8002ab"),
+                   Err(HexDecodeError::Decode(UnexpectedEndOfInput)));
+        assert_eq!(Ctlv::from_hex(""), Err(HexDecodeError::Decode(UnexpectedEndOfInput)));
+    }
+
+    #[test]
+    fn implied_length() {
+        const BUF: [u8; type_implied_length(8)] = [0; 2];
+        assert_eq!(BUF.len(), 2);
+
+        assert_eq!(type_implied_length(0), 1);
+        assert_eq!(type_implied_length(7), 1);
+        assert_eq!(type_implied_length(127), 1 << 15);
+        assert_eq!(type_implied_length(128), 0);
+        assert_eq!(type_implied_length(EXPLICIT_LENGTH_MIN_TYPE - 1), 1 << 15);
+        assert_eq!(type_implied_length(EXPLICIT_LENGTH_MIN_TYPE), 0);
+        assert_eq!(type_implied_length(u64::MAX), 0);
+    }
+
+    #[test]
+    fn encode_exact() {
+        let ctlv = CtlvRef { type_: 128, value: &[42] };
+
+        let mut exact = [0; 3];
+        assert_eq!(ctlv.encode_exact(&mut exact), Ok(()));
+        assert_eq!(exact, [128, 1, 42]);
+
+        assert_eq!(ctlv.encode_exact(&mut [0; 2]),
+                   Err(BufferSizeError { expected: 3, actual: 2 }));
+        assert_eq!(ctlv.encode_exact(&mut [0; 4]),
+                   Err(BufferSizeError { expected: 3, actual: 4 }));
+    }
 }
@@ -0,0 +1,386 @@
+use std::error;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::read::read_header_impl;
+use super::{Ctlv, CtlvRef, DecodeConfig, DecodeError, ReadDecodeError};
+
+/// When a [`CtlvLogWriter`](struct.CtlvLogWriter.html) syncs appended ctlvs to disk.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SyncPolicy {
+    /// Sync after every append, so that `append` only returns once the ctlv is durable.
+    EveryAppend,
+    /// Sync after every this many appends. A crash can lose up to that many ctlvs.
+    Batched(u32),
+    /// Only sync when `sync` is called.
+    Manual,
+}
+
+/// How a [`CtlvLogWriter`](struct.CtlvLogWriter.html) opens and writes its file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct LogOptions {
+    /// When to sync appended ctlvs to disk. Defaults to `SyncPolicy::Manual`.
+    pub sync: SyncPolicy,
+    /// Whether to open the file in append mode (`O_APPEND`), so that every write goes to the
+    /// end of the file even if something else appends to it as well. Defaults to `true`.
+    pub append_mode: bool,
+    /// Whether to truncate an incomplete ctlv at the end of the file, as left behind by a
+    /// crash within an append. If this is `false`, opening such a file errors instead.
+    /// Defaults to `true`.
+    pub repair: bool,
+}
+
+impl Default for LogOptions {
+    fn default() -> LogOptions {
+        LogOptions {
+            sync: SyncPolicy::Manual,
+            append_mode: true,
+            repair: true,
+        }
+    }
+}
+
+/// Appends ctlvs to a log file.
+///
+/// Opening the log checks that the file consists of complete ctlvs, so appending continues at
+/// a ctlv boundary. Each ctlv is written with a single `write` call, so in append mode it is
+/// not interleaved with the writes of other processes appending to the same file.
+///
+/// Appended ctlvs are synced to disk according to the [`SyncPolicy`](enum.SyncPolicy.html).
+/// Dropping the writer does not sync, call [`sync`](#method.sync) first if needed.
+#[derive(Debug)]
+pub struct CtlvLogWriter {
+    file: File,
+    // The offset at which the next ctlv is appended.
+    end: u64,
+    truncated: u64,
+    sync: SyncPolicy,
+    // How many ctlvs have been appended since the last sync.
+    unsynced: u32,
+    buf: Vec<u8>,
+}
+
+impl CtlvLogWriter {
+    /// Opens the log at `path`, creating the file if it does not exist.
+    ///
+    /// Errors with `io::ErrorKind::InvalidData` if the file does not consist of valid ctlvs,
+    /// except for an incomplete ctlv at its end, which is truncated if `options.repair` is set.
+    pub fn open<P: AsRef<Path>>(path: P, options: LogOptions) -> io::Result<CtlvLogWriter> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(!options.append_mode)
+            .append(options.append_mode)
+            .create(true)
+            .open(path)?;
+
+        let len = file.metadata()?.len();
+        let end = valid_prefix_len(&file, len)?;
+        if end < len {
+            if !options.repair {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "log ends within a ctlv"));
+            }
+            file.set_len(end)?;
+            file.sync_data()?;
+        }
+        (&file).seek(SeekFrom::Start(end))?;
+
+        Ok(CtlvLogWriter {
+               file,
+               end,
+               truncated: len - end,
+               sync: options.sync,
+               unsynced: 0,
+               buf: Vec::new(),
+           })
+    }
+
+    /// Appends a ctlv to the log, returning the offset at which it starts.
+    pub fn append(&mut self, frame: CtlvRef) -> io::Result<u64> {
+        self.buf.clear();
+        frame.encode_write(&mut self.buf)?;
+        self.file.write_all(&self.buf)?;
+
+        let offset = self.end;
+        self.end += self.buf.len() as u64;
+        self.unsynced += 1;
+        match self.sync {
+            SyncPolicy::EveryAppend => self.sync()?,
+            SyncPolicy::Batched(n) if self.unsynced >= n => self.sync()?,
+            _ => {}
+        }
+        Ok(offset)
+    }
+
+    /// Syncs all appended ctlvs to disk, see `File::sync_data`.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    /// Returns the length of the log, i.e. the offset at which the next ctlv is appended.
+    pub fn len(&self) -> u64 {
+        self.end
+    }
+
+    /// Returns whether the log is empty.
+    pub fn is_empty(&self) -> bool {
+        self.end == 0
+    }
+
+    /// Returns how many bytes of an incomplete ctlv were truncated when opening the log.
+    pub fn truncated(&self) -> u64 {
+        self.truncated
+    }
+
+    /// Returns the underlying file, without syncing it.
+    pub fn into_inner(self) -> File {
+        self.file
+    }
+}
+
+// Returns how many bytes at the start of the file form complete ctlvs. Errors if the file is
+// invalid before its last ctlv.
+fn valid_prefix_len(file: &File, len: u64) -> io::Result<u64> {
+    let mut r = BufReader::new(file);
+    r.seek(SeekFrom::Start(0))?;
+    let config = DecodeConfig::default();
+    let mut valid = 0;
+    loop {
+        match read_header_impl(&mut r, &config) {
+            Ok((header, header_len)) => {
+                let start = valid + header_len as u64;
+                if header.len > len - start {
+                    return Ok(valid);
+                }
+                r.seek_relative(header.len as i64)?;
+                valid = start + header.len;
+            }
+            Err(ReadDecodeError::Eof) | Err(ReadDecodeError::UnexpectedEof { .. }) => {
+                return Ok(valid)
+            }
+            Err(ReadDecodeError::Decode(e)) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+            Err(ReadDecodeError::Io(e)) => return Err(e),
+        }
+    }
+}
+
+/// Everything that can go wrong when reading a log with a
+/// [`CtlvLogReader`](struct.CtlvLogReader.html).
+#[derive(Debug)]
+pub enum LogReadError {
+    /// The log ends within the ctlv starting at the given offset, as left behind by a crash
+    /// within an append. All ctlvs before it are intact, so writing can resume at the offset.
+    TruncatedTail {
+        /// The offset at which the incomplete ctlv starts.
+        offset: u64,
+    },
+    /// The bytes at the given offset do not form a valid ctlv.
+    Corrupt {
+        /// The offset at which the invalid ctlv starts.
+        offset: u64,
+        /// Why the ctlv is invalid.
+        error: DecodeError,
+    },
+    /// Reading failed with the wrapped error.
+    Io(io::Error),
+}
+
+impl fmt::Display for LogReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        match self {
+            LogReadError::TruncatedTail { offset } => {
+                write!(f, "Log ends within the ctlv at offset {}", offset)
+            }
+            LogReadError::Corrupt { offset, error } => {
+                write!(f, "Corrupt log at offset {}: {}", offset, error)
+            }
+            LogReadError::Io(e) => write!(f, "Could not read log: {}", e),
+        }
+    }
+}
+
+impl error::Error for LogReadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            LogReadError::Corrupt { error, .. } => Some(error),
+            LogReadError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Iterates over the ctlvs of a log file, together with their offsets.
+///
+/// If the file ends within a ctlv, the last item is `LogReadError::TruncatedTail`, which
+/// applications can treat as recoverable by resuming writing at its offset (which is what
+/// [`CtlvLogWriter::open`](struct.CtlvLogWriter.html#method.open) does when repairing).
+/// Invalid ctlvs anywhere else are reported as `LogReadError::Corrupt`. The iterator ends
+/// after the first error.
+#[derive(Debug)]
+pub struct CtlvLogReader {
+    r: BufReader<File>,
+    // The offset at which the next ctlv starts.
+    offset: u64,
+    done: bool,
+}
+
+impl CtlvLogReader {
+    /// Opens the log at `path` for reading.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<CtlvLogReader> {
+        Ok(CtlvLogReader {
+               r: BufReader::new(File::open(path)?),
+               offset: 0,
+               done: false,
+           })
+    }
+
+    /// Returns the offset right after the last complete ctlv read so far. After the iterator
+    /// ended with `None` or `LogReadError::TruncatedTail`, this is where appending can resume.
+    pub fn last_valid_offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl Iterator for CtlvLogReader {
+    type Item = Result<(u64, Ctlv), LogReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let offset = self.offset;
+        let result = match Ctlv::decode_read(&mut self.r) {
+            Ok(ctlv) => {
+                self.offset += ctlv.encoding_length() as u64;
+                return Some(Ok((offset, ctlv)));
+            }
+            Err(ReadDecodeError::Eof) => {
+                self.done = true;
+                return None;
+            }
+            Err(ReadDecodeError::UnexpectedEof { .. }) => LogReadError::TruncatedTail { offset },
+            Err(ReadDecodeError::Decode(error)) => LogReadError::Corrupt { offset, error },
+            Err(ReadDecodeError::Io(e)) => LogReadError::Io(e),
+        };
+        self.done = true;
+        Some(Err(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ctlv-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn append_and_recover() {
+        let path = temp_path("log-recover");
+        let frames = [Ctlv::new(0, vec![1]), Ctlv::new(300, vec![2; 20]), Ctlv::new(128, vec![])];
+
+        let mut log = CtlvLogWriter::open(&path, LogOptions::default()).unwrap();
+        assert!(log.is_empty());
+        assert_eq!(log.append(frames[0].as_ctlv_ref()).unwrap(), 0);
+        assert_eq!(log.append(frames[1].as_ctlv_ref()).unwrap(), 2);
+        log.sync().unwrap();
+        assert_eq!(log.len(), 2 + 24);
+        drop(log);
+
+        // A crash within the append of the second ctlv.
+        fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(2 + 10).unwrap();
+        let options = LogOptions {
+            repair: false,
+            ..LogOptions::default()
+        };
+        let err = CtlvLogWriter::open(&path, options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let options = LogOptions {
+            sync: SyncPolicy::EveryAppend,
+            append_mode: false,
+            repair: true,
+        };
+        let mut log = CtlvLogWriter::open(&path, options).unwrap();
+        assert_eq!(log.truncated(), 10);
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.append(frames[1].as_ctlv_ref()).unwrap(), 2);
+        assert_eq!(log.append(frames[2].as_ctlv_ref()).unwrap(), 26);
+        drop(log);
+
+        let expected: Vec<_> = frames.iter().map(Ctlv::encode_vec).collect();
+        assert_eq!(fs::read(&path).unwrap(), expected.concat());
+
+        // Even a torn header of a single byte is truncated.
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(&[249]).unwrap();
+        let options = LogOptions {
+            sync: SyncPolicy::Batched(2),
+            ..LogOptions::default()
+        };
+        let mut log = CtlvLogWriter::open(&path, options).unwrap();
+        assert_eq!(log.truncated(), 1);
+        assert_eq!(log.append(frames[0].as_ctlv_ref()).unwrap(), 28);
+        assert_eq!(log.unsynced, 1);
+        log.append(frames[0].as_ctlv_ref()).unwrap();
+        assert_eq!(log.unsynced, 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read() {
+        let path = temp_path("log-read");
+        let frames = [Ctlv::new(0, vec![1]), Ctlv::new(300, vec![2; 20]), Ctlv::new(128, vec![])];
+        let encoded: Vec<_> = frames.iter().map(Ctlv::encode_vec).collect();
+        let encoded = encoded.concat();
+
+        fs::write(&path, &encoded).unwrap();
+        let mut reader = CtlvLogReader::open(&path).unwrap();
+        let read: Vec<_> = reader.by_ref().map(Result::unwrap).collect();
+        assert_eq!(read, [(0, frames[0].clone()), (2, frames[1].clone()), (26, frames[2].clone())]);
+        assert_eq!(reader.last_valid_offset(), 28);
+        assert!(reader.next().is_none());
+
+        // Truncated within the header and within the value of the second ctlv.
+        for len in &[4, 12] {
+            fs::write(&path, &encoded[..*len]).unwrap();
+            let mut reader = CtlvLogReader::open(&path).unwrap();
+            assert_eq!(reader.next().unwrap().unwrap(), (0, frames[0].clone()));
+            assert!(matches!(reader.next(), Some(Err(LogReadError::TruncatedTail { offset: 2 }))));
+            assert!(reader.next().is_none());
+            assert_eq!(reader.last_valid_offset(), 2);
+        }
+
+        let mut corrupt = encoded.clone();
+        corrupt.splice(2..2, vec![248, 5]);
+        fs::write(&path, &corrupt).unwrap();
+        let mut reader = CtlvLogReader::open(&path).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        match reader.next() {
+            Some(Err(LogReadError::Corrupt { offset: 2, error: DecodeError::Type(_) })) => {}
+            other => panic!("{:?}", other),
+        }
+        assert!(reader.next().is_none());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid() {
+        let path = temp_path("log-invalid");
+        fs::write(&path, [0, 1, 248, 5, 0, 1]).unwrap();
+        let err = CtlvLogWriter::open(&path, LogOptions::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read(&path).unwrap(), [0, 1, 248, 5, 0, 1]);
+        fs::remove_file(&path).unwrap();
+    }
+}
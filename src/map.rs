@@ -0,0 +1,265 @@
+use std::collections::btree_map::{self, BTreeMap};
+use std::{error, fmt};
+
+use super::{CtlvIter, CtlvRef, DecodeError};
+
+/// How [`CtlvMap::decode`](struct.CtlvMap.html#method.decode) handles multiple ctlvs of the
+/// same type.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DuplicatePolicy {
+    /// Reject the input with a `MapDecodeError::DuplicateType`.
+    Error,
+    /// Keep the value of the first ctlv of each type.
+    KeepFirst,
+    /// Keep the value of the last ctlv of each type.
+    KeepLast,
+}
+
+/// Everything that can go wrong when decoding a `CtlvMap`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MapDecodeError {
+    /// The ctlv at the given offset could not be decoded.
+    Decode(DecodeError, usize),
+    /// The ctlv at the given offset has the same type as an earlier one, and the
+    /// `DuplicatePolicy` was `Error`.
+    DuplicateType {
+        /// The duplicated type.
+        type_: u64,
+        /// The offset of the second ctlv of that type.
+        offset: usize,
+    },
+}
+
+impl fmt::Display for MapDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        match self {
+            MapDecodeError::Decode(e, offset) => write!(f, "{} (at offset {})", e, offset),
+            MapDecodeError::DuplicateType { type_, offset } => {
+                write!(f, "Duplicate ctlv type {} at offset {}", type_, offset)
+            }
+        }
+    }
+}
+
+impl error::Error for MapDecodeError {}
+
+/// A map from types to values, holding at most one value per type.
+///
+/// Its encoding is the concatenation of the encodings of its entries, in ascending order of
+/// their types.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct CtlvMap {
+    entries: BTreeMap<u64, Vec<u8>>,
+}
+
+impl CtlvMap {
+    /// Creates an empty `CtlvMap`.
+    pub fn new() -> CtlvMap {
+        CtlvMap::default()
+    }
+
+    /// Returns how many entries the map contains.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Sets the value of the given type, returning the previous value (if any).
+    pub fn insert(&mut self, type_: u64, value: Vec<u8>) -> Option<Vec<u8>> {
+        self.entries.insert(type_, value)
+    }
+
+    /// Returns the value of the given type.
+    pub fn get(&self, type_: u64) -> Option<&[u8]> {
+        self.entries.get(&type_).map(Vec::as_slice)
+    }
+
+    /// Removes the entry of the given type, returning its value (if any).
+    pub fn remove(&mut self, type_: u64) -> Option<Vec<u8>> {
+        self.entries.remove(&type_)
+    }
+
+    /// Returns an iterator over the entries of the map, in ascending order of their types.
+    pub fn iter(&self) -> CtlvMapIter<'_> {
+        CtlvMapIter(self.entries.iter())
+    }
+
+    /// Return how many bytes the encoding of the `CtlvMap` will take up.
+    pub fn encoding_length(&self) -> usize {
+        self.iter().map(|ctlv| ctlv.encoding_length()).sum()
+    }
+
+    /// Encodes this `CtlvMap` as an owned `Vec<u8>`, in ascending order of types.
+    pub fn encode_vec(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.encoding_length());
+        for ctlv in self.iter() {
+            ctlv.encode_write(&mut out).unwrap();
+        }
+        out
+    }
+
+    /// Decode a `CtlvMap` from a complete sequence of ctlvs, resolving ctlvs of the same type
+    /// according to the given policy.
+    ///
+    /// The input does not need to be sorted by type.
+    pub fn decode(input: &[u8], policy: DuplicatePolicy) -> Result<CtlvMap, MapDecodeError> {
+        let mut map = CtlvMap::new();
+        let mut iter = CtlvIter::new(input);
+
+        loop {
+            let offset = iter.offset();
+            let ctlv = match iter.next() {
+                None => return Ok(map),
+                Some(Err((e, offset))) => return Err(MapDecodeError::Decode(e, offset)),
+                Some(Ok(ctlv)) => ctlv,
+            };
+
+            match map.entries.entry(ctlv.type_) {
+                btree_map::Entry::Vacant(entry) => {
+                    entry.insert(ctlv.value.to_vec());
+                }
+                btree_map::Entry::Occupied(mut entry) => match policy {
+                    DuplicatePolicy::Error => {
+                        return Err(MapDecodeError::DuplicateType {
+                                       type_: ctlv.type_,
+                                       offset,
+                                   })
+                    }
+                    DuplicatePolicy::KeepFirst => {}
+                    DuplicatePolicy::KeepLast => {
+                        entry.insert(ctlv.value.to_vec());
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Group the values of an encoded sequence of ctlvs by their types.
+///
+/// Unlike a [`CtlvMap`](struct.CtlvMap.html), this keeps all values of each type, in the
+/// order in which they occur. If the input is invalid, returns the error and the offset at
+/// which it occurred.
+#[allow(clippy::type_complexity)]
+pub fn to_map(input: &[u8]) -> Result<BTreeMap<u64, Vec<Vec<u8>>>, (DecodeError, usize)> {
+    let mut map: BTreeMap<u64, Vec<Vec<u8>>> = BTreeMap::new();
+    for result in CtlvIter::new(input) {
+        let ctlv = result?;
+        map.entry(ctlv.type_).or_default().push(ctlv.value.to_vec());
+    }
+    Ok(map)
+}
+
+/// Encode the values of a map as a sequence of ctlvs, in ascending order of their types.
+/// Values of the same type are encoded in the order of their list.
+///
+/// This is the inverse of [`to_map`](fn.to_map.html) for sequences sorted by type.
+pub fn from_map(map: &BTreeMap<u64, Vec<Vec<u8>>>) -> Vec<u8> {
+    let ctlvs = || {
+        map.iter().flat_map(|(type_, values)| {
+            values.iter().map(move |value| CtlvRef { type_: *type_, value })
+        })
+    };
+
+    let mut out = Vec::with_capacity(ctlvs().map(|ctlv| ctlv.encoding_length()).sum());
+    for ctlv in ctlvs() {
+        ctlv.encode_write(&mut out).unwrap();
+    }
+    out
+}
+
+/// An iterator over the entries of a `CtlvMap`, in ascending order of their types.
+#[derive(Debug, Clone)]
+pub struct CtlvMapIter<'a>(btree_map::Iter<'a, u64, Vec<u8>>);
+
+impl<'a> Iterator for CtlvMapIter<'a> {
+    type Item = CtlvRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(type_, value)| CtlvRef {
+                              type_: *type_,
+                              value,
+                          })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for CtlvMapIter<'a> {}
+
+impl<'a> IntoIterator for &'a CtlvMap {
+    type Item = CtlvRef<'a>;
+    type IntoIter = CtlvMapIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Type 300 twice, then type 0 once.
+    const DUPLICATES: [u8; 12] = [249, 1, 44, 1, 1, 249, 1, 44, 1, 2, 0, 3];
+
+    #[test]
+    fn duplicate_policies() {
+        assert_eq!(CtlvMap::decode(&DUPLICATES, DuplicatePolicy::Error),
+                   Err(MapDecodeError::DuplicateType {
+                           type_: 300,
+                           offset: 5,
+                       }));
+
+        let first = CtlvMap::decode(&DUPLICATES, DuplicatePolicy::KeepFirst).unwrap();
+        assert_eq!(first.len(), 2);
+        assert_eq!(first.get(300), Some(&[1][..]));
+        assert_eq!(first.get(0), Some(&[3][..]));
+
+        let last = CtlvMap::decode(&DUPLICATES, DuplicatePolicy::KeepLast).unwrap();
+        assert_eq!(last.len(), 2);
+        assert_eq!(last.get(300), Some(&[2][..]));
+        assert_eq!(last.get(0), Some(&[3][..]));
+
+        assert_eq!(CtlvMap::decode(&[128, 2, 0], DuplicatePolicy::Error),
+                   Err(MapDecodeError::Decode(DecodeError::UnexpectedEndOfInput, 0)));
+    }
+
+    #[test]
+    fn canonical_order() {
+        let mut map = CtlvMap::new();
+        assert_eq!(map.insert(300, vec![1]), None);
+        assert_eq!(map.insert(0, vec![3]), None);
+        assert_eq!(map.insert(128, vec![]), None);
+        assert_eq!(map.insert(128, vec![4, 5]), Some(vec![]));
+
+        let enc = map.encode_vec();
+        assert_eq!(enc, [0, 3, 128, 2, 4, 5, 249, 1, 44, 1, 1]);
+        assert_eq!(enc.len(), map.encoding_length());
+        assert_eq!(CtlvMap::decode(&enc, DuplicatePolicy::Error).unwrap(), map);
+
+        assert_eq!(map.remove(128), Some(vec![4, 5]));
+        assert_eq!(map.get(128), None);
+        assert_eq!(map.iter().map(|ctlv| ctlv.type_).collect::<Vec<_>>(), [0, 300]);
+    }
+
+    #[test]
+    fn grouped() {
+        let map = to_map(&DUPLICATES).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map[&300], [vec![1], vec![2]]);
+        assert_eq!(map[&0], [vec![3]]);
+
+        assert_eq!(from_map(&map), [0, 3, 249, 1, 44, 1, 1, 249, 1, 44, 1, 2]);
+        assert_eq!(to_map(&from_map(&map)).unwrap(), map);
+        assert_eq!(from_map(&BTreeMap::new()), []);
+
+        assert_eq!(to_map(&[0, 3, 128]), Err((DecodeError::UnexpectedEndOfInput, 2)));
+    }
+}
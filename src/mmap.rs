@@ -0,0 +1,117 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use memmap2::Mmap;
+
+use super::{CtlvIndex, CtlvIter, CtlvRef, CtlvSlice, DecodeError};
+
+/// A file of encoded ctlvs, mapped into memory so that ctlvs can be accessed without reading
+/// the file into a buffer first.
+///
+/// All ctlvs borrow their values straight from the mapping, so they can not outlive the
+/// `MappedCtlvFile`.
+///
+/// # Caveats
+///
+/// The mapping reflects the file as it was when it was opened: ctlvs appended afterwards are
+/// not visible, and if the file was opened while a ctlv was being appended, the mapping ends
+/// in an incomplete ctlv. Iteration reports that as an error at the end, and the
+/// [`index`](#method.index) simply omits it. Truncating or otherwise modifying the file while
+/// it is mapped, however, must be avoided: depending on the platform, the process may crash
+/// or observe the bytes changing underneath borrowed ctlvs.
+///
+/// Requires the `mmap` feature.
+#[derive(Debug)]
+pub struct MappedCtlvFile {
+    map: Mmap,
+    index: OnceLock<CtlvIndex>,
+}
+
+impl MappedCtlvFile {
+    /// Maps the file at `path` into memory. See the [caveats](#caveats) of mapping files.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<MappedCtlvFile> {
+        let file = File::open(path)?;
+        // The caveats in the documentation tell users not to modify the file while mapped.
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(MappedCtlvFile {
+               map,
+               index: OnceLock::new(),
+           })
+    }
+
+    /// Returns the contents of the file.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// Returns an iterator over the ctlvs in the file.
+    pub fn iter(&self) -> CtlvIter<'_> {
+        CtlvIter::new(&self.map)
+    }
+
+    /// Validates the file, returning a [`CtlvSlice`](struct.CtlvSlice.html) of its ctlvs, or
+    /// the first error and its offset.
+    pub fn slice(&self) -> Result<CtlvSlice<'_>, (DecodeError, usize)> {
+        CtlvSlice::new(&self.map)
+    }
+
+    /// Returns an index of the ctlvs in the file, up to the first invalid one. The index is
+    /// built on the first call and reused afterwards.
+    pub fn index(&self) -> &CtlvIndex {
+        self.index.get_or_init(|| CtlvIndex::build_partial(&self.map).0)
+    }
+
+    /// Returns the `i`-th ctlv in the file, using the [`index`](#method.index).
+    pub fn get(&self, i: usize) -> Option<CtlvRef<'_>> {
+        self.index().get(i, &self.map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{type_implied_length, Ctlv};
+    use std::fs;
+
+    #[test]
+    fn mapped() {
+        let path = std::env::temp_dir().join(format!("ctlv-mmap-{}", std::process::id()));
+        let mut input = Vec::new();
+        for i in 0..3000u64 {
+            let type_ = if i % 2 == 0 { i % 48 } else { 128 + i % 100 };
+            let len = match type_implied_length(type_) {
+                0 => (i % 7) as usize,
+                implied => implied,
+            };
+            Ctlv::new(type_, vec![i as u8; len]).encode_write(&mut input).unwrap();
+        }
+        fs::write(&path, &input).unwrap();
+
+        let file = MappedCtlvFile::open(&path).unwrap();
+        assert_eq!(file.as_bytes(), &input[..]);
+        assert!(file.iter().eq(CtlvIter::new(&input)));
+        assert_eq!(file.slice().unwrap().len(), 3000);
+        assert_eq!(file.index().len(), 3000);
+        for (i, frame) in CtlvIter::new(&input).enumerate() {
+            assert_eq!(file.get(i), Some(frame.unwrap()));
+        }
+        assert_eq!(file.get(3000), None);
+        drop(file);
+
+        // A torn append at the end of the file.
+        input.extend_from_slice(&[249, 1]);
+        fs::write(&path, &input).unwrap();
+        let file = MappedCtlvFile::open(&path).unwrap();
+        assert_eq!(file.iter().last(),
+                   Some(Err((DecodeError::UnexpectedEndOfInput, input.len() - 2))));
+        assert!(file.slice().is_err());
+        assert_eq!(file.index().len(), 3000);
+        drop(file);
+
+        fs::write(&path, []).unwrap();
+        assert_eq!(MappedCtlvFile::open(&path).unwrap().iter().count(), 0);
+        fs::remove_file(&path).unwrap();
+    }
+}
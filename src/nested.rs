@@ -0,0 +1,392 @@
+//! Working with nested ctlvs, i.e. ctlvs whose values are sequences of ctlvs themselves.
+//!
+//! Which types are containers (and thus have their values interpreted as sequences) is not
+//! part of the format, so all functions here take a predicate deciding that.
+
+use std::{error, fmt};
+
+use super::{CtlvIter, CtlvRef, DecodeError};
+
+/// Options for processing nested ctlvs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DecoderOptions {
+    /// How deeply containers may be nested. Top-level ctlvs have depth zero, the children of a
+    /// top-level container have depth one, and so on. Ctlvs deeper than this are rejected with
+    /// `NestedError::DepthExceeded`.
+    pub max_depth: usize,
+}
+
+// The nesting depth allowed by the default `DecoderOptions`.
+pub(crate) const DEFAULT_MAX_DEPTH: usize = 32;
+
+impl Default for DecoderOptions {
+    /// Allows a nesting depth of 32.
+    fn default() -> DecoderOptions {
+        DecoderOptions { max_depth: DEFAULT_MAX_DEPTH }
+    }
+}
+
+/// Everything that can go wrong when processing nested ctlvs.
+///
+/// All offsets are relative to the start of the outermost input.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NestedError {
+    /// The ctlv at the given offset could not be decoded.
+    Decode(DecodeError, usize),
+    /// The container at the given offset has children deeper than the maximum depth.
+    DepthExceeded(usize),
+    /// Rewriting the ctlv at the given offset would change the length of its value (or its
+    /// type), so that its value would not have the length its type implies.
+    ImpliedLength(usize),
+}
+
+impl NestedError {
+    /// Returns the offset at which the error occurred.
+    pub fn offset(&self) -> usize {
+        match self {
+            NestedError::Decode(_, offset) |
+            NestedError::DepthExceeded(offset) |
+            NestedError::ImpliedLength(offset) => *offset,
+        }
+    }
+}
+
+impl fmt::Display for NestedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        match self {
+            NestedError::Decode(e, offset) => write!(f, "{} (at offset {})", e, offset),
+            NestedError::DepthExceeded(offset) => {
+                write!(f, "Invalid nested ctlv: container at offset {} is nested too deeply", offset)
+            }
+            NestedError::ImpliedLength(offset) => {
+                write!(f,
+                       "Invalid nested ctlv: ctlv at offset {} would not have its implied length",
+                       offset)
+            }
+        }
+    }
+}
+
+impl error::Error for NestedError {}
+
+/// A ctlv whose value has been parsed into children if its type denotes a container.
+///
+/// Leaves (ctlvs that are not containers) keep their raw value.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CtlvTree<'a> {
+    frame: CtlvRef<'a>,
+    offset: usize,
+    children: Option<Vec<CtlvTree<'a>>>,
+}
+
+impl<'a> CtlvTree<'a> {
+    /// Parse a sequence of ctlvs into trees, recursively parsing the values of all ctlvs for
+    /// which `is_container` returns true.
+    pub fn parse<F>(input: &'a [u8],
+                    is_container: F,
+                    options: &DecoderOptions)
+                    -> Result<Vec<CtlvTree<'a>>, NestedError>
+        where F: Fn(u64) -> bool
+    {
+        parse_sequence(input, 0, 0, &is_container, options)
+    }
+
+    /// Returns the type of this ctlv.
+    pub fn type_(&self) -> u64 {
+        self.frame.type_
+    }
+
+    /// Returns the raw value of this ctlv. For containers, this is the encoding of the children.
+    pub fn value(&self) -> &'a [u8] {
+        self.frame.value
+    }
+
+    /// Returns this ctlv as a `CtlvRef`.
+    pub fn frame(&self) -> CtlvRef<'a> {
+        self.frame
+    }
+
+    /// Returns the offset of this ctlv, relative to the start of the outermost input.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns whether this ctlv is a container.
+    pub fn is_container(&self) -> bool {
+        self.children.is_some()
+    }
+
+    /// Returns the children of this ctlv. Leaves have no children.
+    pub fn children(&self) -> &[CtlvTree<'a>] {
+        match &self.children {
+            Some(children) => children,
+            None => &[],
+        }
+    }
+
+    /// Returns an iterator over this tree in depth-first pre-order, yielding each node
+    /// together with its depth relative to this node.
+    pub fn depth_first(&self) -> DepthFirst<'_, 'a> {
+        DepthFirst { stack: vec![(0, self)] }
+    }
+}
+
+fn parse_sequence<'a, F>(input: &'a [u8],
+                         base: usize,
+                         depth: usize,
+                         is_container: &F,
+                         options: &DecoderOptions)
+                         -> Result<Vec<CtlvTree<'a>>, NestedError>
+    where F: Fn(u64) -> bool
+{
+    let mut trees = Vec::new();
+    let mut iter = CtlvIter::new(input);
+
+    loop {
+        let offset = base + iter.offset();
+        let frame = match iter.next() {
+            None => return Ok(trees),
+            Some(Err((e, inner))) => return Err(NestedError::Decode(e, base + inner)),
+            Some(Ok(frame)) => frame,
+        };
+
+        let children = if is_container(frame.type_) {
+            if depth >= options.max_depth {
+                return Err(NestedError::DepthExceeded(offset));
+            }
+            let value_offset = offset + frame.encoding_length() - frame.value.len();
+            Some(parse_sequence(frame.value, value_offset, depth + 1, is_container, options)?)
+        } else {
+            None
+        };
+
+        trees.push(CtlvTree {
+                       frame,
+                       offset,
+                       children,
+                   });
+    }
+}
+
+/// The outcome of [`get_path`](fn.get_path.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PathResult<'a> {
+    /// The ctlv at the end of the path.
+    Found(CtlvRef<'a>),
+    /// No ctlv of type `path[depth]` exists at the given depth.
+    Missing {
+        /// The index of the first path segment that could not be found.
+        depth: usize,
+    },
+}
+
+/// Follow a path of types through nested ctlvs, without building a tree and without allocating.
+///
+/// On each level, this finds the first ctlv whose type is the next type of the path, and then
+/// continues in the sequence encoded in its value. Only the sequences along the path are
+/// decoded, and only up to the first match. An empty path yields `Missing { depth: 0 }`.
+pub fn get_path<'a>(input: &'a [u8], path: &[u64]) -> Result<PathResult<'a>, NestedError> {
+    let mut sequence = input;
+    let mut base = 0;
+    let mut found = None;
+
+    for (depth, type_) in path.iter().enumerate() {
+        let mut iter = CtlvIter::new(sequence);
+        loop {
+            let offset = base + iter.offset();
+            match iter.next() {
+                None => return Ok(PathResult::Missing { depth }),
+                Some(Err((e, inner))) => return Err(NestedError::Decode(e, base + inner)),
+                Some(Ok(frame)) if frame.type_ == *type_ => {
+                    base = offset + frame.encoding_length() - frame.value.len();
+                    sequence = frame.value;
+                    found = Some(frame);
+                    break;
+                }
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+
+    Ok(match found {
+           Some(frame) => PathResult::Found(frame),
+           None => PathResult::Missing { depth: 0 },
+       })
+}
+
+/// Bring a sequence of nested ctlvs into canonical form.
+///
+/// In the canonical form, every sequence (the input itself as well as the values of all
+/// containers) is sorted by the encodings of its ctlvs, and contains no duplicates. This
+/// works bottom-up: the children of a container are canonicalized before the container
+/// itself is re-encoded, so that the lengths of all containers (and the number of bytes it
+/// takes to encode those lengths) are correct. Since sorting happens on the final encodings,
+/// canonicalizing a canonical input returns it unchanged.
+///
+/// Errors with `NestedError::ImpliedLength` if deduplication would change the length of a
+/// container whose type implies its length.
+pub fn canonicalize_nested<F>(input: &[u8],
+                              is_container: F,
+                              options: &DecoderOptions)
+                              -> Result<Vec<u8>, NestedError>
+    where F: Fn(u64) -> bool
+{
+    canonicalize_sequence(input, 0, 0, &is_container, options)
+}
+
+fn canonicalize_sequence<F>(input: &[u8],
+                            base: usize,
+                            depth: usize,
+                            is_container: &F,
+                            options: &DecoderOptions)
+                            -> Result<Vec<u8>, NestedError>
+    where F: Fn(u64) -> bool
+{
+    let mut encodings: Vec<Vec<u8>> = Vec::new();
+    let mut iter = CtlvIter::new(input);
+
+    loop {
+        let offset = base + iter.offset();
+        let frame = match iter.next() {
+            None => break,
+            Some(Err((e, inner))) => return Err(NestedError::Decode(e, base + inner)),
+            Some(Ok(frame)) => frame,
+        };
+
+        if is_container(frame.type_) {
+            if depth >= options.max_depth {
+                return Err(NestedError::DepthExceeded(offset));
+            }
+
+            let value_offset = offset + frame.encoding_length() - frame.value.len();
+            let value =
+                canonicalize_sequence(frame.value, value_offset, depth + 1, is_container, options)?;
+            if frame.is_implied_length() && value.len() != frame.value.len() {
+                return Err(NestedError::ImpliedLength(offset));
+            }
+
+            encodings.push(CtlvRef {
+                                   type_: frame.type_,
+                                   value: &value,
+                               }
+                               .encode_vec());
+        } else {
+            encodings.push(frame.encode_vec());
+        }
+    }
+
+    encodings.sort_unstable();
+    encodings.dedup();
+    Ok(encodings.concat())
+}
+
+/// A depth-first pre-order iterator over a `CtlvTree`, see
+/// [`CtlvTree::depth_first`](struct.CtlvTree.html#method.depth_first).
+#[derive(Debug, Clone)]
+pub struct DepthFirst<'t, 'a> {
+    stack: Vec<(usize, &'t CtlvTree<'a>)>,
+}
+
+impl<'t, 'a> Iterator for DepthFirst<'t, 'a> {
+    type Item = (usize, &'t CtlvTree<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (depth, node) = self.stack.pop()?;
+        self.stack
+            .extend(node.children().iter().rev().map(|child| (depth + 1, child)));
+        Some((depth, node))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_util::ctlv;
+
+    fn is_container(type_: u64) -> bool {
+        type_ == 300 || type_ == 200
+    }
+
+    #[test]
+    fn three_levels() {
+        let inner = [ctlv(0, vec![1]), ctlv(128, vec![2, 3])].concat();
+        let middle = [ctlv(200, inner), ctlv(1, vec![4])].concat();
+        let input = [ctlv(300, middle), ctlv(0, vec![5])].concat();
+
+        let trees = CtlvTree::parse(&input, is_container, &DecoderOptions::default()).unwrap();
+        assert_eq!(trees.len(), 2);
+        assert!(trees[0].is_container());
+        assert!(!trees[1].is_container());
+        assert_eq!(trees[1].value(), &[5][..]);
+        assert_eq!(trees[0].children()[0].children()[1].value(), &[2, 3][..]);
+
+        let nodes: Vec<_> = trees[0]
+            .depth_first()
+            .map(|(depth, node)| (depth, node.type_(), node.offset()))
+            .collect();
+        assert_eq!(nodes,
+                   vec![(0, 300, 0), (1, 200, 4), (2, 0, 6), (2, 128, 8), (1, 1, 12)]);
+        assert_eq!(&input[12..14], &[1, 4][..]);
+
+        assert_eq!(CtlvTree::parse(&input, is_container, &DecoderOptions { max_depth: 1 }),
+                   Err(NestedError::DepthExceeded(4)));
+    }
+
+    #[test]
+    fn paths() {
+        let inner = [ctlv(0, vec![1]), ctlv(12, vec![2, 3])].concat();
+        let middle = [ctlv(1, vec![4]), ctlv(200, inner)].concat();
+        let input = [ctlv(0, vec![5]), ctlv(300, middle)].concat();
+
+        assert_eq!(get_path(&input, &[300, 200, 12]),
+                   Ok(PathResult::Found(CtlvRef { type_: 12, value: &[2, 3] })));
+        assert_eq!(get_path(&input, &[300]),
+                   Ok(PathResult::Found(CtlvRef { type_: 300, value: &input[6..] })));
+        assert_eq!(get_path(&input, &[300, 201, 12]), Ok(PathResult::Missing { depth: 1 }));
+        assert_eq!(get_path(&input, &[]), Ok(PathResult::Missing { depth: 0 }));
+
+        // The value of the type 1 leaf is `[4]`, which is not a valid sequence.
+        assert_eq!(get_path(&input, &[300, 1, 4]),
+                   Err(NestedError::Decode(DecodeError::UnexpectedEndOfInput, 7)));
+    }
+
+    #[test]
+    fn canonicalize() {
+        let options = DecoderOptions::default();
+
+        let inner = [ctlv(128, vec![2]), ctlv(0, vec![1]), ctlv(128, vec![2])].concat();
+        let input = [ctlv(1, vec![9]), ctlv(300, inner), ctlv(0, vec![5])].concat();
+        let canonical = canonicalize_nested(&input, is_container, &options).unwrap();
+        let expected = [ctlv(0, vec![5]),
+                        ctlv(1, vec![9]),
+                        ctlv(300, [ctlv(0, vec![1]), ctlv(128, vec![2])].concat())]
+                .concat();
+        assert_eq!(canonical, expected);
+        assert_eq!(canonicalize_nested(&canonical, is_container, &options).unwrap(),
+                   canonical);
+
+        // 125 copies of the same child take up 250 bytes, so the length of the container
+        // takes two bytes to encode. Deduplication shrinks it to a single byte, which in turn
+        // changes the length of the enclosing container.
+        let duplicates = ctlv(0, vec![1]).repeat(125);
+        let input = ctlv(200, ctlv(300, duplicates));
+        assert_eq!(&input[..8], &[200, 248, 255, 249, 1, 44, 248, 250][..]);
+        assert_eq!(canonicalize_nested(&input, is_container, &options).unwrap(),
+                   [200, 6, 249, 1, 44, 2, 0, 1]);
+
+        let implied = ctlv(16, [ctlv(0, vec![1]), ctlv(0, vec![1])].concat());
+        assert_eq!(canonicalize_nested(&implied, |type_| type_ == 16, &options),
+                   Err(NestedError::ImpliedLength(0)));
+        assert_eq!(canonicalize_nested(&input, is_container, &DecoderOptions { max_depth: 1 }),
+                   Err(NestedError::DepthExceeded(3)));
+    }
+
+    #[test]
+    fn invalid_inner_sequence() {
+        let middle = [ctlv(1, vec![4]), vec![128, 5, 0]].concat();
+        let input = [ctlv(0, vec![5]), ctlv(300, middle)].concat();
+
+        assert_eq!(CtlvTree::parse(&input, is_container, &DecoderOptions::default()),
+                   Err(NestedError::Decode(DecodeError::UnexpectedEndOfInput, 8)));
+    }
+}
@@ -0,0 +1,52 @@
+use rayon::prelude::*;
+
+use super::{Ctlv, CtlvIndex, CtlvRef, DecodeError};
+
+impl CtlvIndex {
+    /// Returns a parallel iterator over the indexed ctlvs, decoding them from the input the
+    /// index was built from. The ctlvs keep their order when collected.
+    ///
+    /// Requires the `rayon` feature.
+    ///
+    /// # Panics
+    /// Panics if a ctlv can not be decoded, which only happens if the input is not the one the
+    /// index was built from.
+    pub fn par_frames<'a>(&'a self,
+                          input: &'a [u8])
+                          -> impl IndexedParallelIterator<Item = CtlvRef<'a>> + 'a {
+        (0..self.len()).into_par_iter().map(move |i| {
+            self.get(i, input).expect("input does not match the index")
+        })
+    }
+}
+
+/// Decode all ctlvs of an encoded sequence into owned `Ctlv`s, copying the values in parallel.
+///
+/// The ctlvs are located by building a [`CtlvIndex`](struct.CtlvIndex.html) first, which is
+/// the only part that runs sequentially. The result is the same as that of
+/// [`decode_all`](fn.decode_all.html).
+///
+/// Requires the `rayon` feature.
+pub fn par_decode_all(input: &[u8]) -> Result<Vec<Ctlv>, (DecodeError, usize)> {
+    let index = CtlvIndex::build(input)?;
+    Ok(index.par_frames(input).map(CtlvRef::into_owned).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::decode_all;
+
+    #[test]
+    fn same_as_serial() {
+        let input: Vec<u8> = (0..5000u64)
+            .map(|i| Ctlv::new(128 + i, vec![i as u8; (i % 50) as usize]).encode_vec())
+            .collect::<Vec<_>>()
+            .concat();
+
+        assert_eq!(par_decode_all(&input).unwrap(), decode_all(&input).unwrap());
+
+        let truncated = &input[..input.len() - 1];
+        assert_eq!(par_decode_all(truncated), decode_all(truncated));
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use super::nested::{DecoderOptions, NestedError};
+use super::{CtlvIter, CtlvRef};
+
+/// Options for [`format_tree`](fn.format_tree.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// The limits for descending into containers. Containers nested too deeply are printed
+    /// like invalid containers.
+    pub decoder: DecoderOptions,
+    /// How many bytes of each leaf value to show at most.
+    pub preview_len: usize,
+    /// Human-readable names of types, printed after the type number.
+    pub type_names: BTreeMap<u64, String>,
+}
+
+impl Default for FormatOptions {
+    /// Uses the default `DecoderOptions`, previews up to 16 bytes, and knows no type names.
+    fn default() -> FormatOptions {
+        FormatOptions {
+            decoder: DecoderOptions::default(),
+            preview_len: 16,
+            type_names: BTreeMap::new(),
+        }
+    }
+}
+
+/// Render a sequence of nested ctlvs as human-readable text, for debugging.
+///
+/// Every ctlv gets one line, indented by two spaces per nesting level, showing its offset,
+/// its type (and the name of the type, if known), and the length of its value. Leaves are
+/// followed by a preview of their value in hex and ASCII:
+///
+/// ```text
+/// @0 type 300 (message) len 7
+///   @4 type 0 len 1: 2a |*|
+///   @6 type 128 len 3: 01 02 03 |...|
+/// ```
+///
+/// A container whose value is not a valid sequence is printed like a leaf, with a `!` marker
+/// describing the problem. Only an invalid top-level sequence is reported as an error.
+pub fn format_tree<F>(input: &[u8],
+                      is_container: F,
+                      options: &FormatOptions)
+                      -> Result<String, NestedError>
+    where F: Fn(u64) -> bool
+{
+    let mut out = String::new();
+    format_sequence(&mut out, input, 0, 0, &is_container, options)?;
+    Ok(out)
+}
+
+fn format_sequence<F>(out: &mut String,
+                      input: &[u8],
+                      base: usize,
+                      depth: usize,
+                      is_container: &F,
+                      options: &FormatOptions)
+                      -> Result<(), NestedError>
+    where F: Fn(u64) -> bool
+{
+    let mut iter = CtlvIter::new(input);
+
+    loop {
+        let offset = base + iter.offset();
+        let frame = match iter.next() {
+            None => return Ok(()),
+            Some(Err((e, inner))) => return Err(NestedError::Decode(e, base + inner)),
+            Some(Ok(frame)) => frame,
+        };
+
+        format_header(out, frame, offset, depth, options);
+
+        if is_container(frame.type_) {
+            let problem = if depth >= options.decoder.max_depth {
+                NestedError::DepthExceeded(offset)
+            } else {
+                let value_offset = offset + frame.encoding_length() - frame.value.len();
+                let mut children = String::new();
+                match format_sequence(&mut children,
+                                      frame.value,
+                                      value_offset,
+                                      depth + 1,
+                                      is_container,
+                                      options) {
+                    Ok(()) => {
+                        out.push('\n');
+                        out.push_str(&children);
+                        continue;
+                    }
+                    Err(e) => e,
+                }
+            };
+            write!(out, " !{}", problem).unwrap();
+        }
+
+        format_preview(out, frame.value, options.preview_len);
+        out.push('\n');
+    }
+}
+
+fn format_header(out: &mut String,
+                 frame: CtlvRef,
+                 offset: usize,
+                 depth: usize,
+                 options: &FormatOptions) {
+    for _ in 0..depth {
+        out.push_str("  ");
+    }
+    write!(out, "@{} type {}", offset, frame.type_).unwrap();
+    if let Some(name) = options.type_names.get(&frame.type_) {
+        write!(out, " ({})", name).unwrap();
+    }
+    write!(out, " len {}", frame.value.len()).unwrap();
+}
+
+fn format_preview(out: &mut String, value: &[u8], preview_len: usize) {
+    let shown = &value[..value.len().min(preview_len)];
+    let ellipsis = if shown.len() < value.len() { " .." } else { "" };
+
+    out.push(':');
+    for b in shown {
+        write!(out, " {:02x}", b).unwrap();
+    }
+    write!(out, "{} |", ellipsis).unwrap();
+    for b in shown {
+        out.push(if b.is_ascii_graphic() || *b == b' ' {
+                     *b as char
+                 } else {
+                     '.'
+                 });
+    }
+    write!(out, "|{}", ellipsis).unwrap();
+}
+
+/// Describe a sequence of ctlvs line by line, for debugging input that fails to decode.
+///
+/// Every ctlv that decodes gets one line with its offset, type, length and the full value in
+/// hex. A final line tells where and why decoding stopped, so this never fails, whatever the
+/// input:
+///
+/// ```
+/// assert_eq!(ctlv::dump(&[128, 1, 42, 0]),
+///            "[offset 0] type=128 len=1 value=2a\n\
+///             [offset 3] stopped: Invalid ctlv: Not enough input bytes\n");
+/// ```
+///
+/// Unlike [`format_tree`](fn.format_tree.html), this does not descend into containers.
+pub fn dump(input: &[u8]) -> String {
+    let mut out = String::new();
+    let mut iter = CtlvIter::new(input);
+
+    loop {
+        let offset = iter.offset();
+        match iter.next() {
+            None => {
+                writeln!(out, "[offset {}] end of input", offset).unwrap();
+                return out;
+            }
+            Some(Err((e, offset))) => {
+                writeln!(out, "[offset {}] stopped: {}", offset, e).unwrap();
+                return out;
+            }
+            Some(Ok(frame)) => {
+                write!(out,
+                       "[offset {}] type={} len={} value=",
+                       offset,
+                       frame.type_,
+                       frame.value.len())
+                        .unwrap();
+                for b in frame.value {
+                    write!(out, "{:02x}", b).unwrap();
+                }
+                out.push('\n');
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::DecodeError;
+    use super::super::test_util::ctlv;
+
+    fn is_container(type_: u64) -> bool {
+        type_ == 300 || type_ == 200
+    }
+
+    #[test]
+    fn nested_fixture() {
+        let inner = [ctlv(0, vec![0x2a]), ctlv(128, b"hi!".to_vec())].concat();
+        let middle = [ctlv(1, vec![7]), ctlv(200, inner)].concat();
+        let input = [ctlv(300, middle), ctlv(129, (0..20).collect())].concat();
+
+        let mut options = FormatOptions::default();
+        options.type_names.insert(300, "message".to_string());
+        options.type_names.insert(128, "text".to_string());
+
+        assert_eq!(format_tree(&input, is_container, &options).unwrap(),
+                   "@0 type 300 (message) len 11\n\
+                    \x20 @4 type 1 len 1: 07 |.|\n\
+                    \x20 @6 type 200 len 7\n\
+                    \x20   @8 type 0 len 1: 2a |*|\n\
+                    \x20   @10 type 128 (text) len 3: 68 69 21 |hi!|\n\
+                    @15 type 129 len 20: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f .. \
+                    |................| ..\n");
+    }
+
+    #[test]
+    fn invalid_containers() {
+        let invalid = ctlv(200, vec![128, 5, 0]);
+        let input = [ctlv(300, invalid), ctlv(0, vec![1])].concat();
+
+        assert_eq!(format_tree(&input, is_container, &FormatOptions::default()).unwrap(),
+                   "@0 type 300 len 5\n\
+                    \x20 @4 type 200 len 3 !Invalid ctlv: Not enough input bytes (at offset 6): \
+                    80 05 00 |...|\n\
+                    @9 type 0 len 1: 01 |.|\n");
+
+        let mut options = FormatOptions::default();
+        options.decoder.max_depth = 0;
+        assert_eq!(format_tree(&ctlv(300, vec![]), is_container, &options).unwrap(),
+                   "@0 type 300 len 0 !Invalid nested ctlv: container at offset 0 is nested too \
+                    deeply: ||\n");
+
+        assert_eq!(format_tree(&[0], is_container, &options),
+                   Err(NestedError::Decode(DecodeError::UnexpectedEndOfInput, 0)));
+    }
+
+    #[test]
+    fn dumps() {
+        let input = [ctlv(128, vec![0x2a]), ctlv(8, vec![1, 0xff]), ctlv(300, vec![])].concat();
+        assert_eq!(dump(&input),
+                   "[offset 0] type=128 len=1 value=2a\n\
+                    [offset 3] type=8 len=2 value=01ff\n\
+                    [offset 6] type=300 len=0 value=\n\
+                    [offset 10] end of input\n");
+        assert_eq!(dump(&[]), "[offset 0] end of input\n");
+
+        // Malformed input, including every truncation of valid input.
+        assert_eq!(dump(&[0, 1, 255]),
+                   "[offset 0] type=0 len=1 value=01\n\
+                    [offset 2] stopped: Invalid ctlv: Not enough input bytes\n");
+        assert_eq!(dump(&[248, 5]),
+                   format!("[offset 0] stopped: {}\n",
+                           CtlvRef::decode(&[248, 5]).unwrap_err().0));
+        for len in 1..input.len() {
+            let complete = len == 3 || len == 6;
+            assert_eq!(dump(&input[..len]).ends_with(" end of input\n"), complete);
+        }
+    }
+}
@@ -0,0 +1,535 @@
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::{error, fmt};
+
+use varu64::DecodeError as VarU64Error;
+
+use super::DecodeError::{self, *};
+use super::{encode_header, type_implied_length, Ctlv, CtlvRef, DecodeConfig, Header,
+            EXPLICIT_LENGTH_MIN_TYPE, MAX_HEADER_LENGTH};
+
+/// Everything that can go wrong when decoding a ctlv from an `io::Read`.
+#[derive(Debug)]
+pub enum ReadDecodeError {
+    /// The reader was at its end before reading the first byte of the ctlv. When reading a
+    /// sequence of ctlvs, this signals its regular end.
+    Eof,
+    /// The reader ended in the middle of the ctlv, after the given number of its bytes.
+    UnexpectedEof {
+        /// How many bytes of the ctlv were read.
+        consumed: usize,
+    },
+    /// The bytes read do not form a valid ctlv. Never `DecodeError::UnexpectedEndOfInput`,
+    /// that case is reported as `ReadDecodeError::UnexpectedEof` instead.
+    Decode(DecodeError),
+    /// Reading failed with the wrapped error.
+    Io(io::Error),
+}
+
+impl fmt::Display for ReadDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        match self {
+            ReadDecodeError::Eof => write!(f, "No ctlv: end of input"),
+            ReadDecodeError::UnexpectedEof { consumed } => {
+                write!(f, "Invalid ctlv: input ended after {} bytes", consumed)
+            }
+            ReadDecodeError::Decode(e) => e.fmt(f),
+            ReadDecodeError::Io(e) => write!(f, "Could not read ctlv: {}", e),
+        }
+    }
+}
+
+impl error::Error for ReadDecodeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ReadDecodeError::Decode(e) => Some(e),
+            ReadDecodeError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ReadDecodeError {
+    fn from(e: io::Error) -> ReadDecodeError {
+        ReadDecodeError::Io(e)
+    }
+}
+
+// Reads a single byte, returning `None` at the end of the reader.
+fn read_byte<R: Read>(r: &mut R) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    loop {
+        match r.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Decodes a header from bytes that are fed one at a time, shared by the synchronous and the
+// asynchronous readers.
+pub(crate) struct HeaderParser<'c> {
+    config: &'c DecodeConfig,
+    bytes: [u8; MAX_HEADER_LENGTH],
+    len: usize,
+    // Where the varu64 that is being decoded starts.
+    start: usize,
+    type_: Option<u64>,
+}
+
+impl<'c> HeaderParser<'c> {
+    pub(crate) fn new(config: &'c DecodeConfig) -> HeaderParser<'c> {
+        HeaderParser {
+            config,
+            bytes: [0; MAX_HEADER_LENGTH],
+            len: 0,
+            start: 0,
+            type_: None,
+        }
+    }
+
+    // How many bytes have been fed so far.
+    pub(crate) fn consumed(&self) -> usize {
+        self.len
+    }
+
+    // Feeds the next byte, returning the header once it is complete.
+    pub(crate) fn push(&mut self, byte: u8) -> Result<Option<Header>, DecodeError> {
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        let current = &self.bytes[self.start..self.len];
+        if current[0] >= 248 && current.len() < (current[0] - 246) as usize {
+            return Ok(None);
+        }
+
+        let n = match varu64::decode(current) {
+            Ok((n, _)) => n,
+            Err((VarU64Error::NonCanonical(n), _)) if !self.config.require_canonical => n,
+            Err((e, _)) if self.type_.is_none() => return Err(Type(e)),
+            Err((e, _)) => return Err(Length(e)),
+        };
+        let header = match self.type_ {
+            None if n >= EXPLICIT_LENGTH_MIN_TYPE => {
+                self.type_ = Some(n);
+                self.start = self.len;
+                return Ok(None);
+            }
+            None => {
+                Header {
+                    type_: n,
+                    len: type_implied_length(n) as u64,
+                }
+            }
+            Some(type_) => Header { type_, len: n },
+        };
+
+        if let Some(max) = self.config.max_value_len {
+            if header.len > max as u64 {
+                return Err(LengthTooLarge {
+                               declared: header.len,
+                               max,
+                           });
+            }
+        }
+        Ok(Some(header))
+    }
+}
+
+impl Ctlv {
+    /// Decode a `Ctlv` from a reader, reading exactly the bytes of its encoding.
+    ///
+    /// Errors with `ReadDecodeError::Eof` if the reader is at its end already, so that
+    /// a loop reading a sequence of ctlvs can tell its end apart from a truncated ctlv.
+    ///
+    /// The reader is read from in small pieces, so wrapping it in an `io::BufReader` is
+    /// recommended. The value is read incrementally as well, so a malicious length does not
+    /// cause a large allocation unless the data is actually there; use
+    /// [`decode_read_with`](#method.decode_read_with) to limit the length of values.
+    pub fn decode_read<R: Read>(r: R) -> Result<Ctlv, ReadDecodeError> {
+        Ctlv::decode_read_with(r, &DecodeConfig::default())
+    }
+
+    /// Decode a `Ctlv` from a reader like [`decode_read`](#method.decode_read), but with the
+    /// checks configured by `config`.
+    ///
+    /// If the length of the value exceeds `config.max_value_len`, this errors with
+    /// `DecodeError::LengthTooLarge` right after reading the header, without reading any of
+    /// the value.
+    pub fn decode_read_with<R: Read>(mut r: R,
+                                     config: &DecodeConfig)
+                                     -> Result<Ctlv, ReadDecodeError> {
+        let (header, header_len) = read_header_impl(&mut r, config)?;
+        let mut value = Vec::new();
+        r.take(header.len).read_to_end(&mut value)?;
+        if (value.len() as u64) < header.len {
+            return Err(ReadDecodeError::UnexpectedEof { consumed: header_len + value.len() });
+        }
+        Ok(Ctlv::new(header.type_, value))
+    }
+}
+
+/// Decode the header of a ctlv from a reader, reading exactly the bytes of the type and the
+/// length, so that the reader is left at the start of the value.
+///
+/// Errors like [`Ctlv::decode_read`](struct.CtlvGeneric.html#method.decode_read), in
+/// particular with `ReadDecodeError::Eof` if the reader is at its end already.
+pub fn read_header<R: Read>(r: &mut R) -> Result<Header, ReadDecodeError> {
+    read_header_with(r, &DecodeConfig::default())
+}
+
+/// Decode the header of a ctlv from a reader like [`read_header`](fn.read_header.html), but
+/// with the checks configured by `config`.
+pub fn read_header_with<R: Read>(r: &mut R,
+                                 config: &DecodeConfig)
+                                 -> Result<Header, ReadDecodeError> {
+    read_header_impl(r, config).map(|(header, _)| header)
+}
+
+/// Decode the header of a ctlv from a reader, returning it together with a reader of exactly
+/// its value, so that the value can be streamed without buffering it.
+///
+/// The value must be read completely (or skipped with [`skip_value`](fn.skip_value.html))
+/// before decoding the next ctlv from the inner reader, which `Take::into_inner` returns.
+/// If the reader ends within the value, the `Take` simply ends early.
+pub fn read_frame_streaming<R: Read>(mut r: R) -> Result<(Header, io::Take<R>), ReadDecodeError> {
+    let header = read_header(&mut r)?;
+    Ok((header, r.take(header.len)))
+}
+
+/// Discards the remainder of a value returned by
+/// [`read_frame_streaming`](fn.read_frame_streaming.html), by reading it into a sink. Returns
+/// how many bytes have been discarded.
+///
+/// Errors with `io::ErrorKind::UnexpectedEof` if the reader ends before the value does.
+pub fn skip_value<R: Read>(value: &mut io::Take<R>) -> io::Result<u64> {
+    let remaining = value.limit();
+    let skipped = io::copy(value, &mut io::sink())?;
+    if skipped < remaining {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "value ended early"));
+    }
+    Ok(skipped)
+}
+
+/// Discards the remainder of a value returned by
+/// [`read_frame_streaming`](fn.read_frame_streaming.html) like
+/// [`skip_value`](fn.skip_value.html), but by seeking over it instead of reading it.
+///
+/// Seeking past the end of the reader does not fail, so unlike `skip_value` this does not
+/// detect truncated values.
+pub fn skip_value_seek<R: Read + Seek>(value: &mut io::Take<R>) -> io::Result<u64> {
+    let remaining = value.limit();
+    value.get_mut().seek(SeekFrom::Current(remaining as i64))?;
+    value.set_limit(0);
+    Ok(remaining)
+}
+
+/// Copies the value of a ctlv whose `header` has already been read from `r` to `w`, returning
+/// how many bytes have been copied.
+///
+/// Errors with `io::ErrorKind::UnexpectedEof` if the reader ends before the value does, in
+/// which case the bytes that were available have been copied already.
+pub fn copy_value<R: Read, W: Write>(header: &Header, r: &mut R, w: &mut W) -> io::Result<u64> {
+    let copied = io::copy(&mut r.take(header.len), w)?;
+    if copied < header.len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "value ended early"));
+    }
+    Ok(copied)
+}
+
+/// Forwards a ctlv from `r` to `w` without buffering its value, returning its header.
+///
+/// The header is decoded and encoded again, so the forwarded ctlv is checked to be canonical,
+/// then its value is copied with [`copy_value`](fn.copy_value.html). If the reader ends within
+/// the value, this errors with `ReadDecodeError::UnexpectedEof` after writing the available
+/// part of the ctlv.
+pub fn transfer_frame<R: Read, W: Write>(r: &mut R, w: &mut W) -> Result<Header, ReadDecodeError> {
+    let (header, header_len) = read_header_impl(r, &DecodeConfig::default())?;
+
+    let mut encoded = [0; MAX_HEADER_LENGTH];
+    let encoded_len = encode_header(header.type_, header.len, &mut encoded);
+    w.write_all(&encoded[..encoded_len])?;
+
+    let copied = io::copy(&mut r.take(header.len), w)?;
+    if copied < header.len {
+        return Err(ReadDecodeError::UnexpectedEof { consumed: header_len + copied as usize });
+    }
+    Ok(header)
+}
+
+// Reads a header, returning it and the number of bytes it took up.
+pub(crate) fn read_header_impl<R: Read>(r: &mut R,
+                                        config: &DecodeConfig)
+                                        -> Result<(Header, usize), ReadDecodeError> {
+    let mut parser = HeaderParser::new(config);
+    loop {
+        let byte = match read_byte(r)? {
+            Some(byte) => byte,
+            None if parser.consumed() == 0 => return Err(ReadDecodeError::Eof),
+            None => return Err(ReadDecodeError::UnexpectedEof { consumed: parser.consumed() }),
+        };
+        if let Some(header) = parser.push(byte).map_err(ReadDecodeError::Decode)? {
+            return Ok((header, parser.consumed()));
+        }
+    }
+}
+
+/// Decode a `Ctlv` from a buffered reader, like
+/// [`Ctlv::decode_read`](struct.CtlvGeneric.html#method.decode_read), but copying the ctlv
+/// out of the buffer of the reader in one go if it is contained in there completely.
+pub fn decode_from_bufread<R: BufRead>(r: &mut R) -> Result<Ctlv, ReadDecodeError> {
+    let next = next_frame(r, |frame| match frame {
+        Frame::Borrowed(frame) => frame.into_owned(),
+        Frame::Owned(ctlv) => ctlv,
+    })?;
+    match next {
+        Some(ctlv) => Ok(ctlv),
+        None => Err(ReadDecodeError::Eof),
+    }
+}
+
+/// Decode the next ctlv from a buffered reader and pass it to `f`, returning the result of `f`,
+/// or `None` if the reader is at its end.
+///
+/// If the ctlv is contained completely in the buffer of the reader, `f` gets to borrow it from
+/// there, without any copying. Otherwise, the ctlv is read into an owned `Ctlv` first. Exactly
+/// the bytes of the ctlv are consumed from the reader, so the next call continues with the
+/// next ctlv. After an error, it is unspecified how many bytes have been consumed.
+pub fn with_next_frame<R, T, F>(r: &mut R, f: F) -> Result<Option<T>, ReadDecodeError>
+    where R: BufRead,
+          F: FnOnce(CtlvRef) -> T
+{
+    next_frame(r, |frame| match frame {
+        Frame::Borrowed(frame) => f(frame),
+        Frame::Owned(ctlv) => f(ctlv.as_ctlv_ref()),
+    })
+}
+
+// A ctlv decoded from a `BufRead`, either borrowed from its buffer or read into a `Ctlv`.
+enum Frame<'a> {
+    Borrowed(CtlvRef<'a>),
+    Owned(Ctlv),
+}
+
+fn next_frame<R, T, F>(r: &mut R, f: F) -> Result<Option<T>, ReadDecodeError>
+    where R: BufRead,
+          F: FnOnce(Frame) -> T
+{
+    let buf = loop {
+        match r.fill_buf() {
+            Ok(buf) => break buf,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(ReadDecodeError::Io(e)),
+        }
+    };
+    if buf.is_empty() {
+        return Ok(None);
+    }
+
+    match CtlvRef::decode(buf) {
+        Ok((frame, tail)) => {
+            let consumed = buf.len() - tail.len();
+            let result = f(Frame::Borrowed(frame));
+            r.consume(consumed);
+            Ok(Some(result))
+        }
+        // The ctlv continues past the buffer, nothing has been consumed yet.
+        Err((UnexpectedEndOfInput, _)) => {
+            Ctlv::decode_read(r).map(|ctlv| Some(f(Frame::Owned(ctlv))))
+        }
+        Err((e, _)) => Err(ReadDecodeError::Decode(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn sequence() {
+        let mut r = Cursor::new(vec![0, 1, 128, 2, 3, 4, 249, 1, 44, 0, 8, 5, 6]);
+        assert_eq!(Ctlv::decode_read(&mut r).unwrap(), Ctlv::new(0, vec![1]));
+        assert_eq!(Ctlv::decode_read(&mut r).unwrap(), Ctlv::new(128, vec![3, 4]));
+        assert_eq!(Ctlv::decode_read(&mut r).unwrap(), Ctlv::new(300, vec![]));
+        assert_eq!(Ctlv::decode_read(&mut r).unwrap(), Ctlv::new(8, vec![5, 6]));
+        assert!(matches!(Ctlv::decode_read(&mut r), Err(ReadDecodeError::Eof)));
+        assert!(matches!(Ctlv::decode_read(&[][..]), Err(ReadDecodeError::Eof)));
+    }
+
+    #[test]
+    fn truncated() {
+        let cases: [(&[u8], usize); 5] = [(&[128, 3, 1, 2], 4),
+                                          (&[8, 1], 2),
+                                          (&[128], 1),
+                                          (&[249, 1], 2),
+                                          (&[249, 1, 44, 249], 4)];
+        for (input, consumed) in &cases {
+            match Ctlv::decode_read(Cursor::new(input)) {
+                Err(ReadDecodeError::UnexpectedEof { consumed: c }) => assert_eq!(c, *consumed),
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(matches!(Ctlv::decode_read(&[248, 5, 42][..]),
+                         Err(ReadDecodeError::Decode(Type(VarU64Error::NonCanonical(5))))));
+        assert!(matches!(Ctlv::decode_read(&[128, 248, 1, 7][..]),
+                         Err(ReadDecodeError::Decode(Length(VarU64Error::NonCanonical(1))))));
+
+        let lenient = DecodeConfig {
+            require_canonical: false,
+            ..DecodeConfig::default()
+        };
+        assert_eq!(Ctlv::decode_read_with(&[128, 248, 1, 7][..], &lenient).unwrap(),
+                   Ctlv::new(128, vec![7]));
+    }
+
+    #[test]
+    fn max_value_len() {
+        let config = DecodeConfig {
+            max_value_len: Some(2),
+            ..DecodeConfig::default()
+        };
+        // Nothing after the header is read.
+        let mut r = Cursor::new(vec![128, 3, 1, 2, 3]);
+        assert!(matches!(Ctlv::decode_read_with(&mut r, &config),
+                         Err(ReadDecodeError::Decode(LengthTooLarge { declared: 3, max: 2 }))));
+        assert_eq!(r.position(), 2);
+
+        assert!(matches!(Ctlv::decode_read_with(&[16, 1, 2, 3, 4][..], &config),
+                         Err(ReadDecodeError::Decode(LengthTooLarge { declared: 4, max: 2 }))));
+        assert_eq!(Ctlv::decode_read_with(&[8, 1, 2][..], &config).unwrap(),
+                   Ctlv::new(8, vec![1, 2]));
+    }
+
+    #[test]
+    fn bufread() {
+        let frames = [Ctlv::new(0, vec![1]),
+                      Ctlv::new(300, vec![2; 10]),
+                      Ctlv::new(8, vec![3, 4]),
+                      Ctlv::new(128, vec![]),
+                      Ctlv::new(129, vec![5, 6, 7])];
+        let input: Vec<_> = frames.iter().map(Ctlv::encode_vec).collect();
+        let input = input.concat();
+
+        // With a buffer of four bytes, some ctlvs fit into it and others straddle refills.
+        let mut r = io::BufReader::with_capacity(4, &input[..]);
+        for frame in &frames {
+            assert_eq!(with_next_frame(&mut r, |ctlv| ctlv.to_owned()).unwrap().as_ref(),
+                       Some(frame));
+        }
+        assert_eq!(with_next_frame(&mut r, |ctlv| ctlv.type_).unwrap(), None);
+
+        let mut r = io::BufReader::with_capacity(4, &input[..]);
+        for frame in &frames {
+            assert_eq!(&decode_from_bufread(&mut r).unwrap(), frame);
+        }
+        assert!(matches!(decode_from_bufread(&mut r), Err(ReadDecodeError::Eof)));
+    }
+
+    #[test]
+    fn bufread_invalid() {
+        let mut r = io::BufReader::with_capacity(4, &[0, 1, 128, 3, 1][..]);
+        assert_eq!(with_next_frame(&mut r, |ctlv| ctlv.value[0]).unwrap(), Some(1));
+        assert!(matches!(with_next_frame(&mut r, |_| ()),
+                         Err(ReadDecodeError::UnexpectedEof { consumed: 3 })));
+
+        let mut r = io::BufReader::new(&[248, 5, 42][..]);
+        assert!(matches!(decode_from_bufread(&mut r),
+                         Err(ReadDecodeError::Decode(Type(VarU64Error::NonCanonical(5))))));
+    }
+
+    #[test]
+    fn streaming() {
+        let value: Vec<u8> = (0..5_000_000u32).map(|i| (i % 251) as u8).collect();
+        let mut input = Ctlv::new(300, value).encode_vec();
+        input.extend_from_slice(&[0, 42]);
+        let mut r = &input[..];
+
+        let (header, mut value) = read_frame_streaming(&mut r).unwrap();
+        assert_eq!(header, Header { type_: 300, len: 5_000_000 });
+        let mut buf = [0; 4096];
+        let mut total = 0;
+        loop {
+            let n = value.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            assert!(buf[..n].iter().enumerate().all(|(i, b)| *b == ((total + i) % 251) as u8));
+            total += n;
+        }
+        assert_eq!(total, 5_000_000);
+        assert_eq!(Ctlv::decode_read(value.into_inner()).unwrap(),
+                   Ctlv::new(0, vec![42]));
+        assert!(matches!(read_header(&mut r), Err(ReadDecodeError::Eof)));
+    }
+
+    #[test]
+    fn skip() {
+        let input = [Ctlv::new(128, vec![1; 10000]).encode_vec(), vec![8, 2, 3]];
+        let input = input.concat();
+
+        let (header, mut value) = read_frame_streaming(&input[..]).unwrap();
+        assert_eq!(header.len, 10000);
+        value.read_exact(&mut [0; 10]).unwrap();
+        assert_eq!(skip_value(&mut value).unwrap(), 9990);
+        assert_eq!(read_header(&mut value.into_inner()).unwrap(), Header { type_: 8, len: 2 });
+
+        let (_, mut value) = read_frame_streaming(Cursor::new(&input)).unwrap();
+        assert_eq!(skip_value_seek(&mut value).unwrap(), 10000);
+        assert_eq!(value.limit(), 0);
+        let mut r = value.into_inner();
+        assert_eq!(r.position(), 10004);
+        assert_eq!(Ctlv::decode_read(&mut r).unwrap(), Ctlv::new(8, vec![2, 3]));
+
+        let (_, mut value) = read_frame_streaming(&input[..100]).unwrap();
+        assert_eq!(skip_value(&mut value).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert!(matches!(read_header(&mut &[128][..]),
+                         Err(ReadDecodeError::UnexpectedEof { consumed: 1 })));
+    }
+
+    #[test]
+    fn transfer() {
+        let frames = [Ctlv::new(300, (0..=255).cycle().take(20000).collect()),
+                      Ctlv::new(128, vec![]),
+                      Ctlv::new(0, vec![1])];
+        let input: Vec<_> = frames.iter().map(Ctlv::encode_vec).collect();
+        let input = input.concat();
+
+        let mut r = &input[..];
+        let mut out = Vec::new();
+        for frame in &frames {
+            let header = transfer_frame(&mut r, &mut out).unwrap();
+            assert_eq!(header.len, frame.value().len() as u64);
+        }
+        assert_eq!(out, input);
+        assert!(matches!(transfer_frame(&mut r, &mut out), Err(ReadDecodeError::Eof)));
+
+        let mut r = &input[..100];
+        let mut out = Vec::new();
+        assert!(matches!(transfer_frame(&mut r, &mut out),
+                         Err(ReadDecodeError::UnexpectedEof { consumed: 100 })));
+        assert_eq!(out, input[..100]);
+    }
+
+    #[test]
+    fn copy() {
+        let mut r = &[128, 0, 8, 1, 2][..];
+        let mut out = Vec::new();
+        let header = read_header(&mut r).unwrap();
+        assert_eq!(copy_value(&header, &mut r, &mut out).unwrap(), 0);
+        let header = read_header(&mut r).unwrap();
+        assert_eq!(copy_value(&header, &mut r, &mut out).unwrap(), 2);
+        assert_eq!(out, [1, 2]);
+
+        let mut r = &[128, 3, 1, 2][..];
+        let mut out = Vec::new();
+        let header = read_header(&mut r).unwrap();
+        assert_eq!(copy_value(&header, &mut r, &mut out).unwrap_err().kind(),
+                   io::ErrorKind::UnexpectedEof);
+        assert_eq!(out, [1, 2]);
+    }
+}
@@ -0,0 +1,310 @@
+use std::io::{self, Read};
+use std::iter::FusedIterator;
+
+use super::read::ReadDecodeError;
+use super::{Ctlv, CtlvRef, DecodeConfig, DecodeError};
+
+// How many bytes a `CtlvReader` asks its reader for at a time.
+const CHUNK_LEN: usize = 8 * 1024;
+
+/// Reads a sequence of ctlvs from an `io::Read`, through an internal buffer.
+///
+/// The underlying reader is read from in chunks of several kilobytes. Bytes following a ctlv
+/// stay in the buffer for the next one, and the buffer only ever holds the ctlv that is being
+/// decoded (plus the remainder of the last chunk), so it stays small unless the values are
+/// large. Use a [`DecodeConfig`](struct.DecodeConfig.html) with a `max_value_len` to bound it.
+#[derive(Debug)]
+pub struct CtlvReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    // The bytes in `buf` that have been read but not decoded yet.
+    start: usize,
+    end: usize,
+    config: DecodeConfig,
+    frames: usize,
+    bytes: usize,
+}
+
+impl<R: Read> CtlvReader<R> {
+    /// Creates a `CtlvReader` with the default `DecodeConfig`.
+    pub fn new(inner: R) -> CtlvReader<R> {
+        CtlvReader::with_config(inner, DecodeConfig::default())
+    }
+
+    /// Creates a `CtlvReader` that decodes with the given configuration.
+    pub fn with_config(inner: R, config: DecodeConfig) -> CtlvReader<R> {
+        CtlvReader {
+            inner,
+            buf: Vec::new(),
+            start: 0,
+            end: 0,
+            config,
+            frames: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Returns how many ctlvs have been read.
+    pub fn frames_read(&self) -> usize {
+        self.frames
+    }
+
+    /// Returns the combined length of the encodings of all ctlvs that have been read. This does
+    /// not include bytes that have been read from the underlying reader but not decoded yet.
+    pub fn bytes_read(&self) -> usize {
+        self.bytes
+    }
+
+    /// Returns the underlying reader, together with the bytes that have been read from it but
+    /// not decoded yet. Reading the returned bytes, then the reader, continues right after the
+    /// last ctlv that has been read.
+    pub fn into_inner(self) -> (R, Vec<u8>) {
+        let mut buf = self.buf;
+        buf.truncate(self.end);
+        buf.drain(..self.start);
+        (self.inner, buf)
+    }
+
+    /// Reads the next ctlv as an owned `Ctlv`.
+    ///
+    /// Returns `Ok(None)` if the underlying reader ends after the previous ctlv, and
+    /// `ReadDecodeError::UnexpectedEof` (with the number of bytes of the incomplete ctlv) if it
+    /// ends in the middle of a ctlv. After a decoding error, every further call returns the
+    /// same error.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<Ctlv>, ReadDecodeError> {
+        Ok(self.next_ref()?.map(CtlvRef::into_owned))
+    }
+
+    /// Reads the next ctlv like [`next`](#method.next), but returns it borrowed from the
+    /// internal buffer.
+    pub fn next_ref(&mut self) -> Result<Option<CtlvRef<'_>>, ReadDecodeError> {
+        let (type_, header_len, len) = loop {
+            match CtlvRef::decode_with(&self.buf[self.start..self.end], &self.config) {
+                Ok((frame, _)) => {
+                    break (frame.type_,
+                           frame.encoding_length() - frame.value.len(),
+                           frame.encoding_length())
+                }
+                Err((DecodeError::UnexpectedEndOfInput, _)) => {
+                    if self.fill()? == 0 {
+                        let pending = self.end - self.start;
+                        return if pending == 0 {
+                            Ok(None)
+                        } else {
+                            Err(ReadDecodeError::UnexpectedEof { consumed: pending })
+                        };
+                    }
+                }
+                Err((e, _)) => return Err(ReadDecodeError::Decode(e)),
+            }
+        };
+
+        let value_start = self.start + header_len;
+        self.start += len;
+        self.frames += 1;
+        self.bytes += len;
+        Ok(Some(CtlvRef {
+                    type_,
+                    value: &self.buf[value_start..self.start],
+                }))
+    }
+
+    // Moves the undecoded bytes to the front of the buffer, then reads more bytes, growing the
+    // buffer if it is full. Returns how many bytes were read.
+    fn fill(&mut self) -> io::Result<usize> {
+        if self.start > 0 {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        if self.end == self.buf.len() {
+            let len = (2 * self.buf.len()).max(CHUNK_LEN);
+            self.buf.resize(len, 0);
+        }
+
+        loop {
+            match self.inner.read(&mut self.buf[self.end..]) {
+                Ok(read) => {
+                    self.end += read;
+                    return Ok(read);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Returns an iterator over the ctlvs read from `r`, through a
+/// [`CtlvReader`](struct.CtlvReader.html).
+///
+/// The iterator ends when the reader ends after a ctlv, or after yielding the first error.
+pub fn read_iter<R: Read>(r: R) -> ReadIter<R> {
+    ReadIter {
+        reader: CtlvReader::new(r),
+        done: false,
+    }
+}
+
+/// The iterator returned by [`read_iter`](fn.read_iter.html).
+#[derive(Debug)]
+pub struct ReadIter<R> {
+    reader: CtlvReader<R>,
+    done: bool,
+}
+
+impl<R: Read> ReadIter<R> {
+    /// Returns the underlying reader, together with the bytes that have been read from it but
+    /// not decoded yet, see [`CtlvReader::into_inner`](struct.CtlvReader.html#method.into_inner).
+    pub fn into_inner(self) -> (R, Vec<u8>) {
+        self.reader.into_inner()
+    }
+}
+
+impl<R: Read> Iterator for ReadIter<R> {
+    type Item = Result<Ctlv, ReadDecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let result = self.reader.next().transpose();
+        if !matches!(result, Some(Ok(_))) {
+            self.done = true;
+        }
+        result
+    }
+}
+
+impl<R: Read> FusedIterator for ReadIter<R> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A reader that returns a single byte per `read` call.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    fn fixture() -> Vec<Ctlv> {
+        (0..1000u64)
+            .map(|i| if i % 2 == 0 {
+                     Ctlv::new(i % 8, vec![i as u8])
+                 } else {
+                     Ctlv::new(128 + i, vec![i as u8; (i % 300) as usize])
+                 })
+            .chain(Some(Ctlv::new(300, vec![7; 3 * CHUNK_LEN])))
+            .collect()
+    }
+
+    #[test]
+    fn trickle() {
+        let frames = fixture();
+        let input: Vec<_> = frames.iter().map(Ctlv::encode_vec).collect();
+        let input = input.concat();
+
+        let mut reader = CtlvReader::new(Trickle(&input));
+        for (i, frame) in frames.iter().enumerate() {
+            if i % 3 == 0 {
+                assert_eq!(reader.next_ref().unwrap(), Some(frame.as_ctlv_ref()));
+            } else {
+                assert_eq!(reader.next().unwrap().as_ref(), Some(frame));
+            }
+            // Decoded bytes do not pile up in the buffer, it only grows for the large value.
+            assert_eq!(reader.start, reader.end);
+            if i + 1 < frames.len() {
+                assert_eq!(reader.buf.len(), CHUNK_LEN);
+            }
+        }
+        assert_eq!(reader.next().unwrap(), None);
+        assert_eq!(reader.next().unwrap(), None);
+        assert_eq!(reader.frames_read(), frames.len());
+        assert_eq!(reader.bytes_read(), input.len());
+
+        let mut reader = CtlvReader::new(&input[..]);
+        for frame in &frames {
+            assert_eq!(reader.next().unwrap().as_ref(), Some(frame));
+        }
+        assert_eq!(reader.next().unwrap(), None);
+    }
+
+    #[test]
+    fn unexpected_eof() {
+        let mut reader = CtlvReader::new(Trickle(&[0, 1, 128, 3, 1]));
+        assert_eq!(reader.next().unwrap(), Some(Ctlv::new(0, vec![1])));
+        assert!(matches!(reader.next(), Err(ReadDecodeError::UnexpectedEof { consumed: 3 })));
+        assert_eq!(reader.frames_read(), 1);
+        assert_eq!(reader.bytes_read(), 2);
+
+        assert_eq!(CtlvReader::new(io::empty()).next().unwrap(), None);
+    }
+
+    #[test]
+    fn limits() {
+        let config = DecodeConfig {
+            max_value_len: Some(10),
+            ..DecodeConfig::default()
+        };
+        let mut reader = CtlvReader::with_config(Trickle(&[128, 1, 42, 128, 11, 0]), config);
+        assert_eq!(reader.next().unwrap(), Some(Ctlv::new(128, vec![42])));
+        assert!(matches!(reader.next(),
+                         Err(ReadDecodeError::Decode(DecodeError::LengthTooLarge {
+                                                         declared: 11,
+                                                         max: 10,
+                                                     }))));
+
+        let mut reader = CtlvReader::new(&[248, 5, 42][..]);
+        assert!(matches!(reader.next(), Err(ReadDecodeError::Decode(DecodeError::Type(_)))));
+    }
+
+    #[test]
+    fn iter() {
+        let frames = fixture();
+        let input: Vec<_> = frames.iter().map(Ctlv::encode_vec).collect();
+        let input = input.concat();
+
+        let read: Vec<_> = read_iter(&input[..]).map(Result::unwrap).collect();
+        assert_eq!(read, frames);
+
+        let mut iter = read_iter(&[0, 1, 128, 3, 1][..]);
+        assert_eq!(iter.next().unwrap().unwrap(), Ctlv::new(0, vec![1]));
+        assert!(matches!(iter.next(), Some(Err(ReadDecodeError::UnexpectedEof { .. }))));
+        assert!(iter.next().is_none());
+        assert!(read_iter(io::empty()).next().is_none());
+    }
+
+    #[test]
+    fn into_inner() {
+        let frames = fixture();
+        let input: Vec<_> = frames.iter().map(Ctlv::encode_vec).collect();
+        let input = input.concat();
+
+        let mut iter = read_iter(io::Cursor::new(&input));
+        let read: Vec<_> = iter.by_ref().take(10).map(Result::unwrap).collect();
+        assert_eq!(read, frames[..10]);
+
+        let (cursor, buffered) = iter.into_inner();
+        assert!(!buffered.is_empty());
+        let mut rest = (&buffered[..]).chain(cursor);
+        for frame in &frames[10..] {
+            assert_eq!(&Ctlv::decode_read(&mut rest).unwrap(), frame);
+        }
+        assert!(matches!(Ctlv::decode_read(&mut rest), Err(ReadDecodeError::Eof)));
+
+        let mut reader = CtlvReader::new(&[0, 1, 128, 3][..]);
+        reader.next().unwrap();
+        assert_eq!(reader.into_inner(), (&[][..], vec![128, 3]));
+    }
+}
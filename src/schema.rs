@@ -0,0 +1,724 @@
+use std::collections::BTreeMap;
+use std::ops::{Bound, RangeBounds};
+use std::{error, fmt};
+
+use super::nested::DecoderOptions;
+use super::value::{Interp, Value, ValueError};
+use super::{CtlvIter, CtlvRef, DecodeError};
+
+// An owned range of lengths or counts.
+type Range = (Bound<usize>, Bound<usize>);
+
+fn to_range<R: RangeBounds<usize>>(range: R) -> Range {
+    (range.start_bound().cloned(), range.end_bound().cloned())
+}
+
+/// Whether a [`Schema`](struct.Schema.html) accepts ctlvs of types it has no rule for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum UnknownTypes {
+    /// Accept ctlvs of unknown types as leaves with arbitrary values, even inside containers
+    /// that do not list them as children. This allows documents to be extended without
+    /// breaking older validators.
+    Allow,
+    /// Reject ctlvs of unknown types.
+    Reject,
+}
+
+/// The rule for the children of a container, for use in a [`Schema`](struct.Schema.html).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct ContainerRule {
+    children: BTreeMap<u64, Range>,
+    len: Option<Range>,
+}
+
+impl ContainerRule {
+    /// Creates a rule for containers with no children and values of any length.
+    pub fn new() -> ContainerRule {
+        ContainerRule::default()
+    }
+
+    /// Allows children of the given type, requiring their number to be in the given range.
+    pub fn child<R: RangeBounds<usize>>(mut self, type_: u64, count: R) -> ContainerRule {
+        self.children.insert(type_, to_range(count));
+        self
+    }
+
+    /// Requires the length of the value of the container to be in the given range.
+    pub fn len<R: RangeBounds<usize>>(mut self, len: R) -> ContainerRule {
+        self.len = Some(to_range(len));
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Rule {
+    Leaf(Range),
+    Container(ContainerRule),
+}
+
+/// A description of the structure of nested ctlv documents.
+///
+/// Each type can be declared as a leaf or as a container. For leaves, the schema restricts the
+/// length of their value. For containers, it restricts which children they may contain and how
+/// many of each, and optionally the length of their value. Children of a type the container
+/// does not list are rejected, unless the schema has no rule for that type at all and allows
+/// unknown types.
+///
+/// ```
+/// # use ctlv::{ContainerRule, Schema, UnknownTypes};
+/// // Type 300 holds exactly one type-3 ctlv and any number of type-200 ctlvs,
+/// // a type-200 value is between 1 and 64 bytes long.
+/// let schema = Schema::new()
+///     .container(300, ContainerRule::new().child(3, 1..=1).child(200, ..))
+///     .leaf(3, ..)
+///     .leaf(200, 1..=64)
+///     .unknown_types(UnknownTypes::Reject);
+///
+/// assert!(schema.validate(&[249, 1, 44, 4, 3, 42, 200, 0]).is_err());
+/// assert!(schema.validate(&[249, 1, 44, 5, 3, 42, 200, 1, 7]).is_ok());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Schema {
+    rules: BTreeMap<u64, Rule>,
+    root: Option<ContainerRule>,
+    unknown: UnknownTypes,
+    options: DecoderOptions,
+    fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Field {
+    name: String,
+    path: Vec<u64>,
+    interp: Interp,
+    required: bool,
+}
+
+impl Default for Schema {
+    /// Creates a schema without any rules, which allows unknown types, and uses the default
+    /// `DecoderOptions`.
+    fn default() -> Schema {
+        Schema {
+            rules: BTreeMap::new(),
+            root: None,
+            unknown: UnknownTypes::Allow,
+            options: DecoderOptions::default(),
+            fields: Vec::new(),
+        }
+    }
+}
+
+impl Schema {
+    /// Creates a schema without any rules, which allows unknown types, and uses the default
+    /// `DecoderOptions`.
+    pub fn new() -> Schema {
+        Schema::default()
+    }
+
+    /// Declares the given type as a leaf, requiring the length of its value to be in the given
+    /// range.
+    pub fn leaf<R: RangeBounds<usize>>(mut self, type_: u64, len: R) -> Schema {
+        self.rules.insert(type_, Rule::Leaf(to_range(len)));
+        self
+    }
+
+    /// Declares the given type as a container, following the given rule.
+    pub fn container(mut self, type_: u64, rule: ContainerRule) -> Schema {
+        self.rules.insert(type_, Rule::Container(rule));
+        self
+    }
+
+    /// Restricts the top-level sequence of a document as if it was the value of a container.
+    /// By default, the top-level sequence may contain ctlvs of any (known) type.
+    pub fn root(mut self, rule: ContainerRule) -> Schema {
+        self.root = Some(rule);
+        self
+    }
+
+    /// Sets whether ctlvs of types without a rule are accepted.
+    pub fn unknown_types(mut self, unknown: UnknownTypes) -> Schema {
+        self.unknown = unknown;
+        self
+    }
+
+    /// Sets the limits for descending into containers.
+    pub fn options(mut self, options: DecoderOptions) -> Schema {
+        self.options = options;
+        self
+    }
+
+    /// Names the ctlvs at the given path of types, for extracting them with
+    /// [`decode`](#method.decode). Their values must be of the width that `interp` requires
+    /// for their type (see [`Value`](enum.Value.html)), but they may be absent.
+    pub fn field(self, name: &str, path: &[u64], interp: Interp) -> Schema {
+        self.add_field(name, path, interp, false)
+    }
+
+    /// Like [`field`](#method.field), but [`decode`](#method.decode) fails if the document
+    /// contains no ctlv at the given path.
+    pub fn required_field(self, name: &str, path: &[u64], interp: Interp) -> Schema {
+        self.add_field(name, path, interp, true)
+    }
+
+    fn add_field(mut self, name: &str, path: &[u64], interp: Interp, required: bool) -> Schema {
+        self.fields.push(Field {
+                             name: name.to_string(),
+                             path: path.to_vec(),
+                             interp,
+                             required,
+                         });
+        self
+    }
+
+    /// Validates a sequence of nested ctlvs, and extracts the values of all named fields.
+    ///
+    /// Violations of the schema are reported together with the name of the field at the path
+    /// of the violation, if there is one.
+    pub fn decode<'a>(&self, input: &'a [u8]) -> Result<Document<'a>, DocumentError> {
+        self.validate(input).map_err(|violation| self.violation(violation))?;
+
+        let mut fields = BTreeMap::new();
+        for field in self.fields.iter() {
+            let mut values = Vec::new();
+            gather(input, 0, &field.path, &mut values).map_err(|(offset, e)| {
+                self.violation(SchemaViolation {
+                                   path: field.path.clone(),
+                                   offset,
+                                   kind: ViolationKind::Decode(e),
+                               })
+            })?;
+
+            if field.required && values.is_empty() {
+                return Err(DocumentError::MissingField {
+                               name: field.name.clone(),
+                               path: field.path.clone(),
+                           });
+            }
+
+            match field.interp {
+                Interp::Uint | Interp::Int | Interp::Float => {
+                    for (offset, frame) in values.iter() {
+                        if let Err(ValueError::Width { .. }) =
+                            Value::from_ctlv(*frame, |_| field.interp) {
+                            return Err(DocumentError::FieldType {
+                                           name: field.name.clone(),
+                                           path: field.path.clone(),
+                                           offset: *offset,
+                                       });
+                        }
+                    }
+                }
+                Interp::Bytes | Interp::Sequence => {}
+            }
+
+            fields.insert(field.name.clone(),
+                          (field.interp, values.iter().map(|(_, frame)| frame.value).collect()));
+        }
+
+        Ok(Document { fields })
+    }
+
+    fn violation(&self, violation: SchemaViolation) -> DocumentError {
+        DocumentError::Violation {
+            field: self.fields
+                .iter()
+                .find(|field| field.path == violation.path)
+                .map(|field| field.name.clone()),
+            violation,
+        }
+    }
+
+    /// Checks whether a sequence of nested ctlvs conforms to this schema, returning the first
+    /// violation otherwise.
+    pub fn validate(&self, input: &[u8]) -> Result<(), SchemaViolation> {
+        let mut path = Vec::new();
+        self.validate_sequence(input, 0, 0, self.root.as_ref(), &mut path)
+    }
+
+    // Validate the given (top-level or container) sequence, `path` leads to the ctlv whose
+    // value it is, `base` is the offset of the sequence.
+    fn validate_sequence(&self,
+                         input: &[u8],
+                         base: usize,
+                         container_offset: usize,
+                         rule: Option<&ContainerRule>,
+                         path: &mut Vec<u64>)
+                         -> Result<(), SchemaViolation> {
+        let mut counts = BTreeMap::new();
+        let mut iter = CtlvIter::new(input);
+
+        loop {
+            let offset = base + iter.offset();
+            let frame = match iter.next() {
+                None => break,
+                Some(Err((e, inner))) => {
+                    return Err(SchemaViolation::new(path, base + inner, ViolationKind::Decode(e)))
+                }
+                Some(Ok(frame)) => frame,
+            };
+            path.push(frame.type_);
+
+            match self.rules.get(&frame.type_) {
+                None => {
+                    if self.unknown == UnknownTypes::Reject {
+                        return Err(SchemaViolation::new(path, offset, ViolationKind::UnknownType));
+                    }
+                }
+                Some(type_rule) => {
+                    if let Some(rule) = rule {
+                        if !rule.children.contains_key(&frame.type_) {
+                            return Err(SchemaViolation::new(path,
+                                                            offset,
+                                                            ViolationKind::UnexpectedChild));
+                        }
+                    }
+                    *counts.entry(frame.type_).or_insert(0) += 1;
+
+                    let len = match type_rule {
+                        Rule::Leaf(len) => Some(len),
+                        Rule::Container(rule) => rule.len.as_ref(),
+                    };
+                    if let Some(len) = len {
+                        if !len.contains(&frame.value.len()) {
+                            return Err(SchemaViolation::new(path,
+                                                            offset,
+                                                            ViolationKind::Length(frame.value.len())));
+                        }
+                    }
+
+                    if let Rule::Container(rule) = type_rule {
+                        if path.len() > self.options.max_depth {
+                            return Err(SchemaViolation::new(path,
+                                                            offset,
+                                                            ViolationKind::DepthExceeded));
+                        }
+                        let value_offset = offset + frame.encoding_length() - frame.value.len();
+                        self.validate_sequence(frame.value, value_offset, offset, Some(rule), path)?;
+                    }
+                }
+            }
+
+            path.pop();
+        }
+
+        if let Some(rule) = rule {
+            for (type_, count) in rule.children.iter() {
+                let actual = counts.get(type_).cloned().unwrap_or(0);
+                if !count.contains(&actual) {
+                    return Err(SchemaViolation::new(path,
+                                                    container_offset,
+                                                    ViolationKind::Cardinality {
+                                                        type_: *type_,
+                                                        count: actual,
+                                                    }));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Collect all ctlvs at the given path of types, together with their offsets.
+fn gather<'a>(input: &'a [u8],
+              base: usize,
+              path: &[u64],
+              out: &mut Vec<(usize, CtlvRef<'a>)>)
+              -> Result<(), (usize, DecodeError)> {
+    let mut iter = CtlvIter::new(input);
+    loop {
+        let offset = base + iter.offset();
+        match iter.next() {
+            None => return Ok(()),
+            Some(Err((e, inner))) => return Err((base + inner, e)),
+            Some(Ok(frame)) => {
+                if path.first() == Some(&frame.type_) {
+                    if path.len() == 1 {
+                        out.push((offset, frame));
+                    } else {
+                        let value_offset = offset + frame.encoding_length() - frame.value.len();
+                        gather(frame.value, value_offset, &path[1..], out)?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The named fields of a document, extracted by [`Schema::decode`](struct.Schema.html#method.decode).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Document<'a> {
+    fields: BTreeMap<String, (Interp, Vec<&'a [u8]>)>,
+}
+
+impl<'a> Document<'a> {
+    /// Returns the value of the first ctlv of the given field.
+    pub fn get(&self, name: &str) -> Option<&'a [u8]> {
+        self.get_all(name).first().cloned()
+    }
+
+    /// Returns the values of all ctlvs of the given field, in document order.
+    pub fn get_all(&self, name: &str) -> &[&'a [u8]] {
+        match self.fields.get(name) {
+            Some((_, values)) => values,
+            None => &[],
+        }
+    }
+
+    /// Returns the first value of the given field, if it was declared as `Interp::Uint`.
+    pub fn get_uint(&self, name: &str) -> Option<u64> {
+        self.number(name, Interp::Uint)
+    }
+
+    /// Returns the first value of the given field, if it was declared as `Interp::Int`.
+    pub fn get_int(&self, name: &str) -> Option<i64> {
+        self.number(name, Interp::Int).map(|n| n as i64)
+    }
+
+    /// Returns the first value of the given field, if it was declared as `Interp::Float`.
+    pub fn get_float(&self, name: &str) -> Option<f64> {
+        self.number(name, Interp::Float).map(f64::from_bits)
+    }
+
+    // The first value of the field as a (sign-extended) number, if it has the interpretation.
+    fn number(&self, name: &str, interp: Interp) -> Option<u64> {
+        let (declared, values) = self.fields.get(name)?;
+        if *declared != interp {
+            return None;
+        }
+        let value = values.first()?;
+
+        let mut buf = [0; 8];
+        buf[8 - value.len()..].copy_from_slice(value);
+        let n = u64::from_be_bytes(buf);
+        if interp == Interp::Int {
+            let shift = 64 - 8 * value.len() as u32;
+            Some((((n << shift) as i64) >> shift) as u64)
+        } else {
+            Some(n)
+        }
+    }
+}
+
+/// Everything that can go wrong when decoding a [`Document`](struct.Document.html).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DocumentError {
+    /// The input violates the schema.
+    Violation {
+        /// The name of the field at the path of the violation, if any.
+        field: Option<String>,
+        /// The violation.
+        violation: SchemaViolation,
+    },
+    /// A required field is absent.
+    MissingField {
+        /// The name of the field.
+        name: String,
+        /// The path of types of the field.
+        path: Vec<u64>,
+    },
+    /// The ctlv at the given offset has a value of the wrong width for the interpretation of
+    /// its field.
+    FieldType {
+        /// The name of the field.
+        name: String,
+        /// The path of types of the field.
+        path: Vec<u64>,
+        /// The offset of the ctlv.
+        offset: usize,
+    },
+}
+
+impl fmt::Display for DocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        match self {
+            DocumentError::Violation { field: Some(name), violation } => {
+                write!(f, "{} (field {})", violation, name)
+            }
+            DocumentError::Violation { field: None, violation } => write!(f, "{}", violation),
+            DocumentError::MissingField { name, path } => {
+                write!(f, "Missing field {} at /{}", name, format_path(path))
+            }
+            DocumentError::FieldType { name, path, offset } => {
+                write!(f,
+                       "Field {} at /{} (offset {}) has a value of the wrong width",
+                       name,
+                       format_path(path),
+                       offset)
+            }
+        }
+    }
+}
+
+impl error::Error for DocumentError {}
+
+fn format_path(path: &[u64]) -> String {
+    path.iter().map(|type_| type_.to_string()).collect::<Vec<_>>().join("/")
+}
+
+/// What is wrong about a document that violates a [`Schema`](struct.Schema.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ViolationKind {
+    /// A sequence could not be decoded.
+    Decode(DecodeError),
+    /// A container is nested deeper than the maximum depth.
+    DepthExceeded,
+    /// The schema has no rule for the type of a ctlv, and rejects unknown types.
+    UnknownType,
+    /// A ctlv is the child of a container (or of the top-level sequence) that does not allow
+    /// children of its type.
+    UnexpectedChild,
+    /// The value of a ctlv has a length (the wrapped value) outside the allowed range.
+    Length(usize),
+    /// A container (or the top-level sequence) contains a disallowed number of children of
+    /// some type.
+    Cardinality {
+        /// The type of the children.
+        type_: u64,
+        /// How many children of that type the container contains.
+        count: usize,
+    },
+}
+
+/// A violation of a [`Schema`](struct.Schema.html).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SchemaViolation {
+    /// The types of the ctlvs leading from the top-level sequence to the ctlv at fault. For
+    /// decoding errors and cardinality violations, this is the ctlv whose value is the
+    /// offending sequence, or empty for the top-level sequence.
+    pub path: Vec<u64>,
+    /// The offset of the ctlv at fault (or of the undecodable data), relative to the start
+    /// of the input.
+    pub offset: usize,
+    /// What is wrong.
+    pub kind: ViolationKind,
+}
+
+impl SchemaViolation {
+    fn new(path: &[u64], offset: usize, kind: ViolationKind) -> SchemaViolation {
+        SchemaViolation {
+            path: path.to_vec(),
+            offset,
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        write!(f, "Schema violation at /{} (offset {}): ", format_path(&self.path), self.offset)?;
+
+        match self.kind {
+            ViolationKind::Decode(e) => write!(f, "{}", e),
+            ViolationKind::DepthExceeded => write!(f, "container is nested too deeply"),
+            ViolationKind::UnknownType => write!(f, "unknown type"),
+            ViolationKind::UnexpectedChild => write!(f, "type is not allowed here"),
+            ViolationKind::Length(len) => write!(f, "value length {} is out of range", len),
+            ViolationKind::Cardinality { type_, count } => {
+                write!(f, "{} children of type {} are not allowed", count, type_)
+            }
+        }
+    }
+}
+
+impl error::Error for SchemaViolation {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_util::ctlv;
+
+    fn schema() -> Schema {
+        Schema::new()
+            .container(300, ContainerRule::new().child(3, 1..=1).child(200, ..))
+            .leaf(3, ..)
+            .leaf(200, 1..=64)
+            .unknown_types(UnknownTypes::Reject)
+    }
+
+    #[test]
+    fn valid() {
+        let message = [ctlv(200, vec![1]), ctlv(3, vec![0]), ctlv(200, vec![2; 64])].concat();
+        let input = [ctlv(300, message), ctlv(300, ctlv(3, vec![0]))].concat();
+        assert_eq!(schema().validate(&input), Ok(()));
+        assert_eq!(schema().validate(&[]), Ok(()));
+    }
+
+    #[test]
+    fn cardinality() {
+        let input = [ctlv(3, vec![0]), ctlv(300, [ctlv(3, vec![0]), ctlv(3, vec![1])].concat())]
+            .concat();
+        assert_eq!(schema().validate(&input),
+                   Err(SchemaViolation {
+                           path: vec![300],
+                           offset: 2,
+                           kind: ViolationKind::Cardinality { type_: 3, count: 2 },
+                       }));
+
+        assert_eq!(schema().validate(&ctlv(300, ctlv(200, vec![1]))),
+                   Err(SchemaViolation {
+                           path: vec![300],
+                           offset: 0,
+                           kind: ViolationKind::Cardinality { type_: 3, count: 0 },
+                       }));
+
+        let rooted = schema().root(ContainerRule::new().child(300, 1..));
+        assert_eq!(rooted.validate(&[]),
+                   Err(SchemaViolation {
+                           path: vec![],
+                           offset: 0,
+                           kind: ViolationKind::Cardinality { type_: 300, count: 0 },
+                       }));
+        assert_eq!(rooted.validate(&ctlv(3, vec![0])).unwrap_err().kind,
+                   ViolationKind::UnexpectedChild);
+    }
+
+    #[test]
+    fn lengths() {
+        let input = ctlv(300, [ctlv(3, vec![0]), ctlv(200, vec![])].concat());
+        assert_eq!(schema().validate(&input),
+                   Err(SchemaViolation {
+                           path: vec![300, 200],
+                           offset: 6,
+                           kind: ViolationKind::Length(0),
+                       }));
+
+        let input = ctlv(300, [ctlv(3, vec![0]), ctlv(200, vec![0; 65])].concat());
+        assert_eq!(schema().validate(&input).unwrap_err().kind, ViolationKind::Length(65));
+
+        let bounded = schema().container(300, ContainerRule::new().child(3, ..).len(..2));
+        assert_eq!(bounded.validate(&ctlv(300, ctlv(3, vec![0]))).unwrap_err().kind,
+                   ViolationKind::Length(2));
+    }
+
+    #[test]
+    fn unknown_types() {
+        let input = ctlv(300, [ctlv(3, vec![0]), ctlv(129, vec![1, 2])].concat());
+        let violation = schema().validate(&input).unwrap_err();
+        assert_eq!(violation,
+                   SchemaViolation {
+                       path: vec![300, 129],
+                       offset: 6,
+                       kind: ViolationKind::UnknownType,
+                   });
+        assert_eq!(violation.to_string(), "Schema violation at /300/129 (offset 6): unknown type");
+
+        let lenient = schema().unknown_types(UnknownTypes::Allow);
+        assert_eq!(lenient.validate(&input), Ok(()));
+        assert_eq!(lenient.validate(&ctlv(129, vec![])), Ok(()));
+
+        // Known types must still be allowed by their container.
+        let input = ctlv(300, [ctlv(3, vec![0]), ctlv(300, ctlv(3, vec![0]))].concat());
+        assert_eq!(lenient.validate(&input),
+                   Err(SchemaViolation {
+                           path: vec![300, 300],
+                           offset: 6,
+                           kind: ViolationKind::UnexpectedChild,
+                       }));
+    }
+
+    #[test]
+    fn invalid_input() {
+        let input = ctlv(300, [ctlv(3, vec![0]), vec![128, 5]].concat());
+        assert_eq!(schema().validate(&input),
+                   Err(SchemaViolation {
+                           path: vec![300],
+                           offset: 6,
+                           kind: ViolationKind::Decode(DecodeError::UnexpectedEndOfInput),
+                       }));
+
+        let nested = Schema::new()
+            .container(300, ContainerRule::new().child(300, ..))
+            .options(DecoderOptions { max_depth: 1 });
+        assert_eq!(nested.validate(&ctlv(300, vec![])), Ok(()));
+        assert_eq!(nested.validate(&ctlv(300, ctlv(300, vec![]))).unwrap_err().kind,
+                   ViolationKind::DepthExceeded);
+    }
+
+    fn message_schema() -> Schema {
+        Schema::new()
+            .container(300,
+                       ContainerRule::new()
+                           .child(8, 1..=1)
+                           .child(129, 0..=1)
+                           .child(1, ..)
+                           .child(24, ..))
+            .leaf(8, ..)
+            .leaf(129, ..64)
+            .leaf(1, ..)
+            .leaf(24, ..)
+            .leaf(200, 64..=64)
+            .root(ContainerRule::new().child(300, 1..=1).child(200, ..))
+            .required_field("signature", &[200], Interp::Bytes)
+            .required_field("timestamp", &[300, 8], Interp::Uint)
+            .field("author", &[300, 129], Interp::Bytes)
+            .field("offsets", &[300, 1], Interp::Int)
+            .field("score", &[300, 24], Interp::Float)
+    }
+
+    #[test]
+    fn decode_document() {
+        let message = [ctlv(8, vec![1, 2]),
+                       ctlv(1, vec![255]),
+                       ctlv(129, b"alice".to_vec()),
+                       ctlv(1, vec![3])]
+            .concat();
+        let input = [ctlv(300, message), ctlv(200, vec![1; 64]), ctlv(200, vec![2; 64])].concat();
+
+        let doc = message_schema().decode(&input).unwrap();
+        assert_eq!(doc.get("signature"), Some(&[1; 64][..]));
+        assert_eq!(doc.get_all("signature"), [&[1; 64][..], &[2; 64][..]]);
+        assert_eq!(doc.get_uint("timestamp"), Some(258));
+        assert_eq!(doc.get("timestamp"), Some(&[1, 2][..]));
+        assert_eq!(doc.get("author"), Some(&b"alice"[..]));
+        assert_eq!(doc.get_int("offsets"), Some(-1));
+        assert_eq!(doc.get_all("offsets"), [&[255][..], &[3][..]]);
+        assert_eq!(doc.get("score"), None);
+        assert_eq!(doc.get_float("score"), None);
+        assert!(doc.get_all("score").is_empty());
+
+        // Accessors for other interpretations and unknown names yield nothing.
+        assert_eq!(doc.get_uint("offsets"), None);
+        assert_eq!(doc.get_int("timestamp"), None);
+        assert_eq!(doc.get_uint("author"), None);
+        assert_eq!(doc.get("nonexistent"), None);
+    }
+
+    #[test]
+    fn decode_errors() {
+        let schema = message_schema();
+
+        let error = schema.decode(&ctlv(300, ctlv(8, vec![0, 1]))).unwrap_err();
+        assert_eq!(error,
+                   DocumentError::MissingField {
+                       name: "signature".to_string(),
+                       path: vec![200],
+                   });
+        assert_eq!(error.to_string(), "Missing field signature at /200");
+
+        let error = message_schema().decode(&[ctlv(300, ctlv(8, vec![0, 1])), ctlv(200, vec![0])]
+                                                  .concat())
+            .unwrap_err();
+        assert_eq!(error.to_string(),
+                   "Schema violation at /200 (offset 7): value length 1 is out of range \
+                    (field signature)");
+    }
+
+    #[test]
+    fn field_type() {
+        let schema = Schema::new().field("count", &[300, 129], Interp::Uint);
+        let input = ctlv(300, ctlv(129, vec![0, 1]));
+        let error = schema.decode(&input).unwrap_err();
+        assert_eq!(error,
+                   DocumentError::FieldType {
+                       name: "count".to_string(),
+                       path: vec![300, 129],
+                       offset: 4,
+                   });
+        assert_eq!(error.to_string(),
+                   "Field count at /300/129 (offset 4) has a value of the wrong width");
+
+        let input = ctlv(300, ctlv(129, vec![0, 0, 0, 0, 0, 0, 0, 7]));
+        assert_eq!(schema.decode(&input).unwrap().get_uint("count"), Some(7));
+    }
+}
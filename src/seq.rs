@@ -0,0 +1,165 @@
+use super::{check_implied_length, CtlvIter, CtlvRef, ImpliedLengthError};
+
+/// An owned encoded sequence of ctlvs that is kept canonical: sorted by the encodings of its
+/// ctlvs (see [`CtlvRef::cmp_encoded`](struct.CtlvRef.html#method.cmp_encoded)), without
+/// duplicates.
+///
+/// Besides the encoding, the sequence stores the offset of every ctlv, so that lookups and
+/// insertions can use binary search.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct CtlvSeq {
+    bytes: Vec<u8>,
+    offsets: Vec<usize>,
+}
+
+impl CtlvSeq {
+    /// Creates an empty sequence.
+    pub fn new() -> CtlvSeq {
+        CtlvSeq::default()
+    }
+
+    /// Returns how many ctlvs the sequence contains.
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Returns whether the sequence contains no ctlvs.
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Returns the encoding of the sequence.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Consumes the sequence, returning its encoding.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Returns the ctlv at the given index.
+    pub fn get(&self, i: usize) -> Option<CtlvRef<'_>> {
+        let offset = *self.offsets.get(i)?;
+        Some(CtlvRef::decode(&self.bytes[offset..]).unwrap().0)
+    }
+
+    /// Returns an iterator over the ctlvs of the sequence, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = CtlvRef<'_>> {
+        CtlvIter::new(&self.bytes).map(Result::unwrap)
+    }
+
+    /// Returns whether the sequence contains a ctlv with the same encoding as `frame`.
+    pub fn contains(&self, frame: CtlvRef) -> bool {
+        self.search(frame).is_ok()
+    }
+
+    /// Inserts a ctlv at the position given by its encoding, keeping the sequence canonical.
+    ///
+    /// Returns `Ok(false)` and leaves the sequence unchanged if it already contains a ctlv with
+    /// the same encoding. Fails and leaves the sequence unchanged if the type of `frame` implies
+    /// a length other than that of its value, since its encoding would not decode to it.
+    pub fn insert_sorted(&mut self, frame: CtlvRef) -> Result<bool, ImpliedLengthError> {
+        check_implied_length(frame.type_, frame.value.len())?;
+
+        let i = match self.search(frame) {
+            Ok(_) => return Ok(false),
+            Err(i) => i,
+        };
+        let offset = self.offsets.get(i).cloned().unwrap_or(self.bytes.len());
+        let encoding = frame.encode_vec();
+
+        self.bytes.splice(offset..offset, encoding.iter().cloned());
+        for later in &mut self.offsets[i..] {
+            *later += encoding.len();
+        }
+        self.offsets.insert(i, offset);
+        Ok(true)
+    }
+
+    // Binary search for the index of the ctlv, as in `slice::binary_search`.
+    fn search(&self, frame: CtlvRef) -> Result<usize, usize> {
+        self.offsets.binary_search_by(|offset| {
+            let (existing, _) = CtlvRef::decode(&self.bytes[*offset..]).unwrap();
+            existing.cmp_encoded(&frame)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    use super::super::Ctlv;
+
+    fn frames() -> Vec<Ctlv> {
+        (0..200u64)
+            .map(|i| if i % 4 == 0 {
+                     Ctlv::new(i % 8, vec![(i / 8) as u8])
+                 } else {
+                     Ctlv::new(128 + i % 5, vec![i as u8; (i % 7) as usize])
+                 })
+            .collect()
+    }
+
+    #[test]
+    fn insert_unordered() {
+        let frames = frames();
+        let mut seq = CtlvSeq::new();
+        let mut inserted = BTreeSet::new();
+        // Insert in a scrambled order, with every ctlv inserted twice.
+        for k in 0..2 * frames.len() {
+            let frame = &frames[(k * 37) % frames.len()];
+            assert_eq!(seq.insert_sorted(frame.as_ctlv_ref()),
+                       Ok(inserted.insert(frame.encode_vec())));
+        }
+
+        let mut encodings: Vec<_> = frames.iter().map(Ctlv::encode_vec).collect();
+        encodings.sort_unstable();
+        encodings.dedup();
+        assert_eq!(seq.as_bytes(), &encodings.concat()[..]);
+        assert_eq!(seq.len(), encodings.len());
+
+        for (i, frame) in seq.iter().enumerate() {
+            assert_eq!(seq.get(i), Some(frame));
+            assert!(seq.contains(frame));
+        }
+        assert_eq!(seq.get(seq.len()), None);
+    }
+
+    #[test]
+    fn contains() {
+        let mut seq = CtlvSeq::new();
+        assert!(!seq.contains(CtlvRef { type_: 0, value: &[1] }));
+        assert_eq!(seq.insert_sorted(CtlvRef { type_: 128, value: &[1, 1] }), Ok(true));
+        assert_eq!(seq.insert_sorted(CtlvRef { type_: 128, value: &[2] }), Ok(true));
+        assert_eq!(seq.insert_sorted(CtlvRef { type_: 128, value: &[2] }), Ok(false));
+        assert_eq!(seq.as_bytes(), [128, 1, 2, 128, 2, 1, 1]);
+
+        assert!(seq.contains(CtlvRef { type_: 128, value: &[2] }));
+        assert!(!seq.contains(CtlvRef { type_: 128, value: &[1] }));
+        assert_eq!(seq.clone().into_bytes(), seq.as_bytes());
+    }
+
+    #[test]
+    fn implied_length_mismatch() {
+        let mut seq = CtlvSeq::new();
+        assert_eq!(seq.insert_sorted(CtlvRef { type_: 0, value: &[1] }), Ok(true));
+        assert_eq!(seq.insert_sorted(CtlvRef { type_: 8, value: &[1, 2, 3] }),
+                   Err(ImpliedLengthError {
+                           type_: 8,
+                           implied: 2,
+                           actual: 3,
+                       }));
+        assert_eq!(seq.insert_sorted(CtlvRef { type_: 0, value: &[] }),
+                   Err(ImpliedLengthError {
+                           type_: 0,
+                           implied: 1,
+                           actual: 0,
+                       }));
+        assert_eq!(seq.as_bytes(), [0, 1]);
+        assert_eq!(seq.iter().count(), 1);
+        assert!(!seq.contains(CtlvRef { type_: 8, value: &[1, 2, 3] }));
+    }
+}
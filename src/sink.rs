@@ -0,0 +1,187 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_sink::Sink;
+use tokio::io::AsyncWrite;
+
+use super::write_buffer::WriteBuffer;
+use super::Ctlv;
+
+// The default number of buffered bytes above which `poll_ready` writes them out first.
+const DEFAULT_THRESHOLD: usize = 8 * 1024;
+
+/// A `Sink` that encodes ctlvs into a tokio `AsyncWrite`.
+///
+/// Sending a ctlv only encodes it into an internal buffer, the buffer is written out when the
+/// sink is flushed or closed. To apply backpressure, the sink is only ready to accept another
+/// ctlv once the buffer holds fewer bytes than a threshold, writing out the buffer as needed.
+///
+/// Requires the `tokio` feature.
+#[derive(Debug)]
+pub struct CtlvSink<W> {
+    inner: W,
+    buf: WriteBuffer,
+    threshold: usize,
+}
+
+impl<W: AsyncWrite + Unpin> CtlvSink<W> {
+    /// Creates a `CtlvSink` with a threshold of 8 KiB.
+    pub fn new(inner: W) -> CtlvSink<W> {
+        CtlvSink::with_threshold(inner, DEFAULT_THRESHOLD)
+    }
+
+    /// Creates a `CtlvSink` that writes out its buffer before accepting another ctlv once the
+    /// buffer holds at least `threshold` bytes.
+    pub fn with_threshold(inner: W, threshold: usize) -> CtlvSink<W> {
+        CtlvSink {
+            inner,
+            buf: WriteBuffer::new(),
+            threshold,
+        }
+    }
+
+    /// Returns how many encoded bytes have not been written out yet.
+    pub fn buffered(&self) -> usize {
+        self.buf.buffered()
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns the underlying writer, discarding any bytes that have not been written out.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    // Writes out buffered bytes until fewer than `limit` remain.
+    fn poll_write_buf(&mut self, cx: &mut Context<'_>, limit: usize) -> Poll<io::Result<()>> {
+        let inner = &mut self.inner;
+        self.buf.poll_write_out(limit, |pending| Pin::new(&mut *inner).poll_write(cx, pending))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> Sink<Ctlv> for CtlvSink<W> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let threshold = this.threshold;
+        this.poll_write_buf(cx, threshold)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Ctlv) -> io::Result<()> {
+        self.get_mut().buf.push(item.as_ctlv_ref());
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_write_buf(cx, 1) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_flush(cx),
+            other => other,
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_write_buf(cx, 1) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_shutdown(cx),
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::SinkExt;
+
+    fn fixture() -> Vec<Ctlv> {
+        (0..200u64)
+            .map(|i| Ctlv::new(128 + i, vec![i as u8; i as usize]))
+            .collect()
+    }
+
+    // Accepts at most three bytes per write, and is pending every other time.
+    struct Trickle {
+        out: Vec<u8>,
+        pending: bool,
+        max_buffered: usize,
+    }
+
+    impl AsyncWrite for Trickle {
+        fn poll_write(mut self: Pin<&mut Self>,
+                      cx: &mut Context<'_>,
+                      buf: &[u8])
+                      -> Poll<io::Result<usize>> {
+            self.pending = !self.pending;
+            if self.pending {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let len = buf.len().min(3);
+            self.out.extend_from_slice(&buf[..len]);
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn send() {
+        let frames = fixture();
+        let expected: Vec<_> = frames.iter().map(Ctlv::encode_vec).collect();
+
+        let mut sink = CtlvSink::new(Vec::new());
+        for frame in &frames {
+            sink.send(frame.clone()).await.unwrap();
+            assert_eq!(sink.buffered(), 0);
+        }
+        assert_eq!(sink.into_inner(), expected.concat());
+
+        let mut sink = CtlvSink::new(Vec::new());
+        sink.feed(frames[5].clone()).await.unwrap();
+        assert_eq!(sink.buffered(), 7);
+        assert!(sink.get_ref().is_empty());
+        sink.close().await.unwrap();
+        assert_eq!(sink.get_ref(), &expected[5]);
+    }
+
+    #[tokio::test]
+    async fn backpressure() {
+        let frames = fixture();
+        let expected: Vec<_> = frames.iter().map(Ctlv::encode_vec).collect();
+
+        let trickle = Trickle {
+            out: Vec::new(),
+            pending: false,
+            max_buffered: 0,
+        };
+        let mut sink = CtlvSink::with_threshold(trickle, 100);
+        let mut max_capacity = 0;
+        for frame in &frames {
+            sink.feed(frame.clone()).await.unwrap();
+            max_capacity = max_capacity.max(sink.buf.capacity());
+            let buffered = sink.buffered();
+            let trickle = &mut sink.inner;
+            trickle.max_buffered = trickle.max_buffered.max(buffered);
+        }
+        // At most one ctlv is added to a buffer holding less than the threshold, and the bytes
+        // that have been written out do not stay in the buffer.
+        assert!(sink.get_ref().max_buffered < 100 + 203);
+        assert!(max_capacity < 2 * (100 + 203));
+        assert!(!sink.get_ref().out.is_empty());
+        sink.flush().await.unwrap();
+        assert_eq!(sink.buffered(), 0);
+        assert_eq!(sink.into_inner().out, expected.concat());
+    }
+}
@@ -0,0 +1,116 @@
+use std::iter::FusedIterator;
+
+use super::{CtlvIter, CtlvRef, DecodeError};
+
+/// An encoded sequence of ctlvs that is known to be valid.
+///
+/// The sequence is validated (and its ctlvs counted) once on construction, so iterating over
+/// it yields `CtlvRef`s rather than `Result`s, and the iterator knows its exact length.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CtlvSlice<'a> {
+    bytes: &'a [u8],
+    len: usize,
+}
+
+impl<'a> CtlvSlice<'a> {
+    /// Validates the input. If it is not a valid sequence, returns the error and the offset at
+    /// which it occurred.
+    pub fn new(input: &'a [u8]) -> Result<CtlvSlice<'a>, (DecodeError, usize)> {
+        let mut len = 0;
+        for result in CtlvIter::new(input) {
+            result?;
+            len += 1;
+        }
+        Ok(CtlvSlice { bytes: input, len })
+    }
+
+    /// Returns how many ctlvs the sequence contains.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the sequence contains no ctlvs.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the encoding of the sequence.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Returns an iterator over the ctlvs of the sequence.
+    pub fn iter(&self) -> CtlvSliceIter<'a> {
+        CtlvSliceIter {
+            inner: CtlvIter::new(self.bytes),
+            remaining: self.len,
+        }
+    }
+}
+
+impl<'a> IntoIterator for CtlvSlice<'a> {
+    type Item = CtlvRef<'a>;
+    type IntoIter = CtlvSliceIter<'a>;
+
+    fn into_iter(self) -> CtlvSliceIter<'a> {
+        self.iter()
+    }
+}
+
+/// An iterator over the ctlvs of a [`CtlvSlice`](struct.CtlvSlice.html).
+#[derive(Debug, Clone)]
+pub struct CtlvSliceIter<'a> {
+    inner: CtlvIter<'a>,
+    remaining: usize,
+}
+
+impl<'a> Iterator for CtlvSliceIter<'a> {
+    type Item = CtlvRef<'a>;
+
+    fn next(&mut self) -> Option<CtlvRef<'a>> {
+        let frame = self.inner.next()?.expect("a CtlvSlice is always valid");
+        self.remaining -= 1;
+        Some(frame)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for CtlvSliceIter<'a> {}
+
+impl<'a> FusedIterator for CtlvSliceIter<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_size() {
+        let input = [0, 1, 128, 2, 3, 4, 249, 1, 44, 0, 8, 5, 6];
+        let slice = CtlvSlice::new(&input).unwrap();
+        assert_eq!(slice.len(), 4);
+        assert_eq!(slice.as_bytes(), input);
+
+        let mut iter = slice.iter();
+        for remaining in (0..=4).rev() {
+            assert_eq!(iter.len(), remaining);
+            assert_eq!(iter.size_hint(), (remaining, Some(remaining)));
+            assert_eq!(iter.next().is_some(), remaining > 0);
+        }
+        assert_eq!(iter.next(), None);
+
+        let frames: Vec<_> = slice.into_iter().collect();
+        assert_eq!(frames.capacity(), 4);
+        assert_eq!(frames[2], CtlvRef { type_: 300, value: &[] });
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(CtlvSlice::new(&[0, 1, 128]), Err((DecodeError::UnexpectedEndOfInput, 2)));
+        let empty = CtlvSlice::new(&[]).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.iter().len(), 0);
+    }
+}
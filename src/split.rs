@@ -0,0 +1,131 @@
+use std::iter::FusedIterator;
+
+use super::{CtlvIter, DecodeError};
+
+/// Split an encoded sequence of ctlvs at the last ctlv boundary that is not past `target`.
+///
+/// Both halves are valid sequences. The whole input is checked (the values are skipped, not
+/// inspected); if it is invalid, returns the error and the offset at which it occurred.
+#[allow(clippy::type_complexity)]
+pub fn split_at_frame(input: &[u8],
+                      target: usize)
+                      -> Result<(&[u8], &[u8]), (DecodeError, usize)> {
+    let mut iter = CtlvIter::new(input);
+    let mut split = 0;
+    while let Some(result) = iter.next() {
+        result?;
+        if iter.offset() <= target {
+            split = iter.offset();
+        }
+    }
+    Ok(input.split_at(split))
+}
+
+/// Returns an iterator that splits an encoded sequence of ctlvs into chunks of whole ctlvs, each
+/// at most `chunk_len` bytes long.
+///
+/// A ctlv whose encoding is longer than `chunk_len` forms a chunk of its own. If the input is
+/// invalid, the chunks before the invalid ctlv are yielded first, then the error and the offset
+/// at which it occurred, and then the iterator ends.
+pub fn chunks_by_frames(input: &[u8], chunk_len: usize) -> FrameChunks<'_> {
+    FrameChunks {
+        input,
+        iter: CtlvIter::new(input),
+        chunk_len,
+    }
+}
+
+/// The iterator returned by [`chunks_by_frames`](fn.chunks_by_frames.html).
+#[derive(Debug, Clone)]
+pub struct FrameChunks<'a> {
+    input: &'a [u8],
+    iter: CtlvIter<'a>,
+    chunk_len: usize,
+}
+
+impl<'a> Iterator for FrameChunks<'a> {
+    type Item = Result<&'a [u8], (DecodeError, usize)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.iter.offset();
+        let mut end = start;
+        loop {
+            let mut probe = self.iter.clone();
+            match probe.next() {
+                None => break,
+                Some(Err(e)) => {
+                    if end == start {
+                        self.iter = probe;
+                        return Some(Err(e));
+                    }
+                    break;
+                }
+                Some(Ok(_)) => {
+                    if end > start && probe.offset() - start > self.chunk_len {
+                        break;
+                    }
+                    self.iter = probe;
+                    end = self.iter.offset();
+                }
+            }
+        }
+
+        if end == start {
+            None
+        } else {
+            Some(Ok(&self.input[start..end]))
+        }
+    }
+}
+
+impl<'a> FusedIterator for FrameChunks<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ctlvs at offsets 0, 2, 6 and 10, the last one ending at 13.
+    const INPUT: [u8; 13] = [0, 1, 128, 2, 3, 4, 249, 1, 44, 0, 8, 5, 6];
+
+    #[test]
+    fn split() {
+        // On a boundary.
+        assert_eq!(split_at_frame(&INPUT, 6), Ok((&INPUT[..6], &INPUT[6..])));
+        // Inside a header.
+        assert_eq!(split_at_frame(&INPUT, 7), Ok((&INPUT[..6], &INPUT[6..])));
+        // Inside a value.
+        assert_eq!(split_at_frame(&INPUT, 4), Ok((&INPUT[..2], &INPUT[2..])));
+        assert_eq!(split_at_frame(&INPUT, 12), Ok((&INPUT[..10], &INPUT[10..])));
+
+        assert_eq!(split_at_frame(&INPUT, 0), Ok((&[][..], &INPUT[..])));
+        assert_eq!(split_at_frame(&INPUT, 1), Ok((&[][..], &INPUT[..])));
+        assert_eq!(split_at_frame(&INPUT, 13), Ok((&INPUT[..], &[][..])));
+        assert_eq!(split_at_frame(&INPUT, 100), Ok((&INPUT[..], &[][..])));
+        assert_eq!(split_at_frame(&[], 5), Ok((&[][..], &[][..])));
+
+        assert_eq!(split_at_frame(&INPUT[..12], 4),
+                   Err((DecodeError::UnexpectedEndOfInput, 10)));
+    }
+
+    #[test]
+    fn chunks() {
+        let chunks: Vec<_> = chunks_by_frames(&INPUT, 7).map(Result::unwrap).collect();
+        assert_eq!(chunks, [&INPUT[..6], &INPUT[6..]]);
+
+        // Ctlvs longer than the chunk length get a chunk of their own.
+        let chunks: Vec<_> = chunks_by_frames(&INPUT, 3).map(Result::unwrap).collect();
+        assert_eq!(chunks, [&INPUT[..2], &INPUT[2..6], &INPUT[6..10], &INPUT[10..]]);
+
+        assert_eq!(chunks_by_frames(&INPUT, 100).collect::<Vec<_>>(), [Ok(&INPUT[..])]);
+        assert_eq!(chunks_by_frames(&[], 100).next(), None);
+    }
+
+    #[test]
+    fn chunks_invalid() {
+        let mut chunks = chunks_by_frames(&INPUT[..12], 6);
+        assert_eq!(chunks.next(), Some(Ok(&INPUT[..6])));
+        assert_eq!(chunks.next(), Some(Ok(&INPUT[6..10])));
+        assert_eq!(chunks.next(), Some(Err((DecodeError::UnexpectedEndOfInput, 10))));
+        assert_eq!(chunks.next(), None);
+    }
+}
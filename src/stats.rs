@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+
+use super::nested::{DecoderOptions, NestedError};
+use super::visit::{walk_with, Visit, VisitFlow};
+use super::{CtlvIter, CtlvRef, DecodeError};
+
+/// Statistics about the ctlvs of a single type, see [`Stats`](struct.Stats.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TypeStats {
+    /// How many ctlvs of the type there are.
+    pub count: usize,
+    /// The combined length of their values.
+    pub value_bytes: usize,
+    /// The length of the shortest value.
+    pub min_len: usize,
+    /// The length of the longest value.
+    pub max_len: usize,
+}
+
+impl TypeStats {
+    /// Returns the mean length of the values.
+    pub fn mean_len(&self) -> f64 {
+        self.value_bytes as f64 / self.count as f64
+    }
+}
+
+/// Statistics about the composition of an encoded sequence of ctlvs, as computed by
+/// [`analyze`](fn.analyze.html) and [`analyze_nested`](fn.analyze_nested.html).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Stats {
+    /// The statistics for each type that occurs.
+    pub types: BTreeMap<u64, TypeStats>,
+    /// How many ctlvs there are in total.
+    pub frames: usize,
+    /// The combined length of the headers (types and lengths) of all ctlvs.
+    pub header_bytes: usize,
+    /// The length of the input.
+    pub total_bytes: usize,
+}
+
+impl Stats {
+    /// Returns the fraction of the input that is taken up by headers. This is zero for an
+    /// empty input.
+    pub fn header_overhead(&self) -> f64 {
+        if self.total_bytes == 0 {
+            0.0
+        } else {
+            self.header_bytes as f64 / self.total_bytes as f64
+        }
+    }
+
+    fn add(&mut self, frame: CtlvRef) {
+        let len = frame.value.len();
+        let stats = self.types.entry(frame.type_).or_insert(TypeStats {
+                                                                count: 0,
+                                                                value_bytes: 0,
+                                                                min_len: len,
+                                                                max_len: len,
+                                                            });
+        stats.count += 1;
+        stats.value_bytes += len;
+        stats.min_len = stats.min_len.min(len);
+        stats.max_len = stats.max_len.max(len);
+
+        self.frames += 1;
+        self.header_bytes += frame.encoding_length() - len;
+    }
+}
+
+/// Compute statistics about an encoded sequence of ctlvs, without copying any values.
+///
+/// If the input is invalid, returns the error and the offset at which it occurred.
+pub fn analyze(input: &[u8]) -> Result<Stats, (DecodeError, usize)> {
+    let mut stats = Stats {
+        total_bytes: input.len(),
+        ..Stats::default()
+    };
+    for result in CtlvIter::new(input) {
+        stats.add(result?);
+    }
+    Ok(stats)
+}
+
+/// Compute statistics about a sequence of nested ctlvs, counting the children of all ctlvs for
+/// which `is_container` returns true as well.
+///
+/// The value lengths of containers include the encodings of their children, and the header
+/// bytes include the headers at all levels. Like [`walk`](fn.walk.html), this does not
+/// allocate apart from the map of per-type statistics, and uses the depth limit of the
+/// default `DecoderOptions`.
+pub fn analyze_nested<F>(input: &[u8], is_container: F) -> Result<Stats, NestedError>
+    where F: Fn(u64) -> bool
+{
+    analyze_nested_with(input, is_container, &DecoderOptions::default())
+}
+
+/// Compute statistics about a sequence of nested ctlvs like
+/// [`analyze_nested`](fn.analyze_nested.html), but with the depth limit of the given options,
+/// see [`walk_with`](fn.walk_with.html).
+pub fn analyze_nested_with<F>(input: &[u8],
+                              is_container: F,
+                              options: &DecoderOptions)
+                              -> Result<Stats, NestedError>
+    where F: Fn(u64) -> bool
+{
+    struct Analyzer<F> {
+        stats: Stats,
+        is_container: F,
+    }
+
+    impl<'a, F: Fn(u64) -> bool> Visit<'a> for Analyzer<F> {
+        fn frame(&mut self, _depth: usize, _offset: usize, frame: CtlvRef<'a>) -> VisitFlow {
+            self.stats.add(frame);
+            if (self.is_container)(frame.type_) {
+                VisitFlow::Descend
+            } else {
+                VisitFlow::Skip
+            }
+        }
+    }
+
+    let mut analyzer = Analyzer {
+        stats: Stats {
+            total_bytes: input.len(),
+            ..Stats::default()
+        },
+        is_container: &is_container,
+    };
+    walk_with(input, &is_container, &mut analyzer, options)?;
+    Ok(analyzer.stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_util::ctlv;
+
+    fn fixture() -> Vec<u8> {
+        [ctlv(0, vec![1]),
+         ctlv(300, [ctlv(128, vec![2; 4]), ctlv(0, vec![3])].concat()),
+         ctlv(128, vec![]),
+         ctlv(128, vec![4; 10])]
+            .concat()
+    }
+
+    #[test]
+    fn flat() {
+        let stats = analyze(&fixture()).unwrap();
+        assert_eq!(stats.frames, 4);
+        assert_eq!(stats.total_bytes, 2 + 12 + 2 + 12);
+        assert_eq!(stats.header_bytes, 1 + 4 + 2 + 2);
+        assert_eq!(stats.header_overhead(), 9.0 / 28.0);
+        assert_eq!(stats.types.len(), 3);
+        assert_eq!(stats.types[&128],
+                   TypeStats {
+                       count: 2,
+                       value_bytes: 10,
+                       min_len: 0,
+                       max_len: 10,
+                   });
+        assert_eq!(stats.types[&128].mean_len(), 5.0);
+        assert_eq!(stats.types[&300].value_bytes, 8);
+
+        assert_eq!(analyze(&[]).unwrap().header_overhead(), 0.0);
+        assert_eq!(analyze(&[0, 1, 128]), Err((DecodeError::UnexpectedEndOfInput, 2)));
+    }
+
+    #[test]
+    fn nested() {
+        let stats = analyze_nested(&fixture(), |type_| type_ == 300).unwrap();
+        assert_eq!(stats.frames, 6);
+        assert_eq!(stats.header_bytes, 9 + 2 + 1);
+        assert_eq!(stats.types[&0],
+                   TypeStats {
+                       count: 2,
+                       value_bytes: 2,
+                       min_len: 1,
+                       max_len: 1,
+                   });
+        assert_eq!(stats.types[&128].count, 3);
+        assert_eq!(stats.types[&128].min_len, 0);
+        assert_eq!(stats.types[&128].value_bytes, 14);
+
+        let invalid = ctlv(300, vec![128, 3]);
+        assert_eq!(analyze_nested(&invalid, |type_| type_ == 300),
+                   Err(NestedError::Decode(DecodeError::UnexpectedEndOfInput, 4)));
+
+        let options = DecoderOptions { max_depth: 0 };
+        assert_eq!(analyze_nested_with(&fixture(), |type_| type_ == 300, &options),
+                   Err(NestedError::DepthExceeded(2)));
+        let options = DecoderOptions { max_depth: 100 };
+        assert_eq!(analyze_nested_with(&fixture(), |type_| type_ == 300, &options).unwrap(),
+                   stats);
+    }
+}
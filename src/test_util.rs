@@ -0,0 +1,150 @@
+//! Generators for malformed encodings, for testing code that decodes ctlvs.
+//!
+//! Rather than random data, these target the known ways in which a header can be wrong: types
+//! and lengths that are not encoded canonically, encodings that end one byte (or more) too
+//! early, and lengths far larger than any actual input. Every buffer produced here is rejected
+//! by [`CtlvRef::decode`](../struct.CtlvRef.html#method.decode).
+//!
+//! Requires the `test-util` feature.
+
+use super::CtlvRef;
+#[cfg(test)]
+use super::Ctlv;
+
+// Returns the encoding of a ctlv, for building inputs in the tests of this crate.
+#[cfg(test)]
+pub(crate) fn ctlv(type_: u64, value: Vec<u8>) -> Vec<u8> {
+    Ctlv::new(type_, value).encode_vec()
+}
+
+/// Returns all non-canonical varu64 encodings of `n`, i.e. those that use more bytes than
+/// necessary, from the shortest to the longest.
+pub fn overlong_varu64(n: u64) -> Vec<Vec<u8>> {
+    let canonical_len = varu64::encoding_length(n);
+    (1..=8usize)
+        .filter(|width| 1 + width > canonical_len && (*width == 8 || n >> (8 * width) == 0))
+        .map(|width| {
+                 let mut encoding = vec![247 + width as u8];
+                 encoding.extend_from_slice(&n.to_be_bytes()[8 - width..]);
+                 encoding
+             })
+        .collect()
+}
+
+/// Returns encodings of ctlvs whose type is not encoded canonically, but which would be valid
+/// otherwise. They are rejected with `DecodeError::Type`.
+pub fn non_canonical_types() -> Vec<Vec<u8>> {
+    let mut out = Vec::new();
+    for type_ in &[0, 7, 127, 128, 247, 248, 300, u64::MAX >> 8] {
+        for mut encoding in overlong_varu64(*type_) {
+            if *type_ < 128 {
+                encoding.extend_from_slice(&vec![0; super::type_implied_length(*type_)]);
+            } else {
+                encoding.push(0);
+            }
+            out.push(encoding);
+        }
+    }
+    out
+}
+
+/// Returns encodings of ctlvs whose length is not encoded canonically, but which would be valid
+/// otherwise. They are rejected with `DecodeError::Length`.
+pub fn non_canonical_lengths() -> Vec<Vec<u8>> {
+    let mut out = Vec::new();
+    for len in &[0, 1, 247, 248, 300] {
+        for length in overlong_varu64(*len) {
+            let mut encoding = vec![128];
+            encoding.extend_from_slice(&length);
+            encoding.extend_from_slice(&vec![0; *len as usize]);
+            out.push(encoding);
+        }
+    }
+    out
+}
+
+/// Returns every proper prefix of the encoding of `frame`, from the longest (missing only the
+/// last byte) to the empty one. They are rejected with `DecodeError::UnexpectedEndOfInput`.
+pub fn truncations(frame: CtlvRef) -> Vec<Vec<u8>> {
+    let encoding = frame.encode_vec();
+    (0..encoding.len()).rev().map(|len| encoding[..len].to_vec()).collect()
+}
+
+/// Returns ctlvs of a few bytes whose headers declare huge lengths, up to `u64::MAX`. They are
+/// rejected with `DecodeError::UnexpectedEndOfInput`, or with `DecodeError::LengthTooLarge` by
+/// decoders with a maximum length. Code that allocates space for values before reading them is
+/// likely to fail on these.
+pub fn max_lengths() -> Vec<Vec<u8>> {
+    [u64::MAX, u64::MAX - 1, usize::MAX as u64, u32::MAX as u64 + 1, u32::MAX as u64, 1 << 40]
+        .iter()
+        .map(|len| {
+                 let mut encoding = vec![128];
+                 varu64::encode_write(*len, &mut encoding).unwrap();
+                 encoding.extend_from_slice(&[0; 16]);
+                 encoding
+             })
+        .collect()
+}
+
+/// Returns the buffers of all other generators in this module, with the truncations of a few
+/// ctlvs with implied and explicit lengths.
+pub fn all() -> Vec<Vec<u8>> {
+    let mut out = non_canonical_types();
+    out.extend(non_canonical_lengths());
+    out.extend(max_lengths());
+    let value = [7; 300];
+    for (type_, len) in &[(0, 1), (8, 2), (24, 8), (128, 0), (300, 300), (u64::MAX, 5)] {
+        out.extend(truncations(CtlvRef {
+                                   type_: *type_,
+                                   value: &value[..*len],
+                               }));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::DecodeError;
+    use varu64::DecodeError as VarU64Error;
+
+    #[test]
+    fn overlong() {
+        assert_eq!(overlong_varu64(5)[0], [248, 5]);
+        assert_eq!(overlong_varu64(5)[7], [255, 0, 0, 0, 0, 0, 0, 0, 5]);
+        assert_eq!(overlong_varu64(5).len(), 8);
+        assert_eq!(overlong_varu64(300), [vec![250, 0, 1, 44],
+                                          vec![251, 0, 0, 1, 44],
+                                          vec![252, 0, 0, 0, 1, 44],
+                                          vec![253, 0, 0, 0, 0, 1, 44],
+                                          vec![254, 0, 0, 0, 0, 0, 1, 44],
+                                          vec![255, 0, 0, 0, 0, 0, 0, 1, 44]]);
+        assert!(overlong_varu64(u64::MAX).is_empty());
+        for encoding in overlong_varu64(1 << 20) {
+            assert_eq!(varu64::decode(&encoding),
+                       Err((VarU64Error::NonCanonical(1 << 20), &[][..])));
+        }
+    }
+
+    #[test]
+    fn rejected() {
+        for input in non_canonical_types() {
+            assert!(matches!(CtlvRef::decode(&input), Err((DecodeError::Type(_), _))));
+        }
+        for input in non_canonical_lengths() {
+            assert!(matches!(CtlvRef::decode(&input), Err((DecodeError::Length(_), _))));
+        }
+        for input in max_lengths() {
+            assert!(matches!(CtlvRef::decode(&input),
+                             Err((DecodeError::UnexpectedEndOfInput, _))));
+        }
+        assert_eq!(truncations(CtlvRef { type_: 300, value: &[1] }),
+                   [vec![249, 1, 44, 1], vec![249, 1, 44], vec![249, 1], vec![249], vec![]]);
+
+        let all = all();
+        assert!(all.len() > 100);
+        for input in all {
+            assert!(CtlvRef::decode(&input).is_err(), "{:?}", input);
+        }
+    }
+}
@@ -0,0 +1,393 @@
+use std::{error, fmt};
+
+use super::nested::{DecoderOptions, NestedError};
+use super::{check_implied_length, type_implied_length, CtlvIter, CtlvRef, DecodeError};
+
+/// Copy the ctlvs of an encoded sequence for which `pred` returns true into a new buffer,
+/// dropping all others.
+///
+/// The kept ctlvs are copied verbatim, in their original order. If the input is invalid,
+/// returns the error and the offset at which it occurred.
+pub fn retain_encoded<F>(input: &[u8], mut pred: F) -> Result<Vec<u8>, (DecodeError, usize)>
+    where F: FnMut(&CtlvRef) -> bool
+{
+    let mut out = Vec::with_capacity(input.len());
+    let mut iter = CtlvIter::new(input);
+
+    loop {
+        let start = iter.offset();
+        match iter.next() {
+            None => return Ok(out),
+            Some(Err(e)) => return Err(e),
+            Some(Ok(frame)) => {
+                if pred(&frame) {
+                    out.extend_from_slice(&input[start..iter.offset()]);
+                }
+            }
+        }
+    }
+}
+
+/// How many ctlvs [`strip_types`](fn.strip_types.html) dropped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct StripStats {
+    /// How many ctlvs were dropped.
+    pub frames: usize,
+    /// The combined length of the encodings of the dropped ctlvs.
+    pub bytes: usize,
+}
+
+/// Append an encoded sequence of ctlvs to `out`, without the ctlvs whose type satisfies `drop`.
+///
+/// The remaining ctlvs are copied verbatim. If the input is invalid, returns the error and the
+/// offset at which it occurred, and leaves `out` unchanged.
+pub fn strip_types<D>(input: &[u8],
+                      drop: D,
+                      out: &mut Vec<u8>)
+                      -> Result<StripStats, (DecodeError, usize)>
+    where D: Fn(u64) -> bool
+{
+    let start = out.len();
+    let mut stats = StripStats::default();
+    let mut iter = CtlvIter::new(input);
+
+    loop {
+        let offset = iter.offset();
+        match iter.next() {
+            None => return Ok(stats),
+            Some(Err(e)) => {
+                out.truncate(start);
+                return Err(e);
+            }
+            Some(Ok(frame)) => {
+                let encoding = &input[offset..iter.offset()];
+                if drop(frame.type_) {
+                    stats.frames += 1;
+                    stats.bytes += encoding.len();
+                } else {
+                    out.extend_from_slice(encoding);
+                }
+            }
+        }
+    }
+}
+
+/// Like [`strip_types`](fn.strip_types.html), but removes the ctlvs from the buffer itself,
+/// moving the remaining ones to the front.
+///
+/// The buffer is checked to be a valid sequence first, on error it is left unchanged.
+pub fn strip_types_in_place<D>(buf: &mut Vec<u8>,
+                               drop: D)
+                               -> Result<StripStats, (DecodeError, usize)>
+    where D: Fn(u64) -> bool
+{
+    // The offsets and lengths of the encodings of all ctlvs, and whether to keep them.
+    let mut frames = Vec::new();
+    let mut iter = CtlvIter::new(buf);
+    loop {
+        let offset = iter.offset();
+        match iter.next() {
+            None => break,
+            Some(Err(e)) => return Err(e),
+            Some(Ok(frame)) => frames.push((offset, iter.offset() - offset, !drop(frame.type_))),
+        }
+    }
+
+    let mut stats = StripStats::default();
+    let mut end = 0;
+    for (offset, len, keep) in frames {
+        if keep {
+            buf.copy_within(offset..offset + len, end);
+            end += len;
+        } else {
+            stats.frames += 1;
+            stats.bytes += len;
+        }
+    }
+    buf.truncate(end);
+    Ok(stats)
+}
+
+/// Append an encoded sequence of ctlvs to `out`, with every type replaced by its image under
+/// `map`. Returns how many bytes were appended.
+///
+/// The values are copied verbatim, only the headers are re-encoded, so they may change their
+/// size. Mapping a type to one below 128 makes it imply a length, which must then equal the
+/// length of the value, otherwise this fails with `NestedError::ImpliedLength`. On error,
+/// `out` is left unchanged.
+pub fn remap_types<M>(input: &[u8], map: M, out: &mut Vec<u8>) -> Result<usize, NestedError>
+    where M: Fn(u64) -> u64
+{
+    remap_types_nested(input, map, |_| false, &DecoderOptions::default(), out)
+}
+
+/// Like [`remap_types`](fn.remap_types.html), but also remaps the types inside the values of
+/// all ctlvs for which `is_container` returns true (given the original type).
+///
+/// Since remapping the children can change the lengths of their headers, the lengths of
+/// containers can change as well. This fails with `NestedError::ImpliedLength` if that happens
+/// to a container whose (new) type implies its length.
+pub fn remap_types_nested<M, F>(input: &[u8],
+                                map: M,
+                                is_container: F,
+                                options: &DecoderOptions,
+                                out: &mut Vec<u8>)
+                                -> Result<usize, NestedError>
+    where M: Fn(u64) -> u64,
+          F: Fn(u64) -> bool
+{
+    let start = out.len();
+    match remap_sequence(input, 0, 0, &map, &is_container, options, out) {
+        Ok(()) => Ok(out.len() - start),
+        Err(e) => {
+            out.truncate(start);
+            Err(e)
+        }
+    }
+}
+
+fn remap_sequence<M, F>(input: &[u8],
+                        base: usize,
+                        depth: usize,
+                        map: &M,
+                        is_container: &F,
+                        options: &DecoderOptions,
+                        out: &mut Vec<u8>)
+                        -> Result<(), NestedError>
+    where M: Fn(u64) -> u64,
+          F: Fn(u64) -> bool
+{
+    let mut iter = CtlvIter::new(input);
+
+    loop {
+        let offset = base + iter.offset();
+        let frame = match iter.next() {
+            None => return Ok(()),
+            Some(Err((e, inner))) => return Err(NestedError::Decode(e, base + inner)),
+            Some(Ok(frame)) => frame,
+        };
+        let type_ = map(frame.type_);
+
+        let mut value = Vec::new();
+        let value = if is_container(frame.type_) {
+            if depth >= options.max_depth {
+                return Err(NestedError::DepthExceeded(offset));
+            }
+            let value_offset = offset + frame.encoding_length() - frame.value.len();
+            remap_sequence(frame.value,
+                           value_offset,
+                           depth + 1,
+                           map,
+                           is_container,
+                           options,
+                           &mut value)?;
+            &value[..]
+        } else {
+            frame.value
+        };
+
+        check_implied_length(type_, value.len()).map_err(|_| NestedError::ImpliedLength(offset))?;
+        CtlvRef { type_, value }.encode_write(&mut *out).unwrap();
+    }
+}
+
+/// Everything that can go wrong in [`rewrite_type_in_place`](fn.rewrite_type_in_place.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RewriteError {
+    /// The buffer does not start with a valid ctlv.
+    Decode(DecodeError),
+    /// The encodings of the old and the new type have different lengths.
+    WidthMismatch {
+        /// The old type.
+        old: u64,
+        /// The new type.
+        new: u64,
+    },
+    /// The new type does not describe the length of the value in the same way as the old type:
+    /// one of them implies a length and the other does not, or they imply different lengths.
+    LengthMismatch {
+        /// The old type.
+        old: u64,
+        /// The new type.
+        new: u64,
+    },
+}
+
+impl fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        match self {
+            RewriteError::Decode(e) => e.fmt(f),
+            RewriteError::WidthMismatch { old, new } => {
+                write!(f, "Can not rewrite type {} to {}: encodings differ in length", old, new)
+            }
+            RewriteError::LengthMismatch { old, new } => {
+                write!(f,
+                       "Can not rewrite type {} to {}: lengths are encoded differently",
+                       old,
+                       new)
+            }
+        }
+    }
+}
+
+impl error::Error for RewriteError {}
+
+/// Replace the type of the ctlv at the start of the buffer, by overwriting the bytes of its
+/// encoding.
+///
+/// This only works if the encoding of the new type is exactly as long as that of the old one,
+/// and if both types encode the length of the value the same way (i.e. either both do not imply
+/// a length, or both imply the same length). Otherwise, the rest of the buffer would have to be
+/// moved or reinterpreted, and the buffer is left unchanged.
+pub fn rewrite_type_in_place(buf: &mut [u8], new_type: u64) -> Result<(), RewriteError> {
+    let (frame, _) = CtlvRef::decode(buf).map_err(|(e, _)| RewriteError::Decode(e))?;
+    let old = frame.type_;
+    let width = varu64::encoding_length(old);
+
+    if varu64::encoding_length(new_type) != width {
+        return Err(RewriteError::WidthMismatch { old, new: new_type });
+    }
+    if type_implied_length(new_type) != type_implied_length(old) {
+        return Err(RewriteError::LengthMismatch { old, new: new_type });
+    }
+
+    varu64::encode(new_type, &mut buf[..width]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_util::ctlv;
+
+    #[test]
+    fn retain() {
+        let input = [0, 1, 128, 2, 3, 4, 1, 5, 249, 1, 44, 0];
+        assert_eq!(retain_encoded(&input, |frame| frame.type_ != 128).unwrap(),
+                   [0, 1, 1, 5, 249, 1, 44, 0]);
+        assert_eq!(retain_encoded(&input, |_| true).unwrap(), input);
+        assert_eq!(retain_encoded(&input, |_| false).unwrap(), []);
+
+        let mut seen = Vec::new();
+        retain_encoded(&input, |frame| {
+            seen.push(frame.type_);
+            true
+        })
+            .unwrap();
+        assert_eq!(seen, [0, 128, 1, 300]);
+
+        assert_eq!(retain_encoded(&[0, 1, 128], |_| true),
+                   Err((DecodeError::UnexpectedEndOfInput, 2)));
+    }
+
+    #[test]
+    fn remap() {
+        let migrate = |type_| match type_ {
+            300 => 4300,
+            301 => 0,
+            0 => 129,
+            type_ => type_,
+        };
+        let input = [ctlv(300, vec![1, 2]), ctlv(301, vec![3]), ctlv(0, vec![4])].concat();
+        let mut out = vec![42];
+
+        let written = remap_types(&input, migrate, &mut out).unwrap();
+        assert_eq!(out[1..],
+                   [ctlv(4300, vec![1, 2]), ctlv(0, vec![3]), ctlv(129, vec![4])].concat()[..]);
+        assert_eq!(written, out.len() - 1);
+        assert_eq!(written, input.len() - 3 + 1);
+
+        out.truncate(1);
+        let input = [ctlv(0, vec![4]), ctlv(301, vec![3, 4])].concat();
+        assert_eq!(remap_types(&input, migrate, &mut out), Err(NestedError::ImpliedLength(2)));
+        assert_eq!(out, [42]);
+    }
+
+    #[test]
+    fn remap_nested() {
+        let is_container = |type_| type_ == 300 || type_ == 16;
+        let grow = |type_| if type_ == 0 { 129 } else { type_ };
+
+        let inner = [ctlv(0, vec![1]), ctlv(128, vec![2])].concat();
+        let input = ctlv(300, [ctlv(300, inner), ctlv(0, vec![3])].concat());
+        let mut out = Vec::new();
+        remap_types_nested(&input, grow, is_container, &DecoderOptions::default(), &mut out)
+            .unwrap();
+        let inner = [ctlv(129, vec![1]), ctlv(128, vec![2])].concat();
+        assert_eq!(out, ctlv(300, [ctlv(300, inner), ctlv(129, vec![3])].concat()));
+
+        // The value of a type-16 container must stay four bytes long.
+        let input = ctlv(16, [ctlv(0, vec![1]), ctlv(0, vec![2])].concat());
+        assert_eq!(remap_types_nested(&input,
+                                      grow,
+                                      is_container,
+                                      &DecoderOptions::default(),
+                                      &mut out),
+                   Err(NestedError::ImpliedLength(0)));
+        assert_eq!(remap_types(&input, grow, &mut out).unwrap(), 5);
+    }
+
+    #[test]
+    fn strip() {
+        let debug = |type_| type_ == 1 || type_ == 300;
+        let input = [ctlv(0, vec![1]),
+                     ctlv(1, vec![2]),
+                     ctlv(128, vec![3, 4]),
+                     ctlv(300, vec![5; 10]),
+                     ctlv(0, vec![6])]
+            .concat();
+        let expected = [ctlv(0, vec![1]), ctlv(128, vec![3, 4]), ctlv(0, vec![6])].concat();
+        let stats = StripStats {
+            frames: 2,
+            bytes: 2 + 14,
+        };
+
+        let mut out = vec![42];
+        assert_eq!(strip_types(&input, debug, &mut out), Ok(stats));
+        assert_eq!(out[1..], expected[..]);
+        assert_eq!(CtlvIter::new(&out[1..]).filter(Result::is_err).count(), 0);
+
+        let mut buf = input.clone();
+        assert_eq!(strip_types_in_place(&mut buf, debug), Ok(stats));
+        assert_eq!(buf, expected);
+
+        let mut buf = input.clone();
+        assert_eq!(strip_types_in_place(&mut buf, |_| false), Ok(StripStats::default()));
+        assert_eq!(buf, input);
+    }
+
+    #[test]
+    fn strip_invalid() {
+        let input = [ctlv(1, vec![2]), ctlv(0, vec![1]), vec![128, 3, 0]].concat();
+
+        let mut out = vec![42];
+        assert_eq!(strip_types(&input, |type_| type_ == 1, &mut out),
+                   Err((DecodeError::UnexpectedEndOfInput, 4)));
+        assert_eq!(out, [42]);
+
+        let mut buf = input.clone();
+        assert_eq!(strip_types_in_place(&mut buf, |type_| type_ == 1),
+                   Err((DecodeError::UnexpectedEndOfInput, 4)));
+        assert_eq!(buf, input);
+    }
+
+    #[test]
+    fn rewrite_type() {
+        let mut buf = [ctlv(300, vec![1, 2]), ctlv(0, vec![3])].concat();
+        rewrite_type_in_place(&mut buf, 400).unwrap();
+        assert_eq!(buf, [ctlv(400, vec![1, 2]), ctlv(0, vec![3])].concat());
+        rewrite_type_in_place(&mut buf[6..], 7).unwrap();
+        assert_eq!(buf, [ctlv(400, vec![1, 2]), ctlv(7, vec![3])].concat());
+
+        let original = buf.clone();
+        assert_eq!(rewrite_type_in_place(&mut buf, 129),
+                   Err(RewriteError::WidthMismatch { old: 400, new: 129 }));
+        assert_eq!(rewrite_type_in_place(&mut buf[6..], 128),
+                   Err(RewriteError::LengthMismatch { old: 7, new: 128 }));
+        assert_eq!(rewrite_type_in_place(&mut buf[6..], 8),
+                   Err(RewriteError::LengthMismatch { old: 7, new: 8 }));
+        assert_eq!(rewrite_type_in_place(&mut buf[..5], 401),
+                   Err(RewriteError::Decode(DecodeError::UnexpectedEndOfInput)));
+        assert_eq!(buf, original);
+    }
+}
@@ -0,0 +1,319 @@
+use std::{error, fmt};
+
+use super::nested::DecoderOptions;
+use super::{check_implied_length, type_implied_length, Ctlv, CtlvIter, CtlvRef, DecodeError};
+
+/// How [`Value::from_ctlv`](enum.Value.html#method.from_ctlv) interprets the value of a ctlv
+/// of some type.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Interp {
+    /// Keep the value as opaque bytes.
+    Bytes,
+    /// A big-endian unsigned integer.
+    Uint,
+    /// A big-endian two's complement signed integer.
+    Int,
+    /// A big-endian IEEE 754 double precision float.
+    Float,
+    /// A sequence of ctlvs.
+    Sequence,
+}
+
+/// A dynamically typed ctlv value, for inspecting documents without a fixed schema.
+///
+/// Numbers are exactly as wide as their type requires: the implied length for types below 128,
+/// eight bytes for all other types. Values of any other width are rejected rather than padded
+/// or truncated, so converting a canonical ctlv to a `Value` and back yields the same bytes.
+/// To deal with differently sized numbers, interpret them as `Bytes` and convert them manually.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Opaque bytes.
+    Bytes(Vec<u8>),
+    /// An unsigned integer.
+    Uint(u64),
+    /// A signed integer.
+    Int(i64),
+    /// A float.
+    Float(f64),
+    /// A sequence of ctlvs, as pairs of their types and values.
+    Sequence(Vec<(u64, Value)>),
+}
+
+/// Everything that can go wrong when converting between ctlvs and `Value`s.
+///
+/// All offsets are relative to the start of the outermost ctlv.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ValueError {
+    /// The ctlv at the given offset (inside a sequence) could not be decoded.
+    Decode(DecodeError, usize),
+    /// The sequence at the given offset has children deeper than the maximum depth.
+    DepthExceeded(usize),
+    /// The value of the ctlv at the given offset has a width that its interpretation does not
+    /// allow for its type.
+    Width {
+        /// The type of the ctlv.
+        type_: u64,
+        /// The length of the value of the ctlv.
+        len: usize,
+        /// The offset of the ctlv.
+        offset: usize,
+    },
+    /// A `Value` can not be encoded as a ctlv of the given type, because its encoding does not
+    /// have the width the type requires.
+    Unrepresentable(u64),
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
+        match self {
+            ValueError::Decode(e, offset) => write!(f, "{} (at offset {})", e, offset),
+            ValueError::DepthExceeded(offset) => {
+                write!(f, "Invalid ctlv value: sequence at offset {} is nested too deeply", offset)
+            }
+            ValueError::Width { type_, len, offset } => {
+                write!(f,
+                       "Invalid ctlv value: {} bytes at offset {} are not a valid width for type {}",
+                       len,
+                       offset,
+                       type_)
+            }
+            ValueError::Unrepresentable(type_) => {
+                write!(f, "Value does not fit the width of ctlv type {}", type_)
+            }
+        }
+    }
+}
+
+impl error::Error for ValueError {}
+
+// The width of numbers of the given type.
+fn number_width(type_: u64) -> usize {
+    match type_implied_length(type_) {
+        0 => 8,
+        width => width,
+    }
+}
+
+impl Value {
+    /// Convert a ctlv into a `Value`, with `interp` determining how to read the values of each
+    /// type. Sequences are converted recursively, up to the depth limit of the default
+    /// `DecoderOptions`.
+    pub fn from_ctlv<F>(frame: CtlvRef, interp: F) -> Result<Value, ValueError>
+        where F: Fn(u64) -> Interp
+    {
+        Value::from_ctlv_with(frame, interp, &DecoderOptions::default())
+    }
+
+    /// Convert a ctlv into a `Value` like [`from_ctlv`](#method.from_ctlv), but with the depth
+    /// limit of the given options. The ctlv itself has depth zero.
+    pub fn from_ctlv_with<F>(frame: CtlvRef,
+                             interp: F,
+                             options: &DecoderOptions)
+                             -> Result<Value, ValueError>
+        where F: Fn(u64) -> Interp
+    {
+        Value::from_frame(frame, 0, 0, &interp, options)
+    }
+
+    fn from_frame<F>(frame: CtlvRef,
+                     offset: usize,
+                     depth: usize,
+                     interp: &F,
+                     options: &DecoderOptions)
+                     -> Result<Value, ValueError>
+        where F: Fn(u64) -> Interp
+    {
+        let interp = match interp(frame.type_) {
+            Interp::Bytes => return Ok(Value::Bytes(frame.value.to_vec())),
+            Interp::Sequence => {
+                if depth >= options.max_depth {
+                    return Err(ValueError::DepthExceeded(offset));
+                }
+
+                let value_offset = offset + frame.encoding_length() - frame.value.len();
+                let mut iter = CtlvIter::new(frame.value);
+                let mut children = Vec::new();
+                loop {
+                    let child_offset = value_offset + iter.offset();
+                    match iter.next() {
+                        None => return Ok(Value::Sequence(children)),
+                        Some(Err((e, inner))) => {
+                            return Err(ValueError::Decode(e, value_offset + inner))
+                        }
+                        Some(Ok(child)) => {
+                            let value =
+                                Value::from_frame(child, child_offset, depth + 1, interp, options)?;
+                            children.push((child.type_, value));
+                        }
+                    }
+                }
+            }
+            number => number,
+        };
+
+        let width = frame.value.len();
+        let accepted = match interp {
+            Interp::Float => width == 8,
+            _ => width <= 8,
+        };
+        if width != number_width(frame.type_) || !accepted {
+            return Err(ValueError::Width {
+                           type_: frame.type_,
+                           len: width,
+                           offset,
+                       });
+        }
+
+        let mut buf = [0; 8];
+        buf[8 - width..].copy_from_slice(frame.value);
+        let n = u64::from_be_bytes(buf);
+        Ok(match interp {
+               Interp::Uint => Value::Uint(n),
+               Interp::Float => Value::Float(f64::from_bits(n)),
+               _ => {
+                   // Sign-extend from the actual width.
+                   let shift = 64 - 8 * width as u32;
+                   Value::Int(((n << shift) as i64) >> shift)
+               }
+           })
+    }
+
+    /// Convert this `Value` into a ctlv of the given type.
+    ///
+    /// Children of sequences are converted with their own types. Fails if any value does not
+    /// have the width its type requires, e.g. a `Uint` above 255 for a type implying a single
+    /// byte, or a `Float` for a type implying four bytes.
+    pub fn to_ctlv(&self, type_: u64) -> Result<Ctlv, ValueError> {
+        let value = match self {
+            Value::Bytes(bytes) => bytes.clone(),
+            Value::Uint(n) => number_bytes(type_, *n, |width| width == 8 || *n >> (8 * width) == 0)?,
+            Value::Int(n) => {
+                number_bytes(type_, *n as u64, |width| {
+                    let shift = 64 - 8 * width as u32;
+                    (n << shift) >> shift == *n
+                })?
+            }
+            Value::Float(x) => number_bytes(type_, x.to_bits(), |width| width == 8)?,
+            Value::Sequence(children) => {
+                let mut out = Vec::new();
+                for (child_type, child) in children {
+                    child.to_ctlv(*child_type)?.encode_write(&mut out).unwrap();
+                }
+                out
+            }
+        };
+
+        check_implied_length(type_, value.len()).map_err(|_| ValueError::Unrepresentable(type_))?;
+        Ok(Ctlv::new(type_, value))
+    }
+}
+
+// The big-endian encoding of `n` in the width of the type, if `fits` accepts that width.
+fn number_bytes<F>(type_: u64, n: u64, fits: F) -> Result<Vec<u8>, ValueError>
+    where F: Fn(usize) -> bool
+{
+    let width = number_width(type_);
+    if width > 8 || !fits(width) {
+        return Err(ValueError::Unrepresentable(type_));
+    }
+    Ok(n.to_be_bytes()[8 - width..].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_util::ctlv;
+
+    fn interp(type_: u64) -> Interp {
+        match type_ {
+            0 | 8 | 128 => Interp::Uint,
+            1 | 9 | 129 => Interp::Int,
+            24 | 130 => Interp::Float,
+            300 | 32 => Interp::Sequence,
+            _ => Interp::Bytes,
+        }
+    }
+
+    fn roundtrip(input: &[u8]) -> Value {
+        let (frame, _) = CtlvRef::decode(input).unwrap();
+        let value = Value::from_ctlv(frame, interp).unwrap();
+        assert_eq!(value.to_ctlv(frame.type_).unwrap().encode_vec(), input);
+        value
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(roundtrip(&ctlv(0, vec![200])), Value::Uint(200));
+        assert_eq!(roundtrip(&ctlv(8, vec![1, 2])), Value::Uint(258));
+        assert_eq!(roundtrip(&ctlv(128, vec![0, 0, 0, 0, 0, 0, 1, 0])), Value::Uint(256));
+        assert_eq!(roundtrip(&ctlv(1, vec![255])), Value::Int(-1));
+        assert_eq!(roundtrip(&ctlv(9, vec![127, 255])), Value::Int(32767));
+        assert_eq!(roundtrip(&ctlv(129, (-2i64).to_be_bytes().to_vec())), Value::Int(-2));
+        assert_eq!(roundtrip(&ctlv(24, 1.5f64.to_be_bytes().to_vec())), Value::Float(1.5));
+        assert_eq!(roundtrip(&ctlv(130, (-0.25f64).to_be_bytes().to_vec())),
+                   Value::Float(-0.25));
+    }
+
+    #[test]
+    fn sequences() {
+        let inner = [ctlv(0, vec![7]), ctlv(200, b"hi".to_vec())].concat();
+        let children = [ctlv(300, inner), ctlv(1, vec![128]), ctlv(32, vec![0; 16])].concat();
+        let input = ctlv(300, children);
+
+        assert_eq!(roundtrip(&input),
+                   Value::Sequence(vec![(300,
+                                         Value::Sequence(vec![(0, Value::Uint(7)),
+                                                              (200, Value::Bytes(b"hi".to_vec()))])),
+                                        (1, Value::Int(-128)),
+                                        (32,
+                                         Value::Sequence(vec![(0, Value::Uint(0)); 8]))]));
+    }
+
+    #[test]
+    fn widths_are_explicit() {
+        let input = ctlv(300, ctlv(128, vec![1, 2]));
+        let (frame, _) = CtlvRef::decode(&input).unwrap();
+        assert_eq!(Value::from_ctlv(frame, interp),
+                   Err(ValueError::Width {
+                           type_: 128,
+                           len: 2,
+                           offset: 4,
+                       }));
+        assert_eq!(Value::from_ctlv(CtlvRef { type_: 16, value: &[0; 2] }, |_| Interp::Int),
+                   Err(ValueError::Width {
+                           type_: 16,
+                           len: 2,
+                           offset: 0,
+                       }));
+        assert_eq!(Value::from_ctlv(CtlvRef { type_: 32, value: &[0; 16] }, |_| Interp::Uint),
+                   Err(ValueError::Width {
+                           type_: 32,
+                           len: 16,
+                           offset: 0,
+                       }));
+
+        assert_eq!(Value::Uint(256).to_ctlv(0), Err(ValueError::Unrepresentable(0)));
+        assert_eq!(Value::Int(-129).to_ctlv(1), Err(ValueError::Unrepresentable(1)));
+        assert_eq!(Value::Int(-128).to_ctlv(1).unwrap().value(), [128]);
+        assert_eq!(Value::Float(1.0).to_ctlv(16), Err(ValueError::Unrepresentable(16)));
+        assert_eq!(Value::Bytes(vec![1, 2]).to_ctlv(0), Err(ValueError::Unrepresentable(0)));
+        assert_eq!(Value::Sequence(vec![]).to_ctlv(0), Err(ValueError::Unrepresentable(0)));
+    }
+
+    #[test]
+    fn invalid_sequences() {
+        let input = ctlv(300, vec![128, 5]);
+        let (frame, _) = CtlvRef::decode(&input).unwrap();
+        assert_eq!(Value::from_ctlv(frame, interp),
+                   Err(ValueError::Decode(DecodeError::UnexpectedEndOfInput, 4)));
+
+        let deep = (0..40).fold(vec![], |value, _| ctlv(300, value));
+        let (frame, _) = CtlvRef::decode(&deep).unwrap();
+        assert!(matches!(Value::from_ctlv(frame, interp), Err(ValueError::DepthExceeded(_))));
+
+        // The innermost sequence, at the end of the input, has depth 39.
+        assert!(Value::from_ctlv_with(frame, interp, &DecoderOptions { max_depth: 40 }).is_ok());
+        assert_eq!(Value::from_ctlv_with(frame, interp, &DecoderOptions { max_depth: 39 }),
+                   Err(ValueError::DepthExceeded(deep.len() - 4)));
+    }
+}
@@ -0,0 +1,206 @@
+use std::io::{self, IoSlice, Write};
+
+use super::{Ctlv, CtlvRef, MAX_HEADER_LENGTH};
+
+// How many slices are passed to a single `write_vectored` call. This is the `IOV_MAX` of Linux
+// and most BSDs, operating systems with a lower limit just write fewer slices per call.
+const MAX_SLICES: usize = 1024;
+
+/// Encodes a sequence of ctlvs into the writer with as few `write_vectored` calls as possible,
+/// returning how many bytes have been written.
+///
+/// The headers of all ctlvs are encoded into one buffer first, then the headers and values are
+/// passed to the writer as `IoSlice`s, up to 1024 at a time. Writers that do not support
+/// vectored writes fall back to writing the slices one by one.
+pub fn encode_all_vectored<W: Write>(ctlvs: &[Ctlv], w: &mut W) -> io::Result<usize> {
+    encode_vectored(ctlvs.iter().map(Ctlv::as_ctlv_ref), w)
+}
+
+/// Like [`encode_all_vectored`](fn.encode_all_vectored.html), but for borrowed ctlvs.
+pub fn encode_refs_vectored<W: Write>(frames: &[CtlvRef], w: &mut W) -> io::Result<usize> {
+    encode_vectored(frames.iter().cloned(), w)
+}
+
+fn encode_vectored<'a, I, W>(frames: I, w: &mut W) -> io::Result<usize>
+    where I: ExactSizeIterator<Item = CtlvRef<'a>> + Clone,
+          W: Write
+{
+    let mut headers = vec![0; frames.len() * MAX_HEADER_LENGTH];
+    let mut header_lens = Vec::with_capacity(frames.len());
+    for (frame, header) in frames.clone().zip(headers.chunks_mut(MAX_HEADER_LENGTH)) {
+        header_lens.push(frame.encode_header(header));
+    }
+
+    let mut slices = Vec::with_capacity(2 * frames.len());
+    let mut total = 0;
+    for ((frame, header), header_len) in frames
+            .zip(headers.chunks(MAX_HEADER_LENGTH))
+            .zip(header_lens) {
+        slices.push(IoSlice::new(&header[..header_len]));
+        if !frame.value.is_empty() {
+            slices.push(IoSlice::new(frame.value));
+        }
+        total += header_len + frame.value.len();
+    }
+
+    write_all_vectored(w, &mut slices)?;
+    Ok(total)
+}
+
+// Writes all slices, with at most `MAX_SLICES` per call. After a short write, the next call
+// starts with the first byte that has not been written yet.
+pub(crate) fn write_all_vectored<W: Write>(w: &mut W,
+                                           mut slices: &mut [IoSlice])
+                                           -> io::Result<()> {
+    while !slices.is_empty() {
+        let batch = slices.len().min(MAX_SLICES);
+        match w.write_vectored(&slices[..batch]) {
+            Ok(0) => {
+                return Err(io::Error::new(io::ErrorKind::WriteZero,
+                                          "failed to write whole buffer"))
+            }
+            Ok(written) => IoSlice::advance_slices(&mut slices, written),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Records the number of `write_vectored` calls, accepting at most `limit` bytes per call.
+    struct Recorder {
+        out: Vec<u8>,
+        calls: usize,
+        limit: usize,
+    }
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+            self.calls += 1;
+            let mut written = 0;
+            for buf in bufs {
+                let len = buf.len().min(self.limit - written);
+                self.out.extend_from_slice(&buf[..len]);
+                written += len;
+            }
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn fixture(n: u64) -> Vec<Ctlv> {
+        (0..n)
+            .map(|i| Ctlv::new(128 + i, vec![i as u8; (i % 5) as usize]))
+            .collect()
+    }
+
+    #[test]
+    fn batched() {
+        let ctlvs = fixture(300);
+        let expected: Vec<_> = ctlvs.iter().map(Ctlv::encode_vec).collect();
+        let expected = expected.concat();
+
+        let mut w = Recorder {
+            out: Vec::new(),
+            calls: 0,
+            limit: usize::MAX,
+        };
+        assert_eq!(encode_all_vectored(&ctlvs, &mut w).unwrap(), expected.len());
+        assert_eq!(w.out, expected);
+        assert_eq!(w.calls, 1);
+
+        let mut out = Vec::new();
+        encode_all_vectored(&ctlvs, &mut out).unwrap();
+        assert_eq!(out, expected);
+        assert_eq!(encode_all_vectored(&[], &mut out).unwrap(), 0);
+    }
+
+    #[test]
+    fn chunked() {
+        // 1000 headers and 800 non-empty values.
+        let ctlvs = fixture(1000);
+        let expected: Vec<_> = ctlvs.iter().map(Ctlv::encode_vec).collect();
+        let expected = expected.concat();
+
+        let mut w = Recorder {
+            out: Vec::new(),
+            calls: 0,
+            limit: usize::MAX,
+        };
+        encode_all_vectored(&ctlvs, &mut w).unwrap();
+        assert_eq!(w.out, expected);
+        assert_eq!(w.calls, 2);
+
+        // Partial writes end in the middle of slices.
+        let mut w = Recorder {
+            out: Vec::new(),
+            calls: 0,
+            limit: 7,
+        };
+        encode_all_vectored(&ctlvs, &mut w).unwrap();
+        assert_eq!(w.out, expected);
+        assert_eq!(w.calls, expected.len().div_ceil(7));
+    }
+
+    #[test]
+    fn refs() {
+        let ctlvs = fixture(1000);
+        let frames: Vec<_> = ctlvs.iter().map(Ctlv::as_ctlv_ref).collect();
+        let mut expected = Vec::new();
+        encode_all_vectored(&ctlvs, &mut expected).unwrap();
+
+        for limit in &[1, 3, 100, usize::MAX] {
+            let mut w = Recorder {
+                out: Vec::new(),
+                calls: 0,
+                limit: *limit,
+            };
+            assert_eq!(encode_refs_vectored(&frames, &mut w).unwrap(), expected.len());
+            assert_eq!(w.out, expected);
+        }
+    }
+
+    #[test]
+    fn single() {
+        let ctlv = CtlvRef { type_: 300, value: &[1, 2, 3, 4] };
+        let expected = ctlv.encode_vec();
+
+        for limit in 1..expected.len() + 1 {
+            let mut w = Recorder {
+                out: Vec::new(),
+                calls: 0,
+                limit,
+            };
+            assert_eq!(ctlv.encode_write_vectored(&mut w).unwrap(), expected.len());
+            assert_eq!(w.out, expected);
+            assert_eq!(w.calls, expected.len().div_ceil(limit));
+        }
+
+        let mut w = Recorder {
+            out: Vec::new(),
+            calls: 0,
+            limit: 0,
+        };
+        let e = ctlv.encode_write_vectored(&mut w).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::WriteZero);
+
+        let mut w = Recorder {
+            out: Vec::new(),
+            calls: 0,
+            limit: 1,
+        };
+        assert_eq!(CtlvRef { type_: 300, value: &[] }.encode_write_vectored(&mut w).unwrap(), 4);
+        assert_eq!(w.out, [249, 1, 44, 0]);
+    }
+}
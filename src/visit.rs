@@ -0,0 +1,233 @@
+use super::nested::{DecoderOptions, NestedError, DEFAULT_MAX_DEPTH};
+use super::CtlvRef;
+
+/// How a [`walk`](fn.walk.html) continues after visiting a ctlv.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum VisitFlow {
+    /// Visit the children of the ctlv next, if it is a container. For leaves, this is the same
+    /// as `Skip`.
+    Descend,
+    /// Continue with the next sibling of the ctlv, without visiting its children.
+    Skip,
+    /// End the walk.
+    Stop,
+}
+
+/// Callbacks for a depth-first [`walk`](fn.walk.html) over nested ctlvs.
+pub trait Visit<'a> {
+    /// Called for every ctlv in document order, with its depth (zero for top-level ctlvs) and
+    /// its offset relative to the start of the input.
+    fn frame(&mut self, depth: usize, offset: usize, frame: CtlvRef<'a>) -> VisitFlow;
+
+    /// Called when the sequence at the given depth turns out to be invalid, or when a container
+    /// is nested too deeply. The error carries the offset at which the problem was found.
+    ///
+    /// Returning `Stop` ends the walk with the error. Otherwise, the walk skips the remainder
+    /// of the invalid sequence and continues with the next sibling of the enclosing container.
+    /// The default implementation returns `Stop`.
+    fn error(&mut self, depth: usize, error: NestedError) -> VisitFlow {
+        let _ = (depth, error);
+        VisitFlow::Stop
+    }
+}
+
+/// Visit nested ctlvs in depth-first order.
+///
+/// The values of ctlvs for which `is_container` returns true are traversed as sequences,
+/// unless the visitor returns `Skip` for them. The traversal does not recurse and does not
+/// allocate, its stack has a fixed size. Containers whose children would be deeper than the
+/// depth limit of the default `DecoderOptions` are reported as `NestedError::DepthExceeded`.
+///
+/// Returns an error if the error callback of the visitor returned `Stop`.
+pub fn walk<'a, F, V>(input: &'a [u8], is_container: F, visitor: &mut V) -> Result<(), NestedError>
+    where F: Fn(u64) -> bool,
+          V: Visit<'a> + ?Sized
+{
+    walk_with(input, is_container, visitor, &DecoderOptions::default())
+}
+
+/// Visit nested ctlvs in depth-first order like [`walk`](fn.walk.html), but with the depth
+/// limit of the given options.
+///
+/// Limits up to the default one use a stack of fixed size, larger ones allocate a stack of at
+/// most one entry per byte of input.
+pub fn walk_with<'a, F, V>(input: &'a [u8],
+                           is_container: F,
+                           visitor: &mut V,
+                           options: &DecoderOptions)
+                           -> Result<(), NestedError>
+    where F: Fn(u64) -> bool,
+          V: Visit<'a> + ?Sized
+{
+    // For each level, the part of its sequence that has not been visited yet, and the offset
+    // of that part relative to the input.
+    let mut fixed: [(&'a [u8], usize); DEFAULT_MAX_DEPTH + 1] = [(&[], 0); DEFAULT_MAX_DEPTH + 1];
+    let mut grown;
+    let stack: &mut [(&'a [u8], usize)] = if options.max_depth <= DEFAULT_MAX_DEPTH {
+        &mut fixed
+    } else {
+        // Every level of nesting takes up at least one byte, so this is deep enough.
+        grown = vec![(&[][..], 0); options.max_depth.min(input.len()) + 1];
+        &mut grown
+    };
+    stack[0] = (input, 0);
+    let mut len = 1;
+
+    while len > 0 {
+        let depth = len - 1;
+        let (rest, offset) = stack[depth];
+        if rest.is_empty() {
+            len -= 1;
+            continue;
+        }
+
+        let (frame, tail) = match CtlvRef::decode(rest) {
+            Ok(decoded) => decoded,
+            Err((e, _)) => {
+                match visitor.error(depth, NestedError::Decode(e, offset)) {
+                    VisitFlow::Stop => return Err(NestedError::Decode(e, offset)),
+                    VisitFlow::Descend | VisitFlow::Skip => {
+                        len -= 1;
+                        continue;
+                    }
+                }
+            }
+        };
+        let next_offset = offset + (rest.len() - tail.len());
+        stack[depth] = (tail, next_offset);
+
+        match visitor.frame(depth, offset, frame) {
+            VisitFlow::Stop => return Ok(()),
+            VisitFlow::Skip => {}
+            VisitFlow::Descend if is_container(frame.type_) => {
+                if depth >= options.max_depth {
+                    let e = NestedError::DepthExceeded(offset);
+                    if visitor.error(depth, e) == VisitFlow::Stop {
+                        return Err(e);
+                    }
+                } else {
+                    stack[len] = (frame.value, next_offset - frame.value.len());
+                    len += 1;
+                }
+            }
+            VisitFlow::Descend => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::DecodeError;
+    use super::super::test_util::ctlv;
+
+    fn is_container(type_: u64) -> bool {
+        type_ == 300 || type_ == 200
+    }
+
+    // Records all visited ctlvs, skipping or stopping at the given types.
+    struct Recorder {
+        visited: Vec<(usize, usize, u64)>,
+        errors: Vec<(usize, NestedError)>,
+        skip: u64,
+        stop: u64,
+        on_error: VisitFlow,
+    }
+
+    impl Recorder {
+        fn new(skip: u64, stop: u64) -> Recorder {
+            Recorder {
+                visited: Vec::new(),
+                errors: Vec::new(),
+                skip,
+                stop,
+                on_error: VisitFlow::Skip,
+            }
+        }
+    }
+
+    impl<'a> Visit<'a> for Recorder {
+        fn frame(&mut self, depth: usize, offset: usize, frame: CtlvRef<'a>) -> VisitFlow {
+            self.visited.push((depth, offset, frame.type_));
+            if frame.type_ == self.skip {
+                VisitFlow::Skip
+            } else if frame.type_ == self.stop {
+                VisitFlow::Stop
+            } else {
+                VisitFlow::Descend
+            }
+        }
+
+        fn error(&mut self, depth: usize, error: NestedError) -> VisitFlow {
+            self.errors.push((depth, error));
+            self.on_error
+        }
+    }
+
+    fn fixture() -> Vec<u8> {
+        let inner = [ctlv(0, vec![1]), ctlv(128, vec![2, 3])].concat();
+        let middle = [ctlv(200, inner), ctlv(1, vec![4])].concat();
+        [ctlv(300, middle), ctlv(0, vec![5])].concat()
+    }
+
+    #[test]
+    fn full_walk() {
+        let mut recorder = Recorder::new(u64::MAX, u64::MAX);
+        walk(&fixture(), is_container, &mut recorder).unwrap();
+        assert_eq!(recorder.visited,
+                   vec![(0, 0, 300), (1, 4, 200), (2, 6, 0), (2, 8, 128), (1, 12, 1), (0, 14, 0)]);
+        assert!(recorder.errors.is_empty());
+    }
+
+    #[test]
+    fn skip_prunes_subtree() {
+        let mut recorder = Recorder::new(200, u64::MAX);
+        walk(&fixture(), is_container, &mut recorder).unwrap();
+        assert_eq!(recorder.visited, vec![(0, 0, 300), (1, 4, 200), (1, 12, 1), (0, 14, 0)]);
+    }
+
+    #[test]
+    fn stop_halts_walk() {
+        let mut recorder = Recorder::new(u64::MAX, 128);
+        walk(&fixture(), is_container, &mut recorder).unwrap();
+        assert_eq!(recorder.visited, vec![(0, 0, 300), (1, 4, 200), (2, 6, 0), (2, 8, 128)]);
+    }
+
+    #[test]
+    fn errors() {
+        let input = [ctlv(300, [ctlv(1, vec![4]), vec![128, 5]].concat()), ctlv(0, vec![5])]
+            .concat();
+        let error = NestedError::Decode(DecodeError::UnexpectedEndOfInput, 6);
+
+        let mut recorder = Recorder::new(u64::MAX, u64::MAX);
+        walk(&input, is_container, &mut recorder).unwrap();
+        assert_eq!(recorder.visited, vec![(0, 0, 300), (1, 4, 1), (0, 8, 0)]);
+        assert_eq!(recorder.errors, vec![(1, error)]);
+
+        recorder = Recorder::new(u64::MAX, u64::MAX);
+        recorder.on_error = VisitFlow::Stop;
+        assert_eq!(walk(&input, is_container, &mut recorder), Err(error));
+        assert_eq!(recorder.visited, vec![(0, 0, 300), (1, 4, 1)]);
+
+        let deep = (0..40).fold(vec![], |value, _| ctlv(300, value));
+        recorder = Recorder::new(u64::MAX, u64::MAX);
+        walk(&deep, is_container, &mut recorder).unwrap();
+        assert_eq!(recorder.visited.len(), DEFAULT_MAX_DEPTH + 1);
+        assert_eq!(recorder.errors.len(), 1);
+        assert_eq!(recorder.errors[0].0, DEFAULT_MAX_DEPTH);
+    }
+
+    #[test]
+    fn depth_limits() {
+        let deep = (0..40).fold(vec![], |value, _| ctlv(300, value));
+        for max_depth in [0, 5, 39, 40, 1000] {
+            let mut recorder = Recorder::new(u64::MAX, u64::MAX);
+            let options = DecoderOptions { max_depth };
+            walk_with(&deep, is_container, &mut recorder, &options).unwrap();
+            assert_eq!(recorder.visited.len(), max_depth.min(39) + 1);
+            assert_eq!(recorder.errors.len(), if max_depth <= 39 { 1 } else { 0 });
+        }
+    }
+}
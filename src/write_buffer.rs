@@ -0,0 +1,102 @@
+use std::io;
+use std::task::Poll;
+
+use super::{CtlvRef, MAX_HEADER_LENGTH};
+
+// Encoded ctlvs that an asynchronous sink has not written out yet, shared by the tokio
+// `CtlvSink` and the futures-io `FrameSink`, which only differ in the writer trait.
+#[derive(Debug, Default)]
+pub(crate) struct WriteBuffer {
+    // Only the bytes that have not been written out yet; written bytes are dropped after each
+    // call to `poll_write_out`, so that the buffer does not grow under backpressure.
+    buf: Vec<u8>,
+}
+
+impl WriteBuffer {
+    pub(crate) fn new() -> WriteBuffer {
+        WriteBuffer::default()
+    }
+
+    // Returns how many encoded bytes have not been written out yet.
+    pub(crate) fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    // Returns the capacity of the buffer, for checking that it stays bounded.
+    #[cfg(test)]
+    pub(crate) fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    pub(crate) fn push(&mut self, frame: CtlvRef) {
+        let mut header = [0; MAX_HEADER_LENGTH];
+        let header_len = frame.encode_header(&mut header);
+        self.buf.extend_from_slice(&header[..header_len]);
+        self.buf.extend_from_slice(frame.value);
+    }
+
+    // Writes out buffered bytes with `poll_write` until fewer than `limit` remain, retrying
+    // interrupted writes.
+    pub(crate) fn poll_write_out<F>(&mut self, limit: usize, mut poll_write: F)
+                                    -> Poll<io::Result<()>>
+        where F: FnMut(&[u8]) -> Poll<io::Result<usize>>
+    {
+        let mut written = 0;
+        let result = loop {
+            let pending = &self.buf[written..];
+            if pending.is_empty() || pending.len() < limit {
+                break Poll::Ready(Ok(()));
+            }
+            match poll_write(pending) {
+                Poll::Ready(Ok(0)) => {
+                    break Poll::Ready(Err(io::Error::new(io::ErrorKind::WriteZero,
+                                                         "failed to write buffered ctlvs")))
+                }
+                Poll::Ready(Ok(n)) => written += n,
+                Poll::Ready(Err(ref e)) if e.kind() == io::ErrorKind::Interrupted => {}
+                Poll::Ready(Err(e)) => break Poll::Ready(Err(e)),
+                Poll::Pending => break Poll::Pending,
+            }
+        };
+        self.buf.drain(..written);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_out() {
+        let mut buf = WriteBuffer::new();
+        buf.push(CtlvRef { type_: 300, value: &[1, 2, 3] });
+        buf.push(CtlvRef { type_: 0, value: &[4] });
+        assert_eq!(buf.buffered(), 9);
+
+        // Two bytes per write, interrupted every other time, until fewer than 4 remain.
+        let mut out = Vec::new();
+        let mut interrupt = true;
+        let result = buf.poll_write_out(4, |pending| {
+            interrupt = !interrupt;
+            if interrupt {
+                return Poll::Ready(Err(io::Error::from(io::ErrorKind::Interrupted)));
+            }
+            let len = pending.len().min(2);
+            out.extend_from_slice(&pending[..len]);
+            Poll::Ready(Ok(len))
+        });
+        assert!(matches!(result, Poll::Ready(Ok(()))));
+        assert_eq!(buf.buffered(), 3);
+        assert_eq!(out, [249, 1, 44, 3, 1, 2]);
+
+        assert!(matches!(buf.poll_write_out(1, |_| Poll::Pending), Poll::Pending));
+        match buf.poll_write_out(1, |_| Poll::Ready(Ok(0))) {
+            Poll::Ready(Err(e)) => assert_eq!(e.kind(), io::ErrorKind::WriteZero),
+            _ => panic!("expected an error"),
+        }
+        assert!(matches!(buf.poll_write_out(1, |pending| Poll::Ready(Ok(pending.len()))),
+                         Poll::Ready(Ok(()))));
+        assert_eq!(buf.buffered(), 0);
+    }
+}
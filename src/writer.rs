@@ -0,0 +1,581 @@
+use std::io::{self, Write};
+
+use super::{check_implied_length, encode_header, CtlvGeneric, CtlvRef, MAX_HEADER_LENGTH};
+
+/// Writes ctlvs to an `io::Write`, keeping track of how many ctlvs and bytes have been written.
+///
+/// If writing fails, [`frames_written`](#method.frames_written) only counts the ctlvs that
+/// have been written completely, whereas [`bytes_written`](#method.bytes_written) counts all
+/// bytes the underlying writer has accepted, including those of an incomplete ctlv.
+/// [`is_partial`](#method.is_partial) tells whether such an incomplete ctlv has been written.
+#[derive(Debug)]
+pub struct CtlvWriter<W> {
+    inner: W,
+    frames: usize,
+    bytes: usize,
+    // The value of `bytes` after the last complete ctlv.
+    complete_bytes: usize,
+    passthrough: bool,
+}
+
+// Counts the bytes accepted by the wrapped writer.
+struct Tally<'a, W> {
+    inner: &'a mut W,
+    count: &'a mut usize,
+}
+
+impl<'a, W: Write> Write for Tally<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        *self.count += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> CtlvWriter<W> {
+    /// Creates a `CtlvWriter` that validates the data passed to `write_raw`.
+    pub fn new(inner: W) -> CtlvWriter<W> {
+        CtlvWriter {
+            inner,
+            frames: 0,
+            bytes: 0,
+            complete_bytes: 0,
+            passthrough: false,
+        }
+    }
+
+    /// Sets whether [`write_raw`](#method.write_raw) writes its input without checking it.
+    pub fn set_passthrough(&mut self, passthrough: bool) {
+        self.passthrough = passthrough;
+    }
+
+    /// Writes a ctlv, returning the length of its encoding.
+    pub fn write_frame(&mut self, frame: CtlvRef) -> io::Result<usize> {
+        self.write_counted(|w| frame.encode_write(w))
+    }
+
+    /// Writes the encoding of a single ctlv, returning its length.
+    ///
+    /// Unless passthrough has been enabled with [`set_passthrough`](#method.set_passthrough),
+    /// this fails with an error of kind `io::ErrorKind::InvalidData` (wrapping the
+    /// `DecodeError`) if the input is anything but exactly one valid ctlv, and nothing is
+    /// written. With passthrough, the input is written as it is, and counted as one ctlv.
+    pub fn write_raw(&mut self, encoded: &[u8]) -> io::Result<usize> {
+        if !self.passthrough {
+            match CtlvRef::decode(encoded) {
+                Ok((_, [])) => {}
+                Ok(_) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                              "trailing bytes after the ctlv"))
+                }
+                Err((e, _)) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            }
+        }
+        self.write_counted(|w| w.write_all(encoded).map(|()| encoded.len()))
+    }
+
+    fn write_counted<F>(&mut self, write: F) -> io::Result<usize>
+        where F: FnOnce(&mut Tally<W>) -> io::Result<usize>
+    {
+        let written = write(&mut Tally {
+                                inner: &mut self.inner,
+                                count: &mut self.bytes,
+                            })?;
+        self.frames += 1;
+        self.complete_bytes = self.bytes;
+        Ok(written)
+    }
+
+    /// Returns how many ctlvs have been written completely.
+    pub fn frames_written(&self) -> usize {
+        self.frames
+    }
+
+    /// Returns how many bytes the underlying writer has accepted.
+    pub fn bytes_written(&self) -> usize {
+        self.bytes
+    }
+
+    /// Returns whether the last write failed after the underlying writer accepted part of the
+    /// ctlv, so that the output ends in an incomplete ctlv.
+    pub fn is_partial(&self) -> bool {
+        self.bytes != self.complete_bytes
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Writes the header of a ctlv whose value is to be written afterwards, returning a
+/// [`ValueWriter`](struct.ValueWriter.html) for writing the value.
+///
+/// This allows streaming a value of known length without buffering it. Fails with an error of
+/// kind `io::ErrorKind::InvalidInput` (wrapping an `ImpliedLengthError`) if the type implies a
+/// different length, in which case nothing is written.
+pub fn start_frame<W: Write>(mut w: W, type_: u64, value_len: u64) -> io::Result<ValueWriter<W>> {
+    check_implied_length(type_, value_len as usize)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let mut header = [0; MAX_HEADER_LENGTH];
+    let header_len = encode_header(type_, value_len, &mut header);
+    w.write_all(&header[..header_len])?;
+    Ok(ValueWriter {
+           inner: w,
+           remaining: value_len,
+       })
+}
+
+/// Writes the value of a ctlv whose header has been written by
+/// [`start_frame`](fn.start_frame.html), making sure that the value has the declared length.
+#[derive(Debug)]
+pub struct ValueWriter<W> {
+    inner: W,
+    remaining: u64,
+}
+
+impl<W: Write> ValueWriter<W> {
+    /// Returns how many bytes of the value have not been written yet.
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    /// Checks that the value has been written completely, and returns the underlying writer.
+    ///
+    /// Fails with an error of kind `io::ErrorKind::InvalidData` if fewer bytes than declared
+    /// have been written.
+    pub fn finish(self) -> io::Result<W> {
+        if self.remaining > 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("value is missing {} bytes", self.remaining)));
+        }
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ValueWriter<W> {
+    /// Writes part of the value. Fails with an error of kind `io::ErrorKind::InvalidData` if
+    /// this would write more bytes than declared, in which case nothing is written.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() as u64 > self.remaining {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "value longer than declared"));
+        }
+        let written = self.inner.write(buf)?;
+        self.remaining -= written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Buffers the value of a ctlv whose length is not known in advance, so that the complete ctlv
+/// can be written once the value is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeferredFrameWriter {
+    type_: u64,
+    value: Vec<u8>,
+    max_len: Option<usize>,
+}
+
+impl DeferredFrameWriter {
+    /// Creates a `DeferredFrameWriter` for a ctlv of the given type, with no limit on the
+    /// length of the value.
+    pub fn new(type_: u64) -> DeferredFrameWriter {
+        DeferredFrameWriter {
+            type_,
+            value: Vec::new(),
+            max_len: None,
+        }
+    }
+
+    /// Creates a `DeferredFrameWriter` for a ctlv of the given type, which refuses to buffer
+    /// more than `max_len` bytes of value.
+    pub fn with_max_len(type_: u64, max_len: usize) -> DeferredFrameWriter {
+        DeferredFrameWriter {
+            max_len: Some(max_len),
+            ..DeferredFrameWriter::new(type_)
+        }
+    }
+
+    /// Returns how many bytes of value have been buffered.
+    pub fn len(&self) -> usize {
+        self.value.len()
+    }
+
+    /// Returns whether no bytes of value have been buffered.
+    pub fn is_empty(&self) -> bool {
+        self.value.is_empty()
+    }
+
+    /// Writes the ctlv with the buffered value to `w`, returning the length of its encoding.
+    ///
+    /// Fails with an error of kind `io::ErrorKind::InvalidInput` (wrapping an
+    /// `ImpliedLengthError`) if the type implies a different length, in which case nothing is
+    /// written.
+    pub fn finish<W: Write>(self, w: W) -> io::Result<usize> {
+        check_implied_length(self.type_, self.value.len())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        CtlvRef {
+                type_: self.type_,
+                value: &self.value,
+            }
+            .encode_write(w)
+    }
+}
+
+impl Write for DeferredFrameWriter {
+    /// Buffers part of the value. Fails with an error of kind `io::ErrorKind::InvalidData` if
+    /// this would exceed the maximum length, in which case nothing is buffered.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(max) = self.max_len {
+            if self.value.len() + buf.len() > max {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "value longer than the maximum"));
+            }
+        }
+        self.value.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Buffers the encodings of ctlvs and writes them to an `io::Write` in batches.
+///
+/// The buffer is written out by [`flush`](#method.flush), and automatically before buffering a
+/// ctlv that would make it hold at least a threshold of bytes (8 KiB by default). Dropping the
+/// `CtlvStreamWriter` flushes as well, but ignores errors; call `flush` to handle them.
+#[derive(Debug)]
+pub struct CtlvStreamWriter<W: Write> {
+    // Only `None` once `into_inner` has taken it.
+    inner: Option<W>,
+    buf: Vec<u8>,
+    threshold: usize,
+}
+
+impl<W: Write> CtlvStreamWriter<W> {
+    /// Creates a `CtlvStreamWriter` with a threshold of 8 KiB.
+    pub fn new(inner: W) -> CtlvStreamWriter<W> {
+        CtlvStreamWriter::with_threshold(inner, 8 * 1024)
+    }
+
+    /// Creates a `CtlvStreamWriter` that writes out its buffer before it would hold at least
+    /// `threshold` bytes.
+    pub fn with_threshold(inner: W, threshold: usize) -> CtlvStreamWriter<W> {
+        CtlvStreamWriter {
+            inner: Some(inner),
+            buf: Vec::new(),
+            threshold,
+        }
+    }
+
+    /// Encodes a ctlv into the buffer, first writing out the buffer if the ctlv would make it
+    /// reach the threshold.
+    ///
+    /// Like `io::BufWriter`, this writes out the previously buffered bytes before buffering the
+    /// ctlv. If writing them out fails, the error is returned and the ctlv is not buffered, so
+    /// retrying the `write` does not queue the ctlv twice. A ctlv that alone reaches the threshold
+    /// stays buffered until the next `write` or `flush`.
+    pub fn write<B: AsRef<[u8]>>(&mut self, ctlv: &CtlvGeneric<B>) -> io::Result<()> {
+        if !self.buf.is_empty() && self.buf.len() + ctlv.encoding_length() >= self.threshold {
+            self.write_buf()?;
+        }
+        ctlv.encode_write(&mut self.buf)?;
+        Ok(())
+    }
+
+    /// Writes out the buffer and flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.write_buf()?;
+        self.inner.as_mut().unwrap().flush()
+    }
+
+    /// Returns how many bytes have been buffered but not written out yet.
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Flushes the buffer and returns the underlying writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    // Writes out the buffer. If this fails, the buffer keeps the bytes that were not written.
+    fn write_buf(&mut self) -> io::Result<()> {
+        let inner = self.inner.as_mut().unwrap();
+        let mut written = 0;
+        let result = loop {
+            if written == self.buf.len() {
+                break Ok(());
+            }
+            match inner.write(&self.buf[written..]) {
+                Ok(0) => {
+                    break Err(io::Error::new(io::ErrorKind::WriteZero,
+                                             "failed to write buffered ctlvs"));
+                }
+                Ok(n) => written += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.buf.drain(..written);
+        result
+    }
+}
+
+impl<W: Write> Drop for CtlvStreamWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Ctlv, ImpliedLengthError};
+
+    // A writer that accepts at most `capacity` bytes, then fails.
+    struct FailAfter {
+        out: Vec<u8>,
+        capacity: usize,
+    }
+
+    impl Write for FailAfter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.capacity - self.out.len());
+            if len == 0 {
+                return Err(io::Error::other("full"));
+            }
+            self.out.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn counters() {
+        let mut w = CtlvWriter::new(Vec::new());
+        assert_eq!(w.write_frame(CtlvRef { type_: 0, value: &[1] }).unwrap(), 2);
+        assert_eq!(w.write_raw(&[128, 2, 3, 4]).unwrap(), 4);
+        w.flush().unwrap();
+        assert_eq!(w.frames_written(), 2);
+        assert_eq!(w.bytes_written(), 6);
+        assert!(!w.is_partial());
+        assert_eq!(w.into_inner(), [0, 1, 128, 2, 3, 4]);
+    }
+
+    #[test]
+    fn raw_validation() {
+        let mut w = CtlvWriter::new(Vec::new());
+        for invalid in &[&[128, 2, 3][..], &[0, 1, 0, 1], &[], &[248, 5, 1]] {
+            let e = w.write_raw(invalid).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        }
+        assert_eq!(w.frames_written(), 0);
+        assert!(w.get_ref().is_empty());
+
+        w.set_passthrough(true);
+        assert_eq!(w.write_raw(&[0, 1, 0, 1]).unwrap(), 4);
+        assert_eq!(w.frames_written(), 1);
+        assert_eq!(w.into_inner(), [0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn partial_write() {
+        let frame = Ctlv::new(300, vec![7; 10]);
+        let mut w = CtlvWriter::new(FailAfter {
+                                        out: Vec::new(),
+                                        capacity: 20,
+                                    });
+        assert_eq!(w.write_frame(frame.as_ctlv_ref()).unwrap(), 14);
+        assert!(w.write_frame(frame.as_ctlv_ref()).is_err());
+
+        assert_eq!(w.frames_written(), 1);
+        assert_eq!(w.bytes_written(), 20);
+        assert!(w.is_partial());
+        assert_eq!(w.get_ref().out.len(), 20);
+
+        assert!(w.write_raw(&[0, 1]).is_err());
+        assert_eq!(w.frames_written(), 1);
+        assert_eq!(w.bytes_written(), 20);
+    }
+
+    #[test]
+    fn value_writer() {
+        let mut w = start_frame(Vec::new(), 300, 1000).unwrap();
+        for _ in 0..10 {
+            w.write_all(&[7; 100]).unwrap();
+        }
+        assert_eq!(w.remaining(), 0);
+        let out = w.finish().unwrap();
+        assert_eq!(Ctlv::decode(&out).unwrap(),
+                   (Ctlv::new(300, vec![7; 1000]), &[][..]));
+
+        let mut w = start_frame(Vec::new(), 16, 4).unwrap();
+        w.write_all(&[1, 2]).unwrap();
+        assert_eq!(w.remaining(), 2);
+        assert_eq!(w.write_all(&[3, 4, 5]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        w.write_all(&[3, 4]).unwrap();
+        assert_eq!(w.finish().unwrap(), [16, 1, 2, 3, 4]);
+
+        let mut w = start_frame(Vec::new(), 128, 3).unwrap();
+        w.write_all(&[1, 2]).unwrap();
+        assert_eq!(w.finish().unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        assert_eq!(start_frame(Vec::new(), 129, 0).unwrap().finish().unwrap(), [129, 0]);
+        let e = start_frame(Vec::new(), 8, 3).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(e.into_inner().unwrap().downcast::<ImpliedLengthError>().unwrap().implied, 2);
+    }
+
+    #[test]
+    fn deferred() {
+        let mut out = Vec::new();
+        assert_eq!(DeferredFrameWriter::new(300).finish(&mut out).unwrap(), 4);
+        assert_eq!(out, [249, 1, 44, 0]);
+
+        let mut w = DeferredFrameWriter::new(128);
+        for i in 0..100u8 {
+            w.write_all(&[i; 3]).unwrap();
+        }
+        assert_eq!(w.len(), 300);
+        let mut out = Vec::new();
+        assert_eq!(w.finish(&mut out).unwrap(), 304);
+        let (ctlv, _) = Ctlv::decode(&out).unwrap();
+        assert_eq!(ctlv.value(), (0..100u8).flat_map(|i| vec![i; 3]).collect::<Vec<_>>());
+
+        let mut w = DeferredFrameWriter::new(8);
+        w.write_all(&[1, 2, 3]).unwrap();
+        let mut out = Vec::new();
+        let e = w.finish(&mut out).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(out.is_empty());
+        let mut w = DeferredFrameWriter::new(8);
+        w.write_all(&[1, 2]).unwrap();
+        assert_eq!(w.finish(&mut out).unwrap(), 3);
+
+        let mut w = DeferredFrameWriter::with_max_len(128, 4);
+        w.write_all(&[1, 2, 3]).unwrap();
+        assert_eq!(w.write(&[4, 5]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        w.write_all(&[4]).unwrap();
+        assert!(!w.is_empty());
+        let mut out = Vec::new();
+        w.finish(&mut out).unwrap();
+        assert_eq!(out, [128, 4, 1, 2, 3, 4]);
+    }
+
+    // Records the length of every write.
+    #[derive(Default)]
+    struct Writes(Vec<usize>, Vec<u8>);
+
+    impl Write for &mut Writes {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.push(buf.len());
+            self.1.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stream_writer() {
+        let frame = Ctlv::new(300, vec![7; 10]);
+        let mut writes = Writes::default();
+        let mut w = CtlvStreamWriter::with_threshold(&mut writes, 30);
+        w.write(&frame).unwrap();
+        w.write(&frame).unwrap();
+        assert_eq!(w.buffered(), 28);
+        w.write(&frame).unwrap();
+        assert_eq!(w.buffered(), 14);
+        w.write(&frame).unwrap();
+        w.flush().unwrap();
+        w.write(&Ctlv::new(0, vec![1])).unwrap();
+        drop(w);
+        assert_eq!(writes.0, [28, 28, 2]);
+        assert_eq!(writes.1, [frame.encode_vec().repeat(4), vec![0, 1]].concat());
+
+        let mut w = CtlvStreamWriter::new(FailAfter {
+                                              out: Vec::new(),
+                                              capacity: 20,
+                                          });
+        w.write(&frame).unwrap();
+        w.write(&frame).unwrap();
+        assert!(w.flush().is_err());
+        assert_eq!(w.buffered(), 8);
+        assert_eq!(w.get_ref().out, [frame.encode_vec(), vec![249, 1, 44, 10, 7, 7]].concat());
+
+        let w = CtlvStreamWriter::new(Vec::new());
+        assert!(w.into_inner().unwrap().is_empty());
+    }
+
+    // A writer that fails its first write, and accepts everything afterwards.
+    #[derive(Default)]
+    struct FailOnce {
+        out: Vec<u8>,
+        failed: bool,
+    }
+
+    impl Write for FailOnce {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if !self.failed {
+                self.failed = true;
+                return Err(io::Error::other("try again"));
+            }
+            self.out.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stream_writer_retry() {
+        let frame = Ctlv::new(300, vec![7; 10]);
+        let mut w = CtlvStreamWriter::with_threshold(FailOnce::default(), 30);
+        w.write(&frame).unwrap();
+        w.write(&frame).unwrap();
+        // Writing out the buffer fails, so the third ctlv is not buffered.
+        assert!(w.write(&frame).is_err());
+        assert_eq!(w.buffered(), 28);
+        assert!(w.get_ref().out.is_empty());
+        w.write(&frame).unwrap();
+        assert_eq!(w.buffered(), 14);
+        assert_eq!(w.into_inner().unwrap().out, frame.encode_vec().repeat(3));
+    }
+}